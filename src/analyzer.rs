@@ -0,0 +1,423 @@
+use crate::instruction::Instruction;
+use std::collections::{BTreeMap, BTreeSet};
+
+// Works out which parts of a rom are code by following the flow from the
+// entry point: jumps, calls and returns, and both ways out of every skip.
+// The code found is split into basic blocks and a call graph, and I is
+// tracked through it so that the bytes drawn as sprites, and the writes
+// that land on code, can be picked out.  Jumps through BNNN can go anywhere,
+// so only the run of jumps at the table they point at is followed.
+
+// What a byte of the rom was found to be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteClass {
+    Unknown, // Never reached, drawn or read
+    Code,
+    Sprite, // Drawn by Dxyn
+    Data,   // Read or written through I, other than by drawing
+}
+
+impl ByteClass {
+    pub fn name(self) -> &'static str {
+        match self {
+            ByteClass::Unknown => "unknown",
+            ByteClass::Code => "code",
+            ByteClass::Sprite => "sprite",
+            ByteClass::Data => "data",
+        }
+    }
+}
+
+// A run of instructions only ever entered at the top and left at the bottom
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    pub end: usize,             // Just past the last instruction
+    pub successors: Vec<usize>, // Where it goes next; a call returns to `end`
+    pub call: Option<usize>,    // The subroutine it calls on the way out
+}
+
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub origin: usize,
+    pub classes: Vec<ByteClass>, // One per byte from the origin
+    pub blocks: BTreeMap<usize, Block>,
+    pub calls: BTreeMap<usize, BTreeSet<usize>>, // Subroutine -> the ones it calls
+    pub computed_jumps: BTreeSet<usize>,         // Reached BNNN instructions
+    pub self_modifying: BTreeMap<usize, usize>,  // Write instruction -> the code it lands on
+    pub invalid: BTreeSet<usize>,                // Reached opcodes that don't decode
+}
+
+// How control leaves an instruction
+enum Flow {
+    Next,
+    Jump(usize),
+    Call(usize),
+    Skip,
+    Table(usize),
+    Stop,
+}
+
+fn flow(instruction: Instruction) -> Flow {
+    match instruction {
+        Instruction::Jp(a) => Flow::Jump(a),
+        Instruction::Call(a) => Flow::Call(a),
+        Instruction::JpV0(a) => Flow::Table(a),
+        Instruction::Ret | Instruction::Exit => Flow::Stop,
+        Instruction::SeByte(..)
+        | Instruction::SneByte(..)
+        | Instruction::SeReg(..)
+        | Instruction::SneReg(..)
+        | Instruction::Skp(_)
+        | Instruction::Sknp(_) => Flow::Skip,
+        _ => Flow::Next,
+    }
+}
+
+// What is known about I: nothing yet, a fixed address, or that it varies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IValue {
+    Known(usize),
+    Unknown,
+}
+
+impl IValue {
+    fn join(self, other: IValue) -> IValue {
+        if self == other {
+            self
+        } else {
+            IValue::Unknown
+        }
+    }
+}
+
+pub fn analyze(bytes: &[u8], origin: usize) -> Analysis {
+    let mut analysis = Analysis {
+        origin,
+        classes: vec![ByteClass::Unknown; bytes.len()],
+        ..Analysis::default()
+    };
+    let decode = |address: usize| -> Option<(Instruction, [u8; 4])> {
+        let at = |address: usize| {
+            address
+                .checked_sub(origin)
+                .and_then(|offset| bytes.get(offset))
+                .copied()
+        };
+        let opcode = u16::from_be_bytes([at(address)?, at(address + 1)?]);
+        let instruction = Instruction::decode(opcode)?;
+        let mut raw = [at(address)?, at(address + 1)?, 0, 0];
+        if instruction.size() == 4 {
+            raw[2] = at(address + 2)?;
+            raw[3] = at(address + 3)?;
+        }
+        Some((instruction, raw))
+    };
+
+    // Find everything reachable, and where each instruction can go
+    let mut code: BTreeMap<usize, (Instruction, [u8; 4])> = BTreeMap::new();
+    let mut edges: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    let mut calls: BTreeMap<usize, usize> = BTreeMap::new();
+    let mut work = vec![origin];
+    while let Some(pc) = work.pop() {
+        if code.contains_key(&pc) || analysis.invalid.contains(&pc) {
+            continue;
+        }
+        let (instruction, raw) = match decode(pc) {
+            Some(decoded) => decoded,
+            None => {
+                analysis.invalid.insert(pc);
+                continue;
+            }
+        };
+        code.insert(pc, (instruction, raw));
+        let next = pc + instruction.size();
+        let successors = match flow(instruction) {
+            Flow::Next => vec![next],
+            Flow::Jump(a) => vec![a],
+            Flow::Call(a) => {
+                calls.insert(pc, a);
+                work.push(a);
+                vec![next]
+            }
+            // F000 is skipped over whole
+            Flow::Skip => match decode(next) {
+                Some((skipped, _)) => vec![next, next + skipped.size()],
+                None => vec![next, next + 2],
+            },
+            // Follow the run of jumps that usually makes up the table
+            Flow::Table(a) => {
+                analysis.computed_jumps.insert(pc);
+                let is_jump = |address| matches!(decode(address), Some((Instruction::Jp(_), _)));
+                let mut entries = vec![a];
+                let mut last = a;
+                while is_jump(last) && is_jump(last + 2) {
+                    last += 2;
+                    entries.push(last);
+                }
+                entries
+            }
+            Flow::Stop => vec![],
+        };
+        work.extend(successors.iter().copied());
+        edges.insert(pc, successors);
+    }
+
+    // Blocks start at the entry, subroutines, and anywhere flow lands other
+    // than by falling through
+    let ends_block = |pc: &usize| !matches!(flow(code[pc].0), Flow::Next);
+    let mut leaders: BTreeSet<usize> = calls.values().copied().collect();
+    leaders.insert(origin);
+    for (pc, successors) in &edges {
+        let fallthrough = pc + code[pc].0.size();
+        for successor in successors {
+            if *successor != fallthrough || ends_block(pc) {
+                leaders.insert(*successor);
+            }
+        }
+    }
+    leaders.retain(|leader| code.contains_key(leader));
+    for leader in &leaders {
+        let mut pc = *leader;
+        loop {
+            let next = pc + code[&pc].0.size();
+            if ends_block(&pc) || !code.contains_key(&next) || leaders.contains(&next) {
+                analysis.blocks.insert(
+                    *leader,
+                    Block {
+                        start: *leader,
+                        end: next,
+                        successors: edges[&pc].clone(),
+                        call: calls.get(&pc).copied(),
+                    },
+                );
+                break;
+            }
+            pc = next;
+        }
+    }
+
+    // Which subroutines each subroutine calls
+    let entries: BTreeSet<usize> = std::iter::once(origin)
+        .chain(calls.values().copied())
+        .collect();
+    for entry in entries {
+        let mut callees = BTreeSet::new();
+        let mut seen = BTreeSet::new();
+        let mut work = vec![entry];
+        while let Some(start) = work.pop() {
+            if !seen.insert(start) {
+                continue;
+            }
+            if let Some(block) = analysis.blocks.get(&start) {
+                callees.extend(block.call);
+                work.extend(block.successors.iter().copied());
+            }
+        }
+        if code.contains_key(&entry) {
+            analysis.calls.insert(entry, callees);
+        }
+    }
+
+    for (pc, (instruction, _)) in &code {
+        for offset in 0..instruction.size() {
+            analysis.set_class(pc + offset, ByteClass::Code);
+        }
+    }
+
+    // Track I from block to block.  A subroutine sees I as it was at the
+    // call, but I can't be trusted once it returns.
+    let mut entry_i: BTreeMap<usize, IValue> = BTreeMap::new();
+    entry_i.insert(origin, IValue::Unknown);
+    let mut work = vec![origin];
+    while let Some(start) = work.pop() {
+        let block = match analysis.blocks.get(&start) {
+            Some(block) => block,
+            None => continue,
+        };
+        let mut i = entry_i[&start];
+        let mut pc = start;
+        while pc < block.end {
+            i = step_i(code[&pc], i);
+            pc += code[&pc].0.size();
+        }
+        let mut targets: Vec<(usize, IValue)> = block.successors.iter().map(|s| (*s, i)).collect();
+        if let Some(callee) = block.call {
+            targets = vec![(callee, i), (block.end, IValue::Unknown)];
+        }
+        for (target, value) in targets {
+            let joined = match entry_i.get(&target) {
+                Some(old) => old.join(value),
+                None => value,
+            };
+            if entry_i.get(&target) != Some(&joined) {
+                entry_i.insert(target, joined);
+                work.push(target);
+            }
+        }
+    }
+
+    // Now go over the code with I known where it can be, marking what is
+    // drawn and read, and the writes that hit code
+    let blocks: Vec<(usize, usize)> = analysis.blocks.values().map(|b| (b.start, b.end)).collect();
+    for (start, end) in blocks {
+        let mut i = entry_i.get(&start).copied().unwrap_or(IValue::Unknown);
+        let mut pc = start;
+        while pc < end {
+            if let IValue::Known(address) = i {
+                analysis.note_access(pc, code[&pc].0, address);
+            }
+            i = step_i(code[&pc], i);
+            pc += code[&pc].0.size();
+        }
+    }
+
+    analysis
+}
+
+// I after an instruction runs
+fn step_i((instruction, raw): (Instruction, [u8; 4]), i: IValue) -> IValue {
+    match instruction {
+        Instruction::LdI(a) => IValue::Known(a),
+        Instruction::LdILong => IValue::Known(usize::from(raw[2]) << 8 | usize::from(raw[3])),
+        // Some interpreters move I on after a save or load
+        Instruction::AddIVx(_)
+        | Instruction::LdFVx(_)
+        | Instruction::LdHfVx(_)
+        | Instruction::LdIVx(_)
+        | Instruction::LdVxI(_) => IValue::Unknown,
+        _ => i,
+    }
+}
+
+impl Analysis {
+    pub fn class_at(&self, address: usize) -> ByteClass {
+        address
+            .checked_sub(self.origin)
+            .and_then(|offset| self.classes.get(offset))
+            .copied()
+            .unwrap_or(ByteClass::Unknown)
+    }
+
+    fn set_class(&mut self, address: usize, class: ByteClass) {
+        if let Some(byte) = address
+            .checked_sub(self.origin)
+            .and_then(|offset| self.classes.get_mut(offset))
+        {
+            if *byte != ByteClass::Code {
+                *byte = class;
+            }
+        }
+    }
+
+    // Notes the bytes an instruction at `pc` touches with I at `i`
+    fn note_access(&mut self, pc: usize, instruction: Instruction, i: usize) {
+        let (len, class, write) = match instruction {
+            Instruction::Drw(_, _, 0) => (32, ByteClass::Sprite, false),
+            Instruction::Drw(_, _, n) => (n, ByteClass::Sprite, false),
+            Instruction::LdVxI(x) => (x + 1, ByteClass::Data, false),
+            Instruction::LoadRange(x, y) => (x.abs_diff(y) + 1, ByteClass::Data, false),
+            Instruction::Audio => (16, ByteClass::Data, false),
+            Instruction::LdIVx(x) => (x + 1, ByteClass::Data, true),
+            Instruction::SaveRange(x, y) => (x.abs_diff(y) + 1, ByteClass::Data, true),
+            Instruction::LdBVx(_) => (3, ByteClass::Data, true),
+            _ => return,
+        };
+        for address in i..i + len {
+            if write && self.class_at(address) == ByteClass::Code {
+                self.self_modifying.entry(pc).or_insert(address);
+            }
+            self.set_class(address, class);
+        }
+    }
+
+    // Notes on the instructions worth a second look, for listings and the
+    // debugger
+    pub fn annotations(&self) -> BTreeMap<usize, String> {
+        let mut notes = BTreeMap::new();
+        for pc in &self.computed_jumps {
+            notes.insert(*pc, "computed jump".to_string());
+        }
+        for (pc, target) in &self.self_modifying {
+            notes.insert(*pc, format!("writes to code at {:#05X}", target));
+        }
+        for pc in &self.invalid {
+            notes.insert(*pc, "reached, but not an instruction".to_string());
+        }
+        notes
+    }
+
+    // Runs of bytes of the same class, as (start, end, class)
+    pub fn map(&self) -> Vec<(usize, usize, ByteClass)> {
+        let mut runs: Vec<(usize, usize, ByteClass)> = Vec::new();
+        for (offset, class) in self.classes.iter().enumerate() {
+            let address = self.origin + offset;
+            match runs.last_mut() {
+                Some(run) if run.2 == *class => run.1 = address + 1,
+                _ => runs.push((address, address + 1, *class)),
+            }
+        }
+        runs
+    }
+
+    pub fn to_json(&self) -> String {
+        fn list(items: impl Iterator<Item = String>) -> String {
+            format!("[{}]", items.collect::<Vec<String>>().join(", "))
+        }
+        // A list of objects, one to a line
+        fn rows(items: impl Iterator<Item = String>) -> String {
+            let items: Vec<String> = items.map(|item| format!("\n    {}", item)).collect();
+            if items.is_empty() {
+                "[]".to_string()
+            } else {
+                format!("[{}\n  ]", items.join(","))
+            }
+        }
+        let numbers = |items: &mut dyn Iterator<Item = &usize>| list(items.map(|n| n.to_string()));
+
+        let mut out = "{\n".to_string();
+        out += &format!("  \"origin\": {},\n", self.origin);
+        out += &format!("  \"size\": {},\n", self.classes.len());
+        let blocks = self.blocks.values().map(|block| {
+            format!(
+                "{{\"start\": {}, \"end\": {}, \"successors\": {}, \"call\": {}}}",
+                block.start,
+                block.end,
+                numbers(&mut block.successors.iter()),
+                block
+                    .call
+                    .map_or("null".to_string(), |call| call.to_string())
+            )
+        });
+        out += &format!("  \"blocks\": {},\n", rows(blocks));
+        let calls = self.calls.iter().map(|(sub, callees)| {
+            format!(
+                "{{\"sub\": {}, \"calls\": {}}}",
+                sub,
+                numbers(&mut callees.iter())
+            )
+        });
+        out += &format!("  \"calls\": {},\n", rows(calls));
+        out += &format!(
+            "  \"computed_jumps\": {},\n",
+            numbers(&mut self.computed_jumps.iter())
+        );
+        let writes = self
+            .self_modifying
+            .iter()
+            .map(|(pc, target)| format!("{{\"at\": {}, \"writes\": {}}}", pc, target));
+        out += &format!("  \"self_modifying\": {},\n", rows(writes));
+        out += &format!("  \"invalid\": {},\n", numbers(&mut self.invalid.iter()));
+        let map = self.map().into_iter().map(|(start, end, class)| {
+            format!(
+                "{{\"start\": {}, \"end\": {}, \"class\": \"{}\"}}",
+                start,
+                end,
+                class.name()
+            )
+        });
+        out += &format!("  \"map\": {}\n", rows(map));
+        out += "}\n";
+        out
+    }
+}
@@ -0,0 +1,673 @@
+use crate::instruction::Instruction;
+use std::collections::BTreeMap;
+use std::fmt;
+
+// An assembler for a subset of Octo (http://johnearnest.github.io/Octo/),
+// enough to write test programs by hand:
+//
+//   # Comments run to the end of the line
+//   :const SPEED 3
+//   : main
+//     v0 := 0            i := sprite
+//     loop
+//       sprite v0 v1 5
+//       v0 += SPEED
+//       if v0 == 60 then v0 := 0
+//     again
+//   : sprite
+//     :byte 0xF0  0x90 0x90 0x90 0xF0
+//
+// Statements are separated by whitespace, so several can share a line.
+// Programs start at 0x200 in source order.  Labels may be used before they
+// are defined, constants may not.  A bare label name calls it, as in Octo.
+
+// The assembled program
+#[derive(Debug, Default)]
+pub struct Assembly {
+    pub rom: Vec<u8>,                     // Loaded at 0x200
+    pub symbols: BTreeMap<String, usize>, // Label -> address
+    pub constants: BTreeMap<String, i64>, // :const name -> value
+    pub lines: BTreeMap<usize, usize>,    // Instruction address -> source line
+}
+
+impl Assembly {
+    // The symbol table, one "address name" per line in address order
+    pub fn symbol_table(&self) -> String {
+        let mut symbols: Vec<(&usize, &String)> = self
+            .symbols
+            .iter()
+            .map(|(name, address)| (address, name))
+            .collect();
+        symbols.sort();
+        symbols
+            .iter()
+            .map(|(address, name)| format!("{:#05X} {}\n", address, name))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+impl std::error::Error for AsmError {}
+
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+    let mut tokens = Vec::new();
+    for (n, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or("");
+        tokens.extend(
+            code.split_whitespace()
+                .map(|text| Token { text, line: n + 1 }),
+        );
+    }
+    let mut asm = Assembler {
+        tokens,
+        pos: 0,
+        out: Assembly::default(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+    };
+    asm.run()?;
+    Ok(asm.out)
+}
+
+const START: usize = 0x200;
+
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+}
+
+enum Value {
+    Number(i64),
+    Label(String),
+}
+
+// A label address to fill in once all the labels are known
+struct Fixup {
+    at: usize, // Offset into the rom
+    name: String,
+    line: usize,
+    long: bool, // A 16 bit F000 NNNN address rather than 12 bits in an opcode
+}
+
+// Open `if ... begin` and `loop` blocks, with the jumps waiting on their end
+enum Block {
+    If {
+        jump: usize,
+        line: usize,
+    },
+    Else {
+        jump: usize,
+        line: usize,
+    },
+    Loop {
+        start: usize,
+        breaks: Vec<usize>,
+        line: usize,
+    },
+}
+
+struct Assembler<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    out: Assembly,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+}
+
+impl<'a> Assembler<'a> {
+    fn run(&mut self) -> Result<(), AsmError> {
+        while self.pos < self.tokens.len() {
+            self.statement()?;
+        }
+        if let Some(block) = self.blocks.last() {
+            let (line, what) = match block {
+                Block::If { line, .. } | Block::Else { line, .. } => {
+                    (*line, "'begin' without 'end'")
+                }
+                Block::Loop { line, .. } => (*line, "'loop' without 'again'"),
+            };
+            return Err(AsmError {
+                line,
+                message: what.to_string(),
+            });
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            let address = match self.out.symbols.get(&fixup.name) {
+                Some(address) => *address,
+                None => {
+                    return Err(error(
+                        fixup.line,
+                        format!("undefined label '{}'", fixup.name),
+                    ))
+                }
+            };
+            if fixup.long {
+                self.out.rom[fixup.at..fixup.at + 2]
+                    .copy_from_slice(&(address as u16).to_be_bytes());
+            } else {
+                if address > 0xFFF {
+                    return Err(error(
+                        fixup.line,
+                        format!(
+                            "label '{}' at {:#X} is out of reach, use 'i := long'",
+                            fixup.name, address
+                        ),
+                    ));
+                }
+                self.out.rom[fixup.at] |= (address >> 8) as u8;
+                self.out.rom[fixup.at + 1] = address as u8;
+            }
+        }
+        Ok(())
+    }
+
+    fn here(&self) -> usize {
+        START + self.out.rom.len()
+    }
+
+    fn next(&mut self) -> Result<&'a str, AsmError> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token.text)
+            }
+            None => Err(error(self.line(), "unexpected end of program".to_string())),
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(|token| token.text)
+    }
+
+    // The line of the last token read
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos.max(1) - 1)
+            .map_or(0, |token| token.line)
+    }
+
+    fn fail<T>(&self, message: String) -> Result<T, AsmError> {
+        Err(error(self.line(), message))
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), AsmError> {
+        let token = self.next()?;
+        if token != word {
+            return self.fail(format!("expected '{}', found '{}'", word, token));
+        }
+        Ok(())
+    }
+
+    fn register(&mut self) -> Result<usize, AsmError> {
+        let token = self.next()?;
+        match register(token) {
+            Some(x) => Ok(x),
+            None => self.fail(format!("expected a register, found '{}'", token)),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, AsmError> {
+        let token = self.next()?;
+        if let Some(number) = number(token) {
+            return Ok(Value::Number(number));
+        }
+        if let Some(value) = self.out.constants.get(token) {
+            return Ok(Value::Number(*value));
+        }
+        if !is_name(token) {
+            return self.fail(format!("expected a value, found '{}'", token));
+        }
+        Ok(Value::Label(token.to_string()))
+    }
+
+    fn number(&mut self, min: i64, max: i64) -> Result<i64, AsmError> {
+        match self.value()? {
+            Value::Number(n) if n >= min && n <= max => Ok(n),
+            Value::Number(n) => self.fail(format!("{} is out of range ({} to {})", n, min, max)),
+            Value::Label(name) => self.fail(format!("expected a number, found '{}'", name)),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, AsmError> {
+        Ok(self.number(-128, 255)? as u8)
+    }
+
+    fn nibble(&mut self) -> Result<usize, AsmError> {
+        Ok(self.number(0, 15)? as usize)
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        let line = self.line();
+        self.out.lines.insert(self.here(), line);
+        self.out
+            .rom
+            .extend_from_slice(&instruction.encode().to_be_bytes());
+    }
+
+    // Emits an instruction taking a 12 bit address, which may be a label
+    fn emit_address(&mut self, instruction: fn(usize) -> Instruction) -> Result<(), AsmError> {
+        match self.value()? {
+            Value::Number(n) if (0..=0xFFF).contains(&n) => self.emit(instruction(n as usize)),
+            Value::Number(n) => return self.fail(format!("address {:#X} is out of range", n)),
+            Value::Label(name) => {
+                self.fixups.push(Fixup {
+                    at: self.out.rom.len(),
+                    name,
+                    line: self.line(),
+                    long: false,
+                });
+                self.emit(instruction(0));
+            }
+        }
+        Ok(())
+    }
+
+    // A jump to be pointed somewhere later, returning where it is
+    fn emit_jump(&mut self) -> usize {
+        let at = self.out.rom.len();
+        self.emit(Instruction::Jp(0));
+        at
+    }
+
+    fn patch_jump(&mut self, at: usize) {
+        let target = Instruction::Jp(self.here()).encode();
+        self.out.rom[at..at + 2].copy_from_slice(&target.to_be_bytes());
+    }
+
+    fn statement(&mut self) -> Result<(), AsmError> {
+        let token = self.next()?;
+        if let Some(x) = register(token) {
+            return self.register_statement(x);
+        }
+        if let Some(n) = number(token) {
+            // Bare numbers are data
+            if !(-128..=255).contains(&n) {
+                return self.fail(format!("{} is out of range for a byte", n));
+            }
+            self.out.rom.push(n as u8);
+            return Ok(());
+        }
+
+        match token {
+            ":" => {
+                let name = self.next()?;
+                if !is_name(name) || self.out.constants.contains_key(name) {
+                    return self.fail(format!("'{}' can't be used as a label", name));
+                }
+                if self
+                    .out
+                    .symbols
+                    .insert(name.to_string(), self.here())
+                    .is_some()
+                {
+                    return self.fail(format!("label '{}' is already defined", name));
+                }
+            }
+            ":const" => {
+                let name = self.next()?;
+                if !is_name(name) || self.out.symbols.contains_key(name) {
+                    return self.fail(format!("'{}' can't be used as a constant", name));
+                }
+                let value = self.number(-0x8000, 0xFFFF)?;
+                self.out.constants.insert(name.to_string(), value);
+            }
+            ":byte" => {
+                let byte = self.byte()?;
+                self.out.rom.push(byte);
+            }
+            ":org" => {
+                let address = self.number(0, 0xFFFF)? as usize;
+                if address < self.here() {
+                    return self.fail(format!("can't :org backwards to {:#X}", address));
+                }
+                self.out.rom.resize(address - START, 0);
+            }
+            "clear" => self.emit(Instruction::Cls),
+            "return" | ";" => self.emit(Instruction::Ret),
+            "exit" => self.emit(Instruction::Exit),
+            "hires" => self.emit(Instruction::High),
+            "lores" => self.emit(Instruction::Low),
+            "scroll-left" => self.emit(Instruction::ScrollLeft),
+            "scroll-right" => self.emit(Instruction::ScrollRight),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(Instruction::ScrollDown(n));
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(Instruction::ScrollUp(n));
+            }
+            "jump" => self.emit_address(Instruction::Jp)?,
+            "jump0" => self.emit_address(Instruction::JpV0)?,
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(Instruction::LdBVx(x));
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    self.emit(match token {
+                        "save" => Instruction::SaveRange(x, y),
+                        _ => Instruction::LoadRange(x, y),
+                    });
+                } else {
+                    self.emit(match token {
+                        "save" => Instruction::LdIVx(x),
+                        _ => Instruction::LdVxI(x),
+                    });
+                }
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(Instruction::LdRVx(x));
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(Instruction::LdVxR(x));
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(Instruction::Drw(x, y, n));
+            }
+            "plane" => {
+                let n = self.number(0, 3)? as usize;
+                self.emit(Instruction::Plane(n));
+            }
+            "audio" => self.emit(Instruction::Audio),
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.emit(match token {
+                    "delay" => Instruction::LdDtVx(x),
+                    "buzzer" => Instruction::LdStVx(x),
+                    _ => Instruction::Pitch(x),
+                });
+            }
+            "i" => self.i_statement()?,
+            "if" => self.if_statement()?,
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => {
+                    let line = self.line();
+                    let end = self.emit_jump();
+                    self.patch_jump(jump);
+                    self.blocks.push(Block::Else { jump: end, line });
+                }
+                _ => return self.fail("'else' without 'if ... begin'".to_string()),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) | Some(Block::Else { jump, .. }) => {
+                    self.patch_jump(jump)
+                }
+                _ => return self.fail("'end' without 'if ... begin'".to_string()),
+            },
+            "loop" => {
+                let (start, line) = (self.here(), self.line());
+                self.blocks.push(Block::Loop {
+                    start,
+                    breaks: Vec::new(),
+                    line,
+                });
+            }
+            "while" => {
+                let (_, skip_if_true) = self.condition()?;
+                self.emit(skip_if_true);
+                let jump = self.emit_jump();
+                match self.blocks.iter_mut().rev().find_map(|block| match block {
+                    Block::Loop { breaks, .. } => Some(breaks),
+                    _ => None,
+                }) {
+                    Some(breaks) => breaks.push(jump),
+                    None => return self.fail("'while' outside of a loop".to_string()),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, breaks, .. }) => {
+                    self.emit(Instruction::Jp(start));
+                    for jump in breaks {
+                        self.patch_jump(jump);
+                    }
+                }
+                _ => return self.fail("'again' without 'loop'".to_string()),
+            },
+            name if is_name(name) => {
+                // A bare label calls it
+                self.pos -= 1;
+                self.emit_address(Instruction::Call)?;
+            }
+            _ => return self.fail(format!("unknown statement '{}'", token)),
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: usize) -> Result<(), AsmError> {
+        let op = self.next()?;
+        let operand = self.next()?;
+        if let Some(y) = register(operand) {
+            self.emit(match op {
+                ":=" => Instruction::LdReg(x, y),
+                "+=" => Instruction::AddReg(x, y),
+                "-=" => Instruction::Sub(x, y),
+                "=-" => Instruction::Subn(x, y),
+                "|=" => Instruction::Or(x, y),
+                "&=" => Instruction::And(x, y),
+                "^=" => Instruction::Xor(x, y),
+                ">>=" => Instruction::Shr(x, y),
+                "<<=" => Instruction::Shl(x, y),
+                _ => return self.fail(format!("unknown operator '{}'", op)),
+            });
+            return Ok(());
+        }
+
+        match (op, operand) {
+            (":=", "random") => {
+                let kk = self.byte()?;
+                self.emit(Instruction::Rnd(x, kk));
+            }
+            (":=", "delay") => self.emit(Instruction::LdVxDt(x)),
+            (":=", "key") => self.emit(Instruction::LdVxK(x)),
+            (":=", _) | ("+=", _) | ("-=", _) => {
+                self.pos -= 1;
+                let kk = self.byte()?;
+                self.emit(match op {
+                    ":=" => Instruction::LdByte(x, kk),
+                    "+=" => Instruction::AddByte(x, kk),
+                    _ => Instruction::AddByte(x, kk.wrapping_neg()),
+                });
+            }
+            _ => return self.fail(format!("can't use '{}' with '{}'", op, operand)),
+        }
+        Ok(())
+    }
+
+    fn i_statement(&mut self) -> Result<(), AsmError> {
+        let op = self.next()?;
+        match op {
+            ":=" => match self.peek() {
+                Some("hex") | Some("bighex") => {
+                    let big = self.next()? == "bighex";
+                    let x = self.register()?;
+                    self.emit(if big {
+                        Instruction::LdHfVx(x)
+                    } else {
+                        Instruction::LdFVx(x)
+                    });
+                }
+                Some("long") => {
+                    self.next()?;
+                    self.emit(Instruction::LdILong);
+                    match self.value()? {
+                        Value::Number(n) if (0..=0xFFFF).contains(&n) => {
+                            self.out.rom.extend_from_slice(&(n as u16).to_be_bytes())
+                        }
+                        Value::Number(n) => {
+                            return self.fail(format!("address {:#X} is out of range", n))
+                        }
+                        Value::Label(name) => {
+                            self.fixups.push(Fixup {
+                                at: self.out.rom.len(),
+                                name,
+                                line: self.line(),
+                                long: true,
+                            });
+                            self.out.rom.extend_from_slice(&[0, 0]);
+                        }
+                    }
+                }
+                _ => self.emit_address(Instruction::LdI)?,
+            },
+            "+=" => {
+                let x = self.register()?;
+                self.emit(Instruction::AddIVx(x));
+            }
+            _ => return self.fail(format!("unknown operator '{}' for i", op)),
+        }
+        Ok(())
+    }
+
+    // `if <condition> then <statement>` or `if <condition> begin ... end`
+    fn if_statement(&mut self) -> Result<(), AsmError> {
+        let (skip_if_false, skip_if_true) = self.condition()?;
+        match self.next()? {
+            "then" => {
+                self.emit(skip_if_false);
+                // The skipped statement must be a single instruction
+                let start = self.out.rom.len();
+                self.statement()?;
+                if self.out.rom.len() - start != 2 {
+                    return self
+                        .fail("'if ... then' must be followed by one instruction".to_string());
+                }
+            }
+            "begin" => {
+                let line = self.line();
+                self.emit(skip_if_true);
+                let jump = self.emit_jump();
+                self.blocks.push(Block::If { jump, line });
+            }
+            other => return self.fail(format!("expected 'then' or 'begin', found '{}'", other)),
+        }
+        Ok(())
+    }
+
+    // Reads `vx == n`, `vx != vy`, `vx key`, `vx -key` and so on, returning
+    // the instructions that skip when it is false, and when it is true
+    fn condition(&mut self) -> Result<(Instruction, Instruction), AsmError> {
+        let x = self.register()?;
+        let op = self.next()?;
+        match op {
+            "key" => return Ok((Instruction::Sknp(x), Instruction::Skp(x))),
+            "-key" => return Ok((Instruction::Skp(x), Instruction::Sknp(x))),
+            "==" | "!=" => (),
+            _ => return self.fail(format!("unknown comparison '{}'", op)),
+        }
+        let (equal, not_equal) = match self.peek().and_then(register) {
+            Some(y) => {
+                self.next()?;
+                (Instruction::SeReg(x, y), Instruction::SneReg(x, y))
+            }
+            None => {
+                let kk = self.byte()?;
+                (Instruction::SeByte(x, kk), Instruction::SneByte(x, kk))
+            }
+        };
+        Ok(if op == "==" {
+            (not_equal, equal)
+        } else {
+            (equal, not_equal)
+        })
+    }
+}
+
+fn error(line: usize, message: String) -> AsmError {
+    AsmError { line, message }
+}
+
+fn register(token: &str) -> Option<usize> {
+    let mut chars = token.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v'), Some(x), None) | (Some('V'), Some(x), None) => {
+            x.to_digit(16).map(|x| x as usize)
+        }
+        _ => None,
+    }
+}
+
+fn number(token: &str) -> Option<i64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, token),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+// Words that mean something to the assembler, so can't be labels or constants
+const KEYWORDS: [&str; 37] = [
+    "clear",
+    "return",
+    "exit",
+    "hires",
+    "lores",
+    "scroll-left",
+    "scroll-right",
+    "scroll-down",
+    "scroll-up",
+    "jump",
+    "jump0",
+    "bcd",
+    "save",
+    "load",
+    "saveflags",
+    "loadflags",
+    "sprite",
+    "plane",
+    "audio",
+    "delay",
+    "buzzer",
+    "pitch",
+    "i",
+    "if",
+    "then",
+    "begin",
+    "else",
+    "end",
+    "loop",
+    "while",
+    "again",
+    "key",
+    "-key",
+    "random",
+    "hex",
+    "bighex",
+    "long",
+];
+
+fn is_name(token: &str) -> bool {
+    token
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        && !token.starts_with(|c: char| c.is_ascii_digit() || c == '-')
+        && register(token).is_none()
+        && !KEYWORDS.contains(&token)
+}
@@ -0,0 +1,4 @@
+// Runs a rom without a window, for scripts and CI.  See `r8-headless --help`.
+fn main() {
+    std::process::exit(lib::go_headless());
+}
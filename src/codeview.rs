@@ -0,0 +1,168 @@
+use crate::cpu::Cpu;
+use crate::disassembler::Disassembly;
+use crate::instruction::Instruction;
+use std::collections::BTreeSet;
+
+// The disassembly panel beside the display: the instructions around the PC,
+// decoded from live memory so self-modifying code shows as it now is, with
+// where the branches go and the breakpoints in the gutter.  The view only
+// scrolls when the PC gets near its edges, so loops stay put while stepping.
+
+pub const ROW_HEIGHT: f32 = 15.0;
+#[cfg(feature = "gui")]
+pub const GUTTER_WIDTH: f32 = 26.0;
+const MARGIN: usize = 3; // Rows kept between the PC and the top or bottom
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeLine {
+    pub address: usize,
+    pub text: String,         // Label and mnemonic, as Disassembly::describe has it
+    pub note: Option<String>, // Where a branch goes
+    pub current: bool,        // The instruction at the PC
+    pub breakpoint: bool,
+    pub target: bool, // Somewhere the current instruction may go next
+}
+
+pub struct CodeView {
+    pub rows: usize,
+    start: Option<usize>, // The first address shown
+}
+
+impl CodeView {
+    pub fn new(rows: usize) -> CodeView {
+        CodeView { rows, start: None }
+    }
+
+    // The lines to show now, scrolling first if the PC is out of view
+    pub fn lines(
+        &mut self,
+        cpu: &Cpu,
+        disassembly: &Disassembly,
+        breakpoints: &BTreeSet<usize>,
+    ) -> Vec<CodeLine> {
+        let memory = &cpu.memory;
+        let mut addresses = self.start.map(|start| walk(memory, start, self.rows));
+        let in_view = addresses
+            .as_ref()
+            .and_then(|a| a.iter().position(|&a| a == cpu.pc));
+        match in_view {
+            Some(row) if row >= MARGIN.min(self.rows / 2) && row + MARGIN < self.rows => (),
+            // Put the PC a third of the way down, leaving more to come than gone
+            _ => {
+                let mut start = cpu.pc;
+                for _ in 0..self.rows / 3 {
+                    start = match previous(memory, start) {
+                        Some(address) => address,
+                        None => break,
+                    };
+                }
+                self.start = Some(start);
+                addresses = Some(walk(memory, start, self.rows));
+            }
+        }
+
+        let current = decode(memory, cpu.pc);
+        let targets = destinations(cpu, current);
+        addresses
+            .unwrap_or_default()
+            .into_iter()
+            .map(|address| CodeLine {
+                address,
+                text: disassembly.describe(memory, address),
+                note: note(cpu, address, address == cpu.pc),
+                current: address == cpu.pc,
+                breakpoint: breakpoints.contains(&address),
+                target: targets.contains(&address),
+            })
+            .collect()
+    }
+}
+
+// The line under a point, from the top of the rows
+pub fn line_at(lines: &[CodeLine], y: f32) -> Option<&CodeLine> {
+    if y < 0.0 {
+        return None;
+    }
+    lines.get((y / ROW_HEIGHT) as usize)
+}
+
+fn decode(memory: &[u8], address: usize) -> Option<Instruction> {
+    match memory.get(address..address + 2) {
+        Some(&[high, low]) => Instruction::decode(u16::from_be_bytes([high, low])),
+        _ => None,
+    }
+}
+
+// Where the next instruction starts; anything that doesn't decode is
+// taken two bytes at a time, as describe() shows it
+fn size(memory: &[u8], address: usize) -> usize {
+    decode(memory, address).map_or(2, |instruction| instruction.size())
+}
+
+fn walk(memory: &[u8], start: usize, rows: usize) -> Vec<usize> {
+    let mut addresses = Vec::new();
+    let mut address = start;
+    while addresses.len() < rows && address < memory.len() {
+        addresses.push(address);
+        address += size(memory, address);
+    }
+    addresses
+}
+
+// The instruction before, which is two bytes back unless it was a long load
+fn previous(memory: &[u8], address: usize) -> Option<usize> {
+    match address.checked_sub(4) {
+        Some(long) if decode(memory, long) == Some(Instruction::LdILong) => Some(long),
+        _ => address.checked_sub(2),
+    }
+}
+
+// Where a skip lands, over the next instruction
+fn skip_target(memory: &[u8], address: usize) -> usize {
+    let next = address + 2;
+    next + size(memory, next)
+}
+
+fn is_skip(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::SeByte(..)
+            | Instruction::SneByte(..)
+            | Instruction::SeReg(..)
+            | Instruction::SneReg(..)
+            | Instruction::Skp(_)
+            | Instruction::Sknp(_)
+    )
+}
+
+// Where the current instruction could send the PC, other than straight on
+fn destinations(cpu: &Cpu, instruction: Option<Instruction>) -> Vec<usize> {
+    match instruction {
+        Some(Instruction::Jp(a) | Instruction::Call(a)) => vec![a],
+        Some(Instruction::JpV0(a)) => vec![a + cpu.v[0] as usize],
+        Some(Instruction::Ret) if cpu.sp > 0 => vec![cpu.stack[cpu.sp - 1]],
+        Some(instruction) if is_skip(instruction) => vec![skip_target(&cpu.memory, cpu.pc)],
+        _ => Vec::new(),
+    }
+}
+
+// Jumps and calls say where they go.  The ones that depend on the registers
+// only say so at the PC, where the registers are the ones they'll use.
+fn note(cpu: &Cpu, address: usize, current: bool) -> Option<String> {
+    match decode(&cpu.memory, address)? {
+        Instruction::Jp(a) | Instruction::Call(a) => Some(format!(
+            "{} {:03X}",
+            if a <= address { "up to" } else { "down to" },
+            a
+        )),
+        Instruction::JpV0(a) if current => Some(format!("to {:03X}", a + cpu.v[0] as usize)),
+        Instruction::Ret if current && cpu.sp > 0 => {
+            Some(format!("back to {:03X}", cpu.stack[cpu.sp - 1]))
+        }
+        instruction if is_skip(instruction) => Some(format!(
+            "may skip to {:03X}",
+            skip_target(&cpu.memory, address)
+        )),
+        _ => None,
+    }
+}
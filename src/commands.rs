@@ -0,0 +1,434 @@
+use crate::analyzer::analyze;
+use crate::assembler::assemble;
+use crate::cpu::Cpu;
+use crate::dap::serve_dap;
+use crate::debugger::Watchpoint;
+use crate::disassembler::disassemble_analyzed;
+use crate::headless::{parse_address, run_headless, InputScript, Stop};
+use crate::profiler::Profile;
+use crate::quirks::{Platform, Quirks};
+use crate::repl::Repl;
+use crate::trace::diff_traces;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+// The r8 command line: a rom to run in the window, or one of the tools
+#[derive(StructOpt)]
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub struct Cli {
+    /// The input rom to look for
+    pub rom: Option<String>,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+
+    /// Quirks profile for ambiguous opcodes: default, vip, chip48, schip or xochip
+    #[structopt(
+        short = "q",
+        long = "quirks",
+        value_name = "quirks",
+        default_value = "default"
+    )]
+    pub platform: Platform,
+
+    /// Instructions to run per 60Hz frame (11 is roughly 660 per second)
+    #[structopt(long, default_value = "11")]
+    pub ipf: usize,
+
+    /// Start with the buzzer muted (toggle with F2)
+    #[structopt(long)]
+    pub mute: bool,
+
+    /// Buzzer tone in Hz
+    #[structopt(long, default_value = "440")]
+    pub tone: f32,
+
+    /// Buzzer volume, 0.0 - 1.0
+    #[structopt(long, default_value = "0.25")]
+    pub volume: f32,
+
+    /// Seed for the random number generator, for repeatable runs
+    #[structopt(long)]
+    pub seed: Option<u64>,
+
+    /// Memory size in bytes (defaults to 64K with the xochip quirks, 4K otherwise)
+    #[structopt(long)]
+    pub memory: Option<usize>,
+
+    /// Rewind snapshots to keep (hold Backspace to rewind)
+    #[structopt(long, default_value = "600")]
+    pub rewind: usize,
+
+    /// Frames between rewind snapshots
+    #[structopt(long, default_value = "2")]
+    pub rewind_interval: usize,
+
+    /// Record the keypad to a movie file, written on exit
+    #[structopt(long, conflicts_with = "replay")]
+    pub record: Option<String>,
+
+    /// Replay a movie file, checking the display matches at the end
+    #[structopt(long)]
+    pub replay: Option<String>,
+
+    /// The keymap file (defaults to keymap.conf in the r8 config directory)
+    #[structopt(long)]
+    pub keymap: Option<String>,
+
+    /// Start with just the display, without the debug panels (toggle with F3)
+    #[structopt(long)]
+    pub hide_debug: bool,
+
+    /// Write a line to this file for every instruction run (compare with r8 trace-diff)
+    #[structopt(long)]
+    pub trace: Option<String>,
+
+    /// Stop before running the instruction at this address (hex), may be repeated
+    #[structopt(long = "break", number_of_values = 1, parse(try_from_str = parse_address))]
+    pub breakpoints: Vec<usize>,
+
+    /// Stop when memory is read (r:ADDR) or written (w:ADDR-ADDR), or a
+    /// register changes (v0 - vF, i), may be repeated
+    #[structopt(long = "watch", number_of_values = 1)]
+    pub watchpoints: Vec<Watchpoint>,
+}
+
+// The whole r8 program, returning the exit code.  The tools don't need a
+// window, so they're there even without the gui feature.
+pub fn go() -> i32 {
+    let mut args = Cli::from_args();
+    let rom = match (args.command.take(), args.rom.take()) {
+        (Some(command), _) => return run_command(command),
+        (None, Some(rom)) => rom,
+        (None, None) => structopt::clap::Error::with_description(
+            "a rom to run, or a command, is required",
+            structopt::clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit(),
+    };
+    window(args, rom)
+}
+
+#[cfg(feature = "gui")]
+fn window(args: Cli, rom: String) -> i32 {
+    match crate::gui::window(args, rom) {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("r8: {}", err);
+            1
+        }
+    }
+}
+
+#[cfg(not(feature = "gui"))]
+fn window(_args: Cli, rom: String) -> i32 {
+    eprintln!(
+        "r8: can't run {} without the gui feature, try r8-headless or one of the commands",
+        rom
+    );
+    1
+}
+
+// The tools that run from the r8 binary instead of the emulator window
+#[derive(StructOpt)]
+pub enum Command {
+    /// Assemble an Octo source file into a rom
+    Asm {
+        /// The source file
+        source: PathBuf,
+
+        /// Where to write the rom (defaults to the source with a .ch8 extension)
+        #[structopt(short, long)]
+        output: Option<PathBuf>,
+
+        /// Also write the symbol table to this file
+        #[structopt(long)]
+        symbols: Option<PathBuf>,
+    },
+
+    /// Disassemble a rom, with made up labels for jump targets and sprites
+    Disasm {
+        /// The rom
+        rom: PathBuf,
+
+        /// Write Octo source that assembles back into the rom
+        #[structopt(long)]
+        octo: bool,
+
+        /// Where to write the listing (defaults to stdout)
+        #[structopt(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Map out a rom's code, data and call graph as JSON
+    Analyze {
+        /// The rom
+        rom: PathBuf,
+
+        /// Where to write the JSON (defaults to stdout)
+        #[structopt(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Debug a rom at a gdb style prompt, without a window (try `help`)
+    Debug {
+        /// The rom
+        rom: PathBuf,
+
+        /// Quirks profile for ambiguous opcodes: default, vip, chip48, schip or xochip
+        #[structopt(
+            short = "q",
+            long = "quirks",
+            value_name = "quirks",
+            default_value = "default"
+        )]
+        platform: Platform,
+
+        /// Instructions to run per 60Hz frame
+        #[structopt(long, default_value = "11")]
+        ipf: usize,
+
+        /// Seed for the random number generator
+        #[structopt(long, default_value = "0")]
+        seed: u64,
+
+        /// Memory size in bytes (defaults to 64K with the xochip quirks, 4K otherwise)
+        #[structopt(long)]
+        memory: Option<usize>,
+
+        /// Stop before running the instruction at this address (hex), may be repeated
+        #[structopt(long = "break", number_of_values = 1, parse(try_from_str = parse_address))]
+        breakpoints: Vec<usize>,
+
+        /// Stop on a memory read or write, or a register change, may be repeated
+        #[structopt(long = "watch", number_of_values = 1)]
+        watchpoints: Vec<Watchpoint>,
+    },
+
+    /// Serve the Debug Adapter Protocol on stdin and stdout, for editors
+    Dap,
+
+    /// Find where a trace first differs from a reference emulator's trace
+    TraceDiff {
+        /// Our trace, from --trace
+        ours: PathBuf,
+
+        /// The reference trace, in the same key=value format
+        reference: PathBuf,
+
+        /// Fields not to compare, e.g. --ignore dt,st,cycle
+        #[structopt(long, use_delimiter = true)]
+        ignore: Vec<String>,
+    },
+
+    /// Run a rom without a window and report where its instructions go
+    Profile(ProfileArgs),
+}
+
+#[derive(StructOpt)]
+pub struct ProfileArgs {
+    /// The rom
+    rom: PathBuf,
+
+    /// Quirks profile for ambiguous opcodes: default, vip, chip48, schip or xochip
+    #[structopt(
+        short = "q",
+        long = "quirks",
+        value_name = "quirks",
+        default_value = "default"
+    )]
+    platform: Platform,
+
+    /// Instructions to run per 60Hz frame
+    #[structopt(long, default_value = "11")]
+    ipf: usize,
+
+    /// Seed for the random number generator
+    #[structopt(long, default_value = "0")]
+    seed: u64,
+
+    /// Memory size in bytes (defaults to 64K with the xochip quirks, 4K otherwise)
+    #[structopt(long)]
+    memory: Option<usize>,
+
+    /// Frames to run for
+    #[structopt(long, default_value = "600")]
+    frames: usize,
+
+    /// Input script, as for r8-headless
+    #[structopt(long)]
+    input: Option<PathBuf>,
+
+    /// Lines in each table of the report
+    #[structopt(long, default_value = "10")]
+    top: usize,
+
+    /// Where to write the report (defaults to stdout)
+    #[structopt(short, long)]
+    output: Option<PathBuf>,
+}
+
+// Runs a command, returning the process exit code
+pub fn run_command(command: Command) -> i32 {
+    let result = match command {
+        Command::TraceDiff {
+            ours,
+            reference,
+            ignore,
+        } => return trace_diff(ours, reference, ignore),
+        Command::Asm {
+            source,
+            output,
+            symbols,
+        } => asm(source, output, symbols),
+        Command::Disasm { rom, octo, output } => disasm(rom, octo, output),
+        Command::Analyze { rom, output } => fs::read(&rom)
+            .map_err(|e| format!("{}: {}", rom.display(), e))
+            .and_then(|bytes| write_output(output, analyze(&bytes, 0x200).to_json())),
+        Command::Debug {
+            rom,
+            platform,
+            ipf,
+            seed,
+            memory,
+            breakpoints,
+            watchpoints,
+        } => {
+            let memory = memory.unwrap_or_else(|| platform.memory_size());
+            debug(
+                rom,
+                platform.quirks(),
+                ipf,
+                seed,
+                memory,
+                breakpoints,
+                watchpoints,
+            )
+        }
+        Command::Dap => serve_dap(io::stdin(), io::stdout()),
+        Command::Profile(args) => profile(args),
+    };
+    match result {
+        Ok(_) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+fn asm(source: PathBuf, output: Option<PathBuf>, symbols: Option<PathBuf>) -> Result<(), String> {
+    let text = fs::read_to_string(&source).map_err(|e| format!("{}: {}", source.display(), e))?;
+    let assembly = assemble(&text).map_err(|e| format!("{}:{}", source.display(), e))?;
+
+    let output = output.unwrap_or_else(|| source.with_extension("ch8"));
+    fs::write(&output, &assembly.rom).map_err(|e| format!("{}: {}", output.display(), e))?;
+    println!(
+        "Assembled {} bytes to {}",
+        assembly.rom.len(),
+        output.display()
+    );
+
+    if let Some(path) = symbols {
+        fs::write(&path, assembly.symbol_table())
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+fn disasm(rom: PathBuf, octo: bool, output: Option<PathBuf>) -> Result<(), String> {
+    let bytes = fs::read(&rom).map_err(|e| format!("{}: {}", rom.display(), e))?;
+    let disassembly = disassemble_analyzed(&bytes, 0x200, &analyze(&bytes, 0x200));
+    let text = if octo {
+        disassembly.to_octo()
+    } else {
+        disassembly.to_text()
+    };
+    write_output(output, text)
+}
+
+fn debug(
+    rom: PathBuf,
+    quirks: Quirks,
+    ipf: usize,
+    seed: u64,
+    memory: usize,
+    breakpoints: Vec<usize>,
+    watchpoints: Vec<Watchpoint>,
+) -> Result<(), String> {
+    let bytes = fs::read(&rom).map_err(|e| format!("{}: {}", rom.display(), e))?;
+    let mut repl = Repl::new(&bytes, quirks, memory, seed, ipf)?;
+    repl.debugger.breakpoints.extend(breakpoints);
+    repl.debugger.watchpoints = watchpoints;
+
+    // Only prompt a person, not a script piped in
+    let stdin = io::stdin();
+    let prompt = stdin.is_terminal();
+    repl.run(stdin.lock(), io::stdout(), prompt)
+        .map_err(|e| e.to_string())
+}
+
+fn profile(args: ProfileArgs) -> Result<(), String> {
+    let bytes = fs::read(&args.rom).map_err(|e| format!("{}: {}", args.rom.display(), e))?;
+    let mut cpu = Cpu::with_quirks(args.platform.quirks());
+    cpu.set_memory_size(args.memory.unwrap_or_else(|| args.platform.memory_size()));
+    cpu.seed_rng(args.seed);
+    cpu.load_rom_bytes(&bytes).map_err(|e| e.to_string())?;
+    let script = match &args.input {
+        Some(path) => {
+            let text =
+                fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            InputScript::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?
+        }
+        None => InputScript::default(),
+    };
+
+    cpu.profile = Some(Profile::new());
+    let (stop, frames) = run_headless(&mut cpu, args.ipf, args.frames, None, &script);
+    let mut out = match stop {
+        Stop::Fault(err) => format!("Stopped early, {}\n", err),
+        Stop::Exited => format!("The rom exited in frame {}\n", frames),
+        _ => String::new(),
+    };
+    let disassembly = disassemble_analyzed(&bytes, 0x200, &analyze(&bytes, 0x200));
+    if let Some(profile) = &cpu.profile {
+        out += &profile.report(frames, &disassembly, &cpu.memory, args.top);
+    }
+    write_output(args.output, out)
+}
+
+// Exits 0 if the traces agree and 1 if they don't, like diff, or 2 if
+// they couldn't be read
+fn trace_diff(ours: PathBuf, reference: PathBuf, ignore: Vec<String>) -> i32 {
+    let read = |path: &PathBuf| {
+        fs::read_to_string(path).map_err(|e| eprintln!("{}: {}", path.display(), e))
+    };
+    let (ours, reference) = match (read(&ours), read(&reference)) {
+        (Ok(ours), Ok(reference)) => (ours, reference),
+        _ => return 2,
+    };
+    let ignore: Vec<String> = ignore.iter().map(|f| f.to_ascii_lowercase()).collect();
+    match diff_traces(&ours, &reference, &ignore) {
+        Ok(count) => {
+            println!("traces agree for {} instructions", count);
+            0
+        }
+        Err(divergence) => {
+            print!("{}", divergence);
+            1
+        }
+    }
+}
+
+// Writes to a file if there is one, or else stdout
+fn write_output(output: Option<PathBuf>, text: String) -> Result<(), String> {
+    match output {
+        Some(path) => fs::write(&path, text).map_err(|e| format!("{}: {}", path.display(), e)),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
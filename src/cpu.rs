@@ -1,6 +1,13 @@
-use super::{C8_HEIGHT, C8_WIDTH, OPCODE_SIZE};
-use crate::fonts::FONT_SET;
-use rand::Rng;
+use super::{C8_HEIGHT, C8_WIDTH, MEMORY_SIZE, OPCODE_SIZE, SCHIP_HEIGHT, SCHIP_WIDTH};
+use crate::fonts::{BIG_FONT_ADDR, BIG_FONT_SET, FONT_SET};
+use crate::instruction::Instruction;
+use crate::memview::{format_row, BYTES_PER_ROW};
+use crate::profiler::Profile;
+use crate::quirks::Quirks;
+use crate::random::{RandomSource, SeededRandom};
+use crate::state;
+use crate::trace::{TraceLine, TraceRegisters};
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 
@@ -9,11 +16,77 @@ enum ProgramCounter {
     Skip,
     Jump(usize),
 }
+// Faults raised while executing a ROM.  Each carries the PC and the opcode
+// that was being executed when things went wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError {
+    StackOverflow {
+        pc: usize,
+        opcode: u16,
+    },
+    StackUnderflow {
+        pc: usize,
+        opcode: u16,
+    },
+    MemoryOutOfBounds {
+        pc: usize,
+        opcode: u16,
+        address: usize,
+    },
+    InvalidKey {
+        pc: usize,
+        opcode: u16,
+        key: u8,
+    },
+    PcOutOfRange {
+        pc: usize,
+        opcode: u16,
+    },
+}
+impl CpuError {
+    pub fn pc(&self) -> usize {
+        match *self {
+            CpuError::StackOverflow { pc, .. }
+            | CpuError::StackUnderflow { pc, .. }
+            | CpuError::MemoryOutOfBounds { pc, .. }
+            | CpuError::InvalidKey { pc, .. }
+            | CpuError::PcOutOfRange { pc, .. } => pc,
+        }
+    }
+
+    pub fn opcode(&self) -> u16 {
+        match *self {
+            CpuError::StackOverflow { opcode, .. }
+            | CpuError::StackUnderflow { opcode, .. }
+            | CpuError::MemoryOutOfBounds { opcode, .. }
+            | CpuError::InvalidKey { opcode, .. }
+            | CpuError::PcOutOfRange { opcode, .. } => opcode,
+        }
+    }
+}
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CpuError::StackOverflow { .. } => write!(f, "stack overflow")?,
+            CpuError::StackUnderflow { .. } => write!(f, "stack underflow")?,
+            CpuError::MemoryOutOfBounds { address, .. } => {
+                write!(f, "memory access out of bounds at {:#06X}", address)?
+            }
+            CpuError::InvalidKey { key, .. } => write!(f, "invalid key index {:#04X}", key)?,
+            CpuError::PcOutOfRange { .. } => write!(f, "program counter out of range")?,
+        }
+        write!(f, " (PC:{:#06X} OP:{:#06X})", self.pc(), self.opcode())
+    }
+}
+impl std::error::Error for CpuError {}
+
 pub struct Input {
     // There are 16 keys
     pub keys: [bool; 16],
-    pub read_keys: bool, // If true, tick() will read the key and store it into key_target
+    pub read_keys: bool, // True while op fx0a is waiting on a key press and release
     pub key_target: usize, // Set on op fx0a, where in V[] to store this key
+    pub key_pressed: Option<usize>, // The key that went down during the wait
+    pub last_keys: [bool; 16], // Key state as of the last poll, to spot new presses
 }
 impl Default for Input {
     fn default() -> Self {
@@ -26,8 +99,24 @@ impl Input {
             keys: [false; 16],
             read_keys: false,
             key_target: 0,
+            key_pressed: None,
+            last_keys: [false; 16],
         }
     }
+    // The keypad as a bitmask, bit n set while key n is held
+    pub fn key_mask(&self) -> u16 {
+        self.keys
+            .iter()
+            .enumerate()
+            .fold(0, |mask, (i, held)| mask | ((*held as u16) << i))
+    }
+
+    pub fn set_key_mask(&mut self, mask: u16) {
+        for (i, key) in self.keys.iter_mut().enumerate() {
+            *key = mask & (1 << i) != 0;
+        }
+    }
+
     pub fn dump_keys(&self) -> String {
         let mut k: [usize; 16] = [0; 16];
         for (i, x) in self.keys.iter().enumerate() {
@@ -42,8 +131,8 @@ impl Input {
 }
 
 pub struct Cpu {
-    // Memory
-    pub memory: [u8; 4096],
+    // Memory, 4K normally or up to 64K for XO-CHIP
+    pub memory: Vec<u8>,
     pub opcode: u16,
 
     // Registers
@@ -51,10 +140,11 @@ pub struct Cpu {
     pub i: usize,  // Index register
     pub pc: usize, // Program Counter
 
-    // Array of graphics pixels ( 64 x 32 )
-    pub gfx: [[u8; C8_WIDTH]; C8_HEIGHT],
-    //pub gfx: [u8; C8_WIDTH * C8_HEIGHT],
+    // Rows of graphics pixels ( 64 x 32, or 128 x 64 in hires mode ).  Each
+    // pixel is a bitmask of the XO-CHIP planes it is lit in, so 0 - 3.
+    pub gfx: Vec<Vec<u8>>,
     pub gfx_updated: bool,
+    pub hires: bool,
 
     // Some timers
     pub delay_timer: u8,
@@ -69,6 +159,37 @@ pub struct Cpu {
 
     // A pause tick flag for single stepping
     pub pause_tick: bool,
+
+    // Which interpretation to use for the ambiguous opcodes
+    pub quirks: Quirks,
+
+    // Set by signal_vblank(), used by the display wait quirk
+    pub vblank: bool,
+
+    // Random numbers for op cxkk
+    pub rng: Box<dyn RandomSource>,
+
+    // SUPER-CHIP: set once 00FD has run, and the RPL user flags
+    pub exited: bool,
+    pub rpl: [u8; 16],
+
+    // XO-CHIP: the selected drawing planes, and the audio pattern and pitch
+    pub planes: u8,
+    pub audio_pattern: Option<[u8; 16]>,
+    pub pitch: u8,
+    pub audio_updated: bool,
+
+    // Hash of the loaded ROM, so save states can't be loaded into the wrong game
+    pub rom_hash: u64,
+
+    // Instructions executed so far, so a run can be replayed to an exact point
+    pub cycles: u64,
+
+    // Where to write a line for each instruction, if anywhere
+    pub trace: Option<Box<dyn Write>>,
+
+    // Counts of what ran, while profiling
+    pub profile: Option<Profile>,
 }
 
 impl Default for Cpu {
@@ -80,27 +201,45 @@ impl Default for Cpu {
 impl Cpu {
     pub fn new() -> Cpu {
         let mut cpu = Cpu {
-            memory: [0; 4096],
+            memory: vec![0; MEMORY_SIZE],
             opcode: 0x00,
             v: [0; 16],
             i: 0,
             pc: 0x200,
-            gfx: [[0; C8_WIDTH]; C8_HEIGHT],
+            gfx: vec![vec![0; C8_WIDTH]; C8_HEIGHT],
             gfx_updated: false,
+            hires: false,
             delay_timer: 0,
             sound_timer: 0,
             stack: [0; 16],
             sp: 0,
             input: Input::new(),
             pause_tick: false,
+            quirks: Quirks::default(),
+            vblank: false,
+            rng: Box::new(SeededRandom::new(rand::random())),
+            exited: false,
+            rpl: [0; 16],
+            planes: 1,
+            audio_pattern: None,
+            pitch: 64,
+            audio_updated: false,
+            rom_hash: 0,
+            cycles: 0,
+            trace: None,
+            profile: None,
         };
         cpu.load_fonts();
         cpu
     }
 
+    // A hex and ASCII dump, 16 bytes a line
     pub fn dump_ram(&mut self) -> String {
-        let strs: Vec<String> = self.memory.iter().map(|b| format!("{:02X}", b)).collect();
-        strs.join(" ")
+        let rows: Vec<String> = (0..self.memory.len())
+            .step_by(BYTES_PER_ROW)
+            .map(|address| format_row(&self.memory, address))
+            .collect();
+        rows.join("\n")
     }
 
     #[allow(dead_code)]
@@ -108,51 +247,175 @@ impl Cpu {
         println!("  g: {:?}", self.gfx);
     }
 
+    // Builds a cpu using the given quirks profile
+    pub fn with_quirks(quirks: Quirks) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.quirks = quirks;
+        cpu
+    }
+
+    // Grows (or shrinks) memory, e.g. to the 64K XO-CHIP address space
+    pub fn set_memory_size(&mut self, size: usize) {
+        self.memory.resize(size, 0);
+    }
+
+    // The register fx0a will store a key into, if we are waiting on one
+    pub fn waiting_for_key(&self) -> Option<usize> {
+        if self.input.read_keys {
+            return Some(self.input.key_target);
+        }
+        None
+    }
+
+    // Swaps in a different source of random numbers for op cxkk
+    pub fn set_rng(&mut self, rng: Box<dyn RandomSource>) {
+        self.rng = rng;
+    }
+
+    // Reseeds the default random number generator, for repeatable runs
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = Box::new(SeededRandom::new(seed));
+    }
+
+    // Called by the frontend at the start of each 60Hz frame
+    pub fn signal_vblank(&mut self) {
+        self.vblank = true;
+    }
+
     // Loads to font set into ram
     fn load_fonts(&mut self) {
         for (i, f) in FONT_SET.iter().enumerate() {
             self.memory[i] = *f;
         }
+        for (i, f) in BIG_FONT_SET.iter().enumerate() {
+            self.memory[BIG_FONT_ADDR + i] = *f;
+        }
+    }
+
+    // Display size for the current mode
+    pub fn width(&self) -> usize {
+        if self.hires {
+            SCHIP_WIDTH
+        } else {
+            C8_WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            SCHIP_HEIGHT
+        } else {
+            C8_HEIGHT
+        }
+    }
+
+    // Switches between lores and hires, which also clears the display
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.gfx = vec![vec![0; self.width()]; self.height()];
+        self.gfx_updated = true;
     }
 
     // Load the rom into memory, with the 0x200 offset
     pub fn load_rom(&mut self, file: String) -> Result<(), std::io::Error> {
-        let rom = File::open(file)?;
-        for (i, b) in rom.bytes().enumerate() {
-            self.memory[0x200 + i] = b.unwrap();
+        let mut rom = Vec::new();
+        File::open(file)?.read_to_end(&mut rom)?;
+        self.load_rom_bytes(&rom)
+    }
+
+    // Loads a ROM image already in memory at 0x200
+    pub fn load_rom_bytes(&mut self, rom: &[u8]) -> Result<(), std::io::Error> {
+        if 0x200 + rom.len() > self.memory.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("ROM is too large ({} bytes)", rom.len()),
+            ));
+        }
+        for (i, b) in rom.iter().enumerate() {
+            self.memory[0x200 + i] = *b;
         }
+        self.rom_hash = state::fnv1a(rom);
 
         Ok(())
     }
 
     // Reads a word from memory located at program counter
-    pub fn read_word(&mut self) -> u16 {
-        (u16::from(self.memory[self.pc]) << 8) | u16::from(self.memory[self.pc + 1])
+    pub fn read_word(&mut self) -> Result<u16, CpuError> {
+        if self.pc + 1 >= self.memory.len() {
+            return Err(CpuError::PcOutOfRange {
+                pc: self.pc,
+                opcode: self.opcode,
+            });
+        }
+        Ok(self.word_at(self.pc))
+    }
+
+    // The big endian word at an address, or 0 past the end of memory
+    pub fn word_at(&self, address: usize) -> u16 {
+        match (self.memory.get(address), self.memory.get(address + 1)) {
+            (Some(hi), Some(lo)) => (u16::from(*hi) << 8) | u16::from(*lo),
+            _ => 0,
+        }
     }
 
     // TODO: Output state control for sound/graphics
-    pub fn tick(&mut self, dump_regs: bool) {
-        // Store the key pressed into the expected key_target
-        if self.input.read_keys {
-            for i in 0..self.input.keys.len() {
-                if self.input.keys[i] {
-                    self.input.read_keys = false;
-                    self.v[self.input.key_target] = i as u8;
-                    break;
-                }
-            }
+    pub fn tick(&mut self, dump_regs: bool) -> Result<(), CpuError> {
+        // Nothing more to do once the program has exited
+        if self.exited {
+            return Ok(());
         }
 
-        // Decrement the delay counter if it is above zero
-        self.delay_timer = if self.delay_timer > 0 {
-            self.delay_timer - 1
-        } else {
-            0
-        };
+        let pc = self.pc;
+        let opcode = self.read_word()?;
+        self.run_opcode(opcode, Some(dump_regs))?;
+        self.cycles += 1;
+        if let Some(profile) = &mut self.profile {
+            profile.record(pc, Instruction::decode(opcode), self.pc);
+        }
+        Ok(())
+    }
 
-        let opcode = self.read_word();
-        self.opcode = opcode;
-        self.run_opcode(opcode, Some(dump_regs));
+    // Counts the delay and sound timers down, once per 60Hz frame
+    pub fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+
+    // A hash of the display, to check that two runs drew the same thing
+    pub fn gfx_hash(&self) -> u64 {
+        self.gfx
+            .iter()
+            .fold(state::fnv1a(&[self.hires as u8]), |hash, row| {
+                state::fnv1a_extend(hash, row)
+            })
+    }
+
+    // The buzzer sounds for as long as the sound timer is non zero
+    pub fn sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    // Runs a single 60Hz frame: `cycles` instructions followed by
+    // a single step of the timers.
+    pub fn run_frame(&mut self, cycles: usize) -> Result<(), CpuError> {
+        self.run_frame_until(cycles, |_| false).map(|_| ())
+    }
+
+    // As run_frame(), but checks `stop` after every instruction and leaves
+    // the frame there if it says so.  True if the frame was cut short.
+    pub fn run_frame_until<F>(&mut self, cycles: usize, mut stop: F) -> Result<bool, CpuError>
+    where
+        F: FnMut(&Cpu) -> bool,
+    {
+        self.signal_vblank();
+        for _ in 0..cycles {
+            self.tick(false)?;
+            if stop(self) {
+                return Ok(true);
+            }
+        }
+        self.tick_timers();
+        Ok(false)
     }
 
     pub fn get_nibbles(&mut self, opcode: u16) -> (u16, u16, u16, u8) {
@@ -166,24 +429,16 @@ impl Cpu {
         )
     }
 
-    pub fn run_opcode(&mut self, opcode: u16, dump_regs: Option<bool>) {
-        // Break the opcode into its distinct parts so we can determine what
-        // to do with what and where
-        let nibbles = self.get_nibbles(opcode);
-        let nnn = (opcode & 0x0FFF) as usize;
-        let kk = (opcode & 0x00FF) as u8;
-        let x = nibbles.1 as usize;
-        let y = nibbles.2 as usize;
-        let n = nibbles.3 as usize;
-
-        if dump_regs.unwrap_or(false) {
-            println!("Running opcode: {:X}", opcode);
-            println!("  Nibbles: {:?}", nibbles);
-            println!(
-                "  nnn:{} / kk:{}|{:X} / x,y,n: {},{},{}",
-                nnn, kk, kk, x, y, n
-            );
-        }
+    pub fn run_opcode(&mut self, opcode: u16, dump_regs: Option<bool>) -> Result<(), CpuError> {
+        self.opcode = opcode;
+        let instruction = Instruction::decode(opcode);
+
+        // Dumping the registers prints the trace line
+        let dump_regs = dump_regs.unwrap_or(false);
+        let trace = match dump_regs || self.trace.is_some() {
+            true => Some((self.pc, TraceRegisters::of(self))),
+            false => None,
+        };
 
         //After each runcode we need to update our program counter so we can read
         //a specific opcode out of ram.  Sometimes it's just "the next one", sometimes
@@ -192,68 +447,230 @@ impl Cpu {
         //Therefore each opcode's function will return either "next", "skip", or "jump"
         //perfect use of an Enum here.
 
-        let pc_change: ProgramCounter = match nibbles {
-            // Skipping 0NNN (Jump to machine code at location NNN)
-            (0x00, 0x00, 0x0E, 0x00) => self.op_00e0(), // Clears the screen
-            (0x00, 0x00, 0x0E, 0x0E) => self.op_00ee(), // Set PC to addr at top of stack and sub 1 from sp.
-            (0x01, _, _, _) => self.op_1nnn(nnn),       // PC Jumps to location at nnn
-            (0x02, _, _, _) => self.op_2nnn(nnn),       // Call subroutine at nnn
-            (0x03, _, _, _) => self.op_3xkk(x, kk),     // Skip if Vx = kk
-            (0x04, _, _, _) => self.op_4xkk(x, kk),     // Skip if Vx != kk
-            (0x05, _, _, 0x00) => self.op_5xy0(x, y),   // Skip if Vx = Vy
-            (0x06, _, _, _) => self.op_6xkk(x, kk),     // Puts value kk into register Vx
-            (0x07, _, _, _) => self.op_7xkk(x, kk),     // Sets Vx = Vx + kk with overflow
-            (0x08, _, _, 0x00) => self.op_8xy0(x, y),   // Puts value Vy into Vx
-            (0x08, _, _, 0x01) => self.op_8xy1(x, y),   // Bitwise OR of Vx and Vy; result in Vx
-            (0x08, _, _, 0x02) => self.op_8xy2(x, y),   // Bitwise AND of Vx and Vy; result in Vx
-            (0x08, _, _, 0x03) => self.op_8xy3(x, y),   // Bitwise XOR of Vx and Vy; result in Vx
-            (0x08, _, _, 0x04) => self.op_8xy4(x, y),   // Vx = Vx + Vy; if carry set VF
-            (0x08, _, _, 0x05) => self.op_8xy5(x, y),   // Vx = Vx - Vy; if carry set VF
-            (0x08, _, _, 0x06) => self.op_8x06(x),      // SHR Vx {, Vy}
-            (0x08, _, _, 0x07) => self.op_8xy7(x, y),   // SUB Vx from Vy
-            (0x08, _, _, 0x0E) => self.op_8x0e(x),      // Vx *= 2; with VF set if MSB Vx = 1
-            (0x09, _, _, 0x00) => self.op_9xy0(x, y),   // Skip next if Vx != Vy
-            (0x0A, _, _, _) => self.op_annn(nnn),       // Load nnn into register I
-            (0x0B, _, _, _) => self.op_bnnn(nnn),       // Jump to nnn+v[0]
-            (0x0C, _, _, _) => self.op_cxkk(x, kk),     // Set Vx = random byte AND kk.
-            (0x0D, _, _, _) => self.op_dxyn(x, y, n),   // Display n-byte sprite
-            (0x0E, _, 0x09, 0x0E) => self.op_ex9e(x),   // Skip if key at v[x] is pressed
-            (0x0E, _, 0x0A, 0x01) => self.op_exa1(x),   // Skip if key at v[x] is not pressed
-            (0x0F, _, 0x00, 0x07) => self.op_fx07(x),   // Vx = Dt value
-            (0x0F, _, 0x00, 0x0A) => self.op_fx0a(x),   // Store keypress into v[x]
-            (0x0F, _, 0x01, 0x05) => self.op_fx15(x),   // Dt = Vx
-            (0x0F, _, 0x01, 0x08) => self.op_fx18(x),   // St = Vx
-            (0x0F, _, 0x01, 0x0E) => self.op_fx1e(x),   // I = I + Vx.
-            (0x0F, _, 0x02, 0x09) => self.op_fx29(x),   // I = location of sprite for digit Vx.
-            (0x0F, _, 0x03, 0x03) => self.op_fx33(x), // BCD rep of Vx in memory locations I, I+1, and I+2.
-            (0x0F, _, 0x05, 0x05) => self.op_fx55(x), // Store V0 through Vx in memory starting at I.
-            (0x0F, _, 0x06, 0x05) => self.op_fx65(x), // Read V0 through Vx from memory starting at I.
-            _ => ProgramCounter::Next,
+        let pc_change: ProgramCounter = match instruction {
+            Some(instruction) => self.execute(instruction)?,
+            None => ProgramCounter::Next,
         };
 
         match pc_change {
             ProgramCounter::Next => self.pc += OPCODE_SIZE,
-            ProgramCounter::Skip => self.pc += OPCODE_SIZE * 2,
+            ProgramCounter::Skip => {
+                // Skipping over an XO-CHIP F000 NNNN skips all four bytes
+                self.pc += OPCODE_SIZE;
+                self.pc += match Instruction::decode(self.word_at(self.pc)) {
+                    Some(next) => next.size(),
+                    None => OPCODE_SIZE,
+                };
+            }
             ProgramCounter::Jump(p) => self.pc = p,
         }
+
+        if let Some((pc, before)) = trace {
+            self.write_trace(pc, opcode, instruction, before, dump_regs);
+        }
+        Ok(())
+    }
+
+    fn write_trace(
+        &mut self,
+        pc: usize,
+        opcode: u16,
+        instruction: Option<Instruction>,
+        before: TraceRegisters,
+        print: bool,
+    ) {
+        let opcode = match instruction {
+            Some(Instruction::LdILong) => {
+                (u32::from(opcode) << 16) | u32::from(self.word_at(pc + OPCODE_SIZE))
+            }
+            _ => u32::from(opcode),
+        };
+        let line = TraceLine {
+            cycle: self.cycles,
+            pc,
+            opcode,
+            instruction,
+            before,
+            after: TraceRegisters::of(self),
+        };
+        if print {
+            println!("{}", line);
+        }
+        if let Some(trace) = &mut self.trace {
+            // A trace with holes in it is worse than none
+            if let Err(err) = writeln!(trace, "{}", line) {
+                eprintln!("Stopped tracing: {}", err);
+                self.trace = None;
+            }
+        }
+    }
+
+    // Dispatches a decoded instruction to the function implementing it
+    fn execute(&mut self, instruction: Instruction) -> Result<ProgramCounter, CpuError> {
+        let pc_change = match instruction {
+            Instruction::Sys(_) => ProgramCounter::Next, // Skipping 0NNN (Jump to machine code at location NNN)
+            Instruction::ScrollDown(n) => self.op_00cn(n), // Scroll down n pixels
+            Instruction::ScrollUp(n) => self.op_00dn(n), // Scroll up n pixels
+            Instruction::ScrollRight => self.op_00fb(),  // Scroll right 4 pixels
+            Instruction::ScrollLeft => self.op_00fc(),   // Scroll left 4 pixels
+            Instruction::Exit => self.op_00fd(),         // Exit the interpreter
+            Instruction::Low => self.op_00fe(),          // Lores mode
+            Instruction::High => self.op_00ff(),         // Hires mode
+            Instruction::Cls => self.op_00e0(),          // Clears the screen
+            Instruction::Ret => self.op_00ee()?, // Set PC to addr at top of stack and sub 1 from sp.
+            Instruction::Jp(nnn) => self.op_1nnn(nnn), // PC Jumps to location at nnn
+            Instruction::Call(nnn) => self.op_2nnn(nnn)?, // Call subroutine at nnn
+            Instruction::SeByte(x, kk) => self.op_3xkk(x, kk), // Skip if Vx = kk
+            Instruction::SneByte(x, kk) => self.op_4xkk(x, kk), // Skip if Vx != kk
+            Instruction::SeReg(x, y) => self.op_5xy0(x, y), // Skip if Vx = Vy
+            Instruction::SaveRange(x, y) => self.op_5xy2(x, y)?, // Store Vx..Vy at I
+            Instruction::LoadRange(x, y) => self.op_5xy3(x, y)?, // Read Vx..Vy from I
+            Instruction::LdByte(x, kk) => self.op_6xkk(x, kk), // Puts value kk into register Vx
+            Instruction::AddByte(x, kk) => self.op_7xkk(x, kk), // Sets Vx = Vx + kk with overflow
+            Instruction::LdReg(x, y) => self.op_8xy0(x, y), // Puts value Vy into Vx
+            Instruction::Or(x, y) => self.op_8xy1(x, y), // Bitwise OR of Vx and Vy; result in Vx
+            Instruction::And(x, y) => self.op_8xy2(x, y), // Bitwise AND of Vx and Vy; result in Vx
+            Instruction::Xor(x, y) => self.op_8xy3(x, y), // Bitwise XOR of Vx and Vy; result in Vx
+            Instruction::AddReg(x, y) => self.op_8xy4(x, y), // Vx = Vx + Vy; if carry set VF
+            Instruction::Sub(x, y) => self.op_8xy5(x, y), // Vx = Vx - Vy; if carry set VF
+            Instruction::Shr(x, y) => self.op_8x06(x, y), // SHR Vx {, Vy}
+            Instruction::Subn(x, y) => self.op_8xy7(x, y), // SUB Vx from Vy
+            Instruction::Shl(x, y) => self.op_8x0e(x, y), // Vx *= 2; with VF set if MSB Vx = 1
+            Instruction::SneReg(x, y) => self.op_9xy0(x, y), // Skip next if Vx != Vy
+            Instruction::LdI(nnn) => self.op_annn(nnn), // Load nnn into register I
+            Instruction::JpV0(nnn) => self.op_bnnn(nnn), // Jump to nnn+v[0]
+            Instruction::Rnd(x, kk) => self.op_cxkk(x, kk), // Set Vx = random byte AND kk.
+            Instruction::Drw(x, y, n) => self.op_dxyn(x, y, n)?, // Display n-byte sprite
+            Instruction::Skp(x) => self.op_ex9e(x)?, // Skip if key at v[x] is pressed
+            Instruction::Sknp(x) => self.op_exa1(x)?, // Skip if key at v[x] is not pressed
+            Instruction::LdILong => self.op_f000()?, // I = the next 16 bit word
+            Instruction::Plane(n) => self.op_fn01(n), // Select drawing planes
+            Instruction::Audio => self.op_f002()?, // Load the audio pattern from I
+            Instruction::LdVxDt(x) => self.op_fx07(x), // Vx = Dt value
+            Instruction::LdVxK(x) => self.op_fx0a(x), // Store keypress into v[x]
+            Instruction::LdDtVx(x) => self.op_fx15(x), // Dt = Vx
+            Instruction::LdStVx(x) => self.op_fx18(x), // St = Vx
+            Instruction::Pitch(x) => self.op_fx3a(x), // Pitch = Vx
+            Instruction::AddIVx(x) => self.op_fx1e(x), // I = I + Vx.
+            Instruction::LdFVx(x) => self.op_fx29(x), // I = location of sprite for digit Vx.
+            Instruction::LdHfVx(x) => self.op_fx30(x), // I = location of big sprite for digit Vx.
+            Instruction::LdBVx(x) => self.op_fx33(x)?, // BCD rep of Vx in memory locations I, I+1, and I+2.
+            Instruction::LdIVx(x) => self.op_fx55(x)?, // Store V0 through Vx in memory starting at I.
+            Instruction::LdVxI(x) => self.op_fx65(x)?, // Read V0 through Vx from memory starting at I.
+            Instruction::LdRVx(x) => self.op_fx75(x),  // Store V0 through Vx in the RPL flags.
+            Instruction::LdVxR(x) => self.op_fx85(x),  // Read V0 through Vx from the RPL flags.
+        };
+        Ok(pc_change)
+    }
+
+    // Ensures that an address falls within memory before we touch it
+    fn check_address(&self, address: usize) -> Result<usize, CpuError> {
+        if address >= self.memory.len() {
+            return Err(CpuError::MemoryOutOfBounds {
+                pc: self.pc,
+                opcode: self.opcode,
+                address,
+            });
+        }
+        Ok(address)
+    }
+
+    // Ensures that a register value is a valid key index (0-F)
+    fn check_key(&self, key: u8) -> Result<usize, CpuError> {
+        if key as usize >= self.input.keys.len() {
+            return Err(CpuError::InvalidKey {
+                pc: self.pc,
+                opcode: self.opcode,
+                key,
+            });
+        }
+        Ok(key as usize)
     }
 
     // Clear screen
-    // TODO: Implement graphics
+    // Only the selected XO-CHIP planes are cleared
     fn op_00e0(&mut self) -> ProgramCounter {
         for row in self.gfx.iter_mut() {
             for elem in row.iter_mut() {
-                *elem = 0;
+                *elem &= !self.planes;
+            }
+        }
+        self.gfx_updated = true;
+        ProgramCounter::Next
+    }
+
+    // Moves the selected planes of the display by (dx, dy), filling in
+    // with blank pixels.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let old = self.gfx.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = (x - dx, y - dy);
+                let src = if sx >= 0 && sx < width && sy >= 0 && sy < height {
+                    old[sy as usize][sx as usize]
+                } else {
+                    0
+                };
+                let pixel = &mut self.gfx[y as usize][x as usize];
+                *pixel = (*pixel & !self.planes) | (src & self.planes);
             }
         }
         self.gfx_updated = true;
+    }
+
+    // Scroll the display down n pixels
+    fn op_00cn(&mut self, n: usize) -> ProgramCounter {
+        self.scroll(0, n as isize);
+        ProgramCounter::Next
+    }
+
+    // Scroll the display up n pixels
+    fn op_00dn(&mut self, n: usize) -> ProgramCounter {
+        self.scroll(0, -(n as isize));
+        ProgramCounter::Next
+    }
+
+    // Scroll the display right 4 pixels
+    fn op_00fb(&mut self) -> ProgramCounter {
+        self.scroll(4, 0);
+        ProgramCounter::Next
+    }
+
+    // Scroll the display left 4 pixels
+    fn op_00fc(&mut self) -> ProgramCounter {
+        self.scroll(-4, 0);
+        ProgramCounter::Next
+    }
+
+    // Exit the interpreter; the PC stays here and tick() does nothing more
+    fn op_00fd(&mut self) -> ProgramCounter {
+        self.exited = true;
+        ProgramCounter::Jump(self.pc)
+    }
+
+    // Switch to lores (64 x 32) mode
+    fn op_00fe(&mut self) -> ProgramCounter {
+        self.set_hires(false);
+        ProgramCounter::Next
+    }
+
+    // Switch to hires (128 x 64) mode
+    fn op_00ff(&mut self) -> ProgramCounter {
+        self.set_hires(true);
         ProgramCounter::Next
     }
 
     // Subtract 1 from sp and jump to address in stack
-    fn op_00ee(&mut self) -> ProgramCounter {
+    fn op_00ee(&mut self) -> Result<ProgramCounter, CpuError> {
+        if self.sp == 0 {
+            return Err(CpuError::StackUnderflow {
+                pc: self.pc,
+                opcode: self.opcode,
+            });
+        }
         self.sp -= 1;
-        ProgramCounter::Jump(self.stack[self.sp])
+        Ok(ProgramCounter::Jump(self.stack[self.sp]))
     }
 
     // PC Jumps to location NNN
@@ -262,10 +679,16 @@ impl Cpu {
     }
 
     // Call subroutine at NNN
-    fn op_2nnn(&mut self, nnn: usize) -> ProgramCounter {
+    fn op_2nnn(&mut self, nnn: usize) -> Result<ProgramCounter, CpuError> {
+        if self.sp >= self.stack.len() {
+            return Err(CpuError::StackOverflow {
+                pc: self.pc,
+                opcode: self.opcode,
+            });
+        }
         self.stack[self.sp] = self.pc + OPCODE_SIZE;
         self.sp += 1;
-        ProgramCounter::Jump(nnn)
+        Ok(ProgramCounter::Jump(nnn))
     }
 
     // Skip next if Vx = kk
@@ -292,6 +715,28 @@ impl Cpu {
         ProgramCounter::Next
     }
 
+    // Store Vx through Vy in memory starting at I, in either order.  I is unchanged.
+    fn op_5xy2(&mut self, x: usize, y: usize) -> Result<ProgramCounter, CpuError> {
+        let count = x.abs_diff(y);
+        self.check_address(self.i + count)?;
+        for l in 0..=count {
+            let r = if x > y { x - l } else { x + l };
+            self.memory[self.i + l] = self.v[r];
+        }
+        Ok(ProgramCounter::Next)
+    }
+
+    // Read Vx through Vy from memory starting at I, in either order.  I is unchanged.
+    fn op_5xy3(&mut self, x: usize, y: usize) -> Result<ProgramCounter, CpuError> {
+        let count = x.abs_diff(y);
+        self.check_address(self.i + count)?;
+        for l in 0..=count {
+            let r = if x > y { x - l } else { x + l };
+            self.v[r] = self.memory[self.i + l];
+        }
+        Ok(ProgramCounter::Next)
+    }
+
     // Set Vx = kk
     fn op_6xkk(&mut self, x: usize, kk: u8) -> ProgramCounter {
         self.v[x] = kk;
@@ -316,18 +761,27 @@ impl Cpu {
     // Bitwise OR of Vx and Vy with result in Vx
     fn op_8xy1(&mut self, x: usize, y: usize) -> ProgramCounter {
         self.v[x] |= self.v[y];
+        if self.quirks.vf_reset {
+            self.v[0x0F] = 0;
+        }
         ProgramCounter::Next
     }
 
     // Bitwise AND of Vx and Vy with result in Vx
     fn op_8xy2(&mut self, x: usize, y: usize) -> ProgramCounter {
         self.v[x] &= self.v[y];
+        if self.quirks.vf_reset {
+            self.v[0x0F] = 0;
+        }
         ProgramCounter::Next
     }
 
     // Bitwise XOR of Vx and Vy with result in Vx
     fn op_8xy3(&mut self, x: usize, y: usize) -> ProgramCounter {
         self.v[x] ^= self.v[y];
+        if self.quirks.vf_reset {
+            self.v[0x0F] = 0;
+        }
         ProgramCounter::Next
     }
 
@@ -361,8 +815,11 @@ impl Cpu {
 
     // Vx = Vx SHR 1
     // If LSB of Vx = 1 then VF = 1 else VF = 0; then Vx
-    // is divided by 2.
-    fn op_8x06(&mut self, x: usize) -> ProgramCounter {
+    // is divided by 2.  Without the shift quirk Vy is shifted into Vx.
+    fn op_8x06(&mut self, x: usize, y: usize) -> ProgramCounter {
+        if !self.quirks.shift {
+            self.v[x] = self.v[y];
+        }
         self.v[0x0F] = self.v[x] & 0b01; // And with 1 to get final bit
         self.v[x] >>= 1; // Shift right 1, dividing by 2
         ProgramCounter::Next
@@ -378,8 +835,11 @@ impl Cpu {
     }
 
     // If Most Significant Bit of V[x] = 1 then set V[F] = 1 else V[F] = 0
-    // Multiply V[x] by 2
-    fn op_8x0e(&mut self, x: usize) -> ProgramCounter {
+    // Multiply V[x] by 2.  Without the shift quirk Vy is shifted into Vx.
+    fn op_8x0e(&mut self, x: usize, y: usize) -> ProgramCounter {
+        if !self.quirks.shift {
+            self.v[x] = self.v[y];
+        }
         self.v[0x0F] = (self.v[x] & 0b1000_0000) >> 7; // Bitmask with shift for 1 or 0
         self.v[x] <<= 1; // Multiply by 2
         ProgramCounter::Next
@@ -401,54 +861,137 @@ impl Cpu {
 
     // Jump to location nnn + V0.
     // The program counter is set to nnn plus the value of V0.
+    // With the jump quirk this is Bxnn: xnn plus the value of Vx.
     fn op_bnnn(&mut self, nnn: usize) -> ProgramCounter {
-        ProgramCounter::Jump(nnn + self.v[0] as usize)
+        let x = if self.quirks.jump_with_vx {
+            nnn >> 8
+        } else {
+            0
+        };
+        ProgramCounter::Jump(nnn + self.v[x] as usize)
     }
 
     // Set Vx = random byte AND kk.
     fn op_cxkk(&mut self, x: usize, kk: u8) -> ProgramCounter {
-        let mut rng = rand::thread_rng();
-        let v = rng.gen_range(0_u16, 256_u16) as u8; // Inclusive of low, exclusive of high, so 0-255
-        self.v[x] = kk.wrapping_add(v);
+        self.v[x] = self.rng.next_byte() & kk;
 
         ProgramCounter::Next
     }
 
     // Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
+    // Dxy0 draws a 16x16 sprite, two bytes per row.  In hires mode VF is the
+    // number of rows that collided (or were clipped off the bottom), like SUPER-CHIP.
+    // With both XO-CHIP planes selected the second plane's sprite data
+    // follows straight on from the first's.
     // TODO: Separate this into a display module?
-    fn op_dxyn(&mut self, x: usize, y: usize, n: usize) -> ProgramCounter {
-        // Set VF to zero to start
-        self.v[0x0F] = 0;
-
-        for byte in 0..n {
-            let y = (self.v[y] as usize + byte) % C8_HEIGHT;
-            for bit in 0..8 {
-                let x = (self.v[x] as usize + bit) % C8_WIDTH;
-                let color = (self.memory[self.i + byte] >> (7 - bit)) & 1;
-                self.v[0x0F] |= color & self.gfx[y][x];
-                self.gfx[y][x] ^= color;
+    fn op_dxyn(&mut self, x: usize, y: usize, n: usize) -> Result<ProgramCounter, CpuError> {
+        let (rows, cols) = if n == 0 { (16, 16) } else { (n, 8) };
+        let row_bytes = cols / 8;
+        let sprite_bytes = rows * row_bytes;
+        let planes: Vec<u8> = [1, 2]
+            .iter()
+            .cloned()
+            .filter(|plane| self.planes & plane != 0)
+            .collect();
+        if !planes.is_empty() {
+            self.check_address(self.i + sprite_bytes * planes.len() - 1)?;
+        }
+
+        // With the display wait quirk only one sprite is drawn per frame,
+        // so hold the PC here until the next vblank.
+        if self.quirks.display_wait {
+            if !self.vblank {
+                return Ok(ProgramCounter::Jump(self.pc));
+            }
+            self.vblank = false;
+        }
+
+        // The starting position always wraps, the rest of the sprite
+        // either wraps or is clipped at the edges.
+        let (width, height) = (self.width(), self.height());
+        let start_x = self.v[x] as usize % width;
+        let start_y = self.v[y] as usize % height;
+        let mut collided = vec![false; rows];
+        let mut clipped = 0;
+
+        for (p, plane) in planes.iter().enumerate() {
+            let sprite = self.i + p * sprite_bytes;
+            for (row, row_collided) in collided.iter_mut().enumerate() {
+                if self.quirks.clip && start_y + row >= height {
+                    clipped = rows - row;
+                    break;
+                }
+                let y = (start_y + row) % height;
+                for col in 0..cols {
+                    if self.quirks.clip && start_x + col >= width {
+                        break;
+                    }
+                    let x = (start_x + col) % width;
+                    let byte = self.memory[sprite + row * row_bytes + col / 8];
+                    if (byte >> (7 - col % 8)) & 1 == 1 {
+                        *row_collided |= self.gfx[y][x] & plane != 0;
+                        self.gfx[y][x] ^= plane;
+                    }
+                }
             }
         }
 
-        //self.gfx[vx as usize][vy as usize] = 1;
+        let collisions = collided.iter().filter(|c| **c).count() + clipped;
+        self.v[0x0F] = if self.hires {
+            collisions as u8
+        } else {
+            (collisions > 0) as u8
+        };
         self.gfx_updated = true;
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // Skip next instruction if key with the value of Vx is pressed.
-    fn op_ex9e(&mut self, x: usize) -> ProgramCounter {
-        if self.input.keys[self.v[x] as usize] {
-            return ProgramCounter::Skip;
+    fn op_ex9e(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
+        let key = self.check_key(self.v[x])?;
+        if self.input.keys[key] {
+            return Ok(ProgramCounter::Skip);
         }
 
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // Skip next instruction if key with the value of Vx is not pressed.
-    fn op_exa1(&mut self, x: usize) -> ProgramCounter {
-        if !self.input.keys[self.v[x] as usize] {
-            return ProgramCounter::Skip;
+    fn op_exa1(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
+        let key = self.check_key(self.v[x])?;
+        if !self.input.keys[key] {
+            return Ok(ProgramCounter::Skip);
         }
+        Ok(ProgramCounter::Next)
+    }
+
+    // I = the 16 bit address in the word following this opcode
+    fn op_f000(&mut self) -> Result<ProgramCounter, CpuError> {
+        self.check_address(self.pc + 3)?;
+        self.i = self.word_at(self.pc + 2) as usize;
+        Ok(ProgramCounter::Jump(self.pc + 4))
+    }
+
+    // Select the planes that drawing, clearing and scrolling work on
+    fn op_fn01(&mut self, n: usize) -> ProgramCounter {
+        self.planes = n as u8 & 0b11;
+        ProgramCounter::Next
+    }
+
+    // Load the 16 byte (128 bit) audio pattern starting at I
+    fn op_f002(&mut self) -> Result<ProgramCounter, CpuError> {
+        self.check_address(self.i + 15)?;
+        let mut pattern = [0; 16];
+        pattern.copy_from_slice(&self.memory[self.i..self.i + 16]);
+        self.audio_pattern = Some(pattern);
+        self.audio_updated = true;
+        Ok(ProgramCounter::Next)
+    }
+
+    // Set the audio pattern playback pitch = Vx
+    fn op_fx3a(&mut self, x: usize) -> ProgramCounter {
+        self.pitch = self.v[x];
+        self.audio_updated = true;
         ProgramCounter::Next
     }
 
@@ -459,10 +1002,35 @@ impl Cpu {
     }
 
     // Wait for a key press, store the value of the key in Vx.
+    // Like the VIP, this waits for a key to go down and come back up again,
+    // and the PC stays on this opcode until it does.  Timers keep running.
     fn op_fx0a(&mut self, x: usize) -> ProgramCounter {
-        self.input.read_keys = true; // Tell tick() to read a key
-        self.input.key_target = x; // And store it into V[x]
-        ProgramCounter::Next
+        if !self.input.read_keys {
+            // Start waiting; keys that are already held down do not count
+            self.input.read_keys = true;
+            self.input.key_target = x; // And store it into V[x]
+            self.input.key_pressed = None;
+            self.input.last_keys = self.input.keys;
+            return ProgramCounter::Jump(self.pc);
+        }
+
+        match self.input.key_pressed {
+            None => {
+                let keys = self.input.keys;
+                let last = self.input.last_keys;
+                self.input.key_pressed = (0..keys.len()).find(|&k| keys[k] && !last[k]);
+                self.input.last_keys = keys;
+                ProgramCounter::Jump(self.pc)
+            }
+            Some(key) if !self.input.keys[key] => {
+                // Released, we are done waiting
+                self.v[self.input.key_target] = key as u8;
+                self.input.read_keys = false;
+                self.input.key_pressed = None;
+                ProgramCounter::Next
+            }
+            Some(_) => ProgramCounter::Jump(self.pc),
+        }
     }
 
     // Set Dt = Vx
@@ -489,27 +1057,50 @@ impl Cpu {
         ProgramCounter::Next
     }
 
+    // I = location of the 10 byte big font sprite for digit Vx
+    fn op_fx30(&mut self, x: usize) -> ProgramCounter {
+        self.i = BIG_FONT_ADDR + (self.v[x] as usize & 0x0F) * 10;
+        ProgramCounter::Next
+    }
+
     // BCD representation of Vx in memory locations I, I+1, and I+2
-    fn op_fx33(&mut self, x: usize) -> ProgramCounter {
+    fn op_fx33(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
+        self.check_address(self.i + 2)?;
         self.memory[self.i] = self.get_digit(self.v[x], 3); // hundreds
         self.memory[self.i + 1] = self.get_digit(self.v[x], 2); // tens
         self.memory[self.i + 2] = self.get_digit(self.v[x], 1); // ones
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // Store registers V0 through Vx in memory starting at location I.
-    fn op_fx55(&mut self, x: usize) -> ProgramCounter {
+    fn op_fx55(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
+        self.check_address(self.i + x)?;
         for l in 0..x + 1 {
             self.memory[self.i + l] = self.v[l];
         }
-        ProgramCounter::Next
+        self.i += self.quirks.load_store.increment(x);
+        Ok(ProgramCounter::Next)
     }
 
     // Read registers V0 through Vx from memory starting at location I.
-    fn op_fx65(&mut self, x: usize) -> ProgramCounter {
+    fn op_fx65(&mut self, x: usize) -> Result<ProgramCounter, CpuError> {
+        self.check_address(self.i + x)?;
         for l in 0..x + 1 {
             self.v[l] = self.memory[self.i + l];
         }
+        self.i += self.quirks.load_store.increment(x);
+        Ok(ProgramCounter::Next)
+    }
+
+    // Store registers V0 through Vx in the RPL user flags
+    fn op_fx75(&mut self, x: usize) -> ProgramCounter {
+        self.rpl[..=x].copy_from_slice(&self.v[..=x]);
+        ProgramCounter::Next
+    }
+
+    // Read registers V0 through Vx from the RPL user flags
+    fn op_fx85(&mut self, x: usize) -> ProgramCounter {
+        self.v[..=x].copy_from_slice(&self.rpl[..=x]);
         ProgramCounter::Next
     }
 
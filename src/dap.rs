@@ -0,0 +1,664 @@
+use crate::analyzer::analyze;
+use crate::assembler::{assemble, Assembly};
+use crate::cpu::Cpu;
+use crate::debugger::{Debugger, StopReason};
+use crate::disassembler::{disassemble_analyzed, Disassembly};
+use crate::headless::{parse_address, parse_value};
+use crate::instruction::Instruction;
+use crate::json::Json;
+use crate::quirks::Platform;
+use crate::repl::set_register;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+// A Debug Adapter Protocol server, for `r8 dap`, so editors can debug roms.
+// Requests come in on one thread and are handled between frames on
+// another, so a running rom can still be paused.  The launch arguments are:
+//
+//   program      the rom, or an Octo source file to assemble
+//   source       the Octo source the rom was built from, for line breakpoints
+//   quirks, ipf, seed, memory    as for the emulator
+//   stopOnEntry  stop before the first instruction
+//
+// There is one thread, and the stack frames are the PC and the return
+// addresses on the stack.
+
+const THREAD: usize = 1;
+const REGISTERS: usize = 1; // variablesReference of the registers scope
+const STACK: usize = 2; // and of the stack scope
+
+// Reads one message, or None at the end of the input
+pub fn read_message<R: BufRead>(input: &mut R) -> Result<Option<Json>, String> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).map_err(|e| e.to_string())? == 0 {
+            return match length {
+                None => Ok(None),
+                Some(_) => Err("the input ended in a header".to_string()),
+            };
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| "a message without a Content-Length".to_string())?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).map_err(|e| e.to_string())?;
+    let body = String::from_utf8(body).map_err(|e| e.to_string())?;
+    Json::parse(&body).map(Some)
+}
+
+pub fn write_message<W: Write>(output: &mut W, message: &Json) -> Result<(), String> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+        .and_then(|_| output.flush())
+        .map_err(|e| e.to_string())
+}
+
+// Serves one session, until the client disconnects or the input ends
+pub fn serve_dap<R, W>(input: R, mut output: W) -> Result<(), String>
+where
+    R: Read + Send + 'static,
+    W: Write,
+{
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = BufReader::new(input);
+        loop {
+            let message = read_message(&mut input);
+            let last = !matches!(message, Ok(Some(_)));
+            if sender.send(message).is_err() || last {
+                break;
+            }
+        }
+    });
+
+    // Roughly 60 frames a second while running, and a nap while stopped
+    let frame = Duration::from_micros(16_667);
+    let mut session = Session::default();
+    while !session.done {
+        let message = if session.running {
+            match receiver.recv_timeout(frame) {
+                Ok(message) => Some(message),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        } else {
+            match receiver.recv() {
+                Ok(message) => Some(message),
+                Err(_) => break,
+            }
+        };
+        match message {
+            Some(Ok(Some(request))) => session.handle(&request),
+            Some(Ok(None)) => break,
+            Some(Err(err)) => return Err(err),
+            None => session.run_frame(),
+        }
+        for message in session.out.drain(..) {
+            write_message(&mut output, &message)?;
+        }
+    }
+    Ok(())
+}
+
+// The launched rom
+struct Target {
+    cpu: Cpu,
+    debugger: Debugger,
+    ipf: usize,
+    disassembly: Disassembly,
+    source: Option<(PathBuf, Assembly)>,
+    source_breakpoints: BTreeSet<usize>,
+    instruction_breakpoints: BTreeSet<usize>,
+}
+
+impl Target {
+    // The source line an address was assembled from
+    fn line(&self, address: usize) -> Option<usize> {
+        let (_, assembly) = self.source.as_ref()?;
+        assembly.lines.get(&address).copied()
+    }
+
+    fn source_json(&self) -> Json {
+        match &self.source {
+            Some((path, _)) => Json::object(vec![
+                (
+                    "name",
+                    path.file_name()
+                        .map(|name| name.to_string_lossy().to_string())
+                        .unwrap_or_default()
+                        .into(),
+                ),
+                ("path", path.to_string_lossy().to_string().into()),
+            ]),
+            None => Json::Null,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Session {
+    seq: usize,
+    out: Vec<Json>,    // Messages to send
+    events: Vec<Json>, // Events to send after the response being made
+    target: Option<Target>,
+    stop_on_entry: bool,
+    running: bool,
+    done: bool,
+}
+
+impl Session {
+    // Numbers a message and queues it to go
+    fn send(&mut self, mut message: Json) {
+        self.seq += 1;
+        if let Json::Object(members) = &mut message {
+            members.insert("seq".to_string(), self.seq.into());
+        }
+        self.out.push(message);
+    }
+
+    fn send_events(&mut self) {
+        for event in std::mem::take(&mut self.events) {
+            self.send(event);
+        }
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.events.push(Json::object(vec![
+            ("type", "event".into()),
+            ("event", event.into()),
+            ("body", body),
+        ]));
+    }
+
+    fn handle(&mut self, request: &Json) {
+        let command = request.get("command").as_str().unwrap_or("");
+        let args = request.get("arguments");
+        let result = match command {
+            "initialize" => self.initialize(),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(Json::object(vec![])),
+            "configurationDone" => self.configuration_done(),
+            "threads" => Ok(Json::object(vec![(
+                "threads",
+                vec![Json::object(vec![
+                    ("id", THREAD.into()),
+                    ("name", "CHIP-8".into()),
+                ])]
+                .into(),
+            )])),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(Json::object(vec![(
+                "scopes",
+                vec![scope("Registers", REGISTERS), scope("Stack", STACK)].into(),
+            )])),
+            "variables" => self.variables(args),
+            "setVariable" => self.set_variable(args),
+            "readMemory" => self.read_memory(args),
+            "disassemble" => self.disassemble(args),
+            "continue" => self.resume(None),
+            "next" => self.resume(Some("next")),
+            "stepIn" => self.resume(Some("stepIn")),
+            "stepOut" => self.resume(Some("stepOut")),
+            "pause" => self.pause(),
+            "disconnect" | "terminate" => {
+                self.done = true;
+                Ok(Json::object(vec![]))
+            }
+            _ => Err(format!("'{}' isn't supported", command)),
+        };
+
+        let mut response = vec![
+            ("type", "response".into()),
+            (
+                "request_seq",
+                request.get("seq").as_usize().unwrap_or(0).into(),
+            ),
+            ("command", command.into()),
+            ("success", result.is_ok().into()),
+        ];
+        match result {
+            Ok(body) => response.push(("body", body)),
+            Err(message) => response.push(("message", message.into())),
+        }
+        self.send(Json::object(response));
+        self.send_events();
+    }
+
+    fn target(&mut self) -> Result<&mut Target, String> {
+        self.target
+            .as_mut()
+            .ok_or_else(|| "no rom has been launched".to_string())
+    }
+
+    fn initialize(&mut self) -> Result<Json, String> {
+        self.event("initialized", Json::object(vec![]));
+        Ok(Json::object(vec![
+            ("supportsConfigurationDoneRequest", true.into()),
+            ("supportsInstructionBreakpoints", true.into()),
+            ("supportsReadMemoryRequest", true.into()),
+            ("supportsDisassembleRequest", true.into()),
+            ("supportsSetVariable", true.into()),
+        ]))
+    }
+
+    fn launch(&mut self, args: &Json) -> Result<Json, String> {
+        let program = PathBuf::from(
+            args.get("program")
+                .as_str()
+                .ok_or_else(|| "launch needs a program".to_string())?,
+        );
+        let mut source = match args.get("source").as_str() {
+            Some(path) => Some(assemble_file(Path::new(path))?),
+            None => None,
+        };
+        let rom = if program.extension().and_then(|e| e.to_str()) == Some("8o") {
+            let (path, assembly) = assemble_file(&program)?;
+            let rom = assembly.rom.clone();
+            source = Some((path, assembly));
+            rom
+        } else {
+            fs::read(&program).map_err(|e| format!("{}: {}", program.display(), e))?
+        };
+        if let Some((path, assembly)) = &source {
+            if assembly.rom != rom {
+                return Err(format!(
+                    "{} doesn't assemble to {}",
+                    path.display(),
+                    program.display()
+                ));
+            }
+        }
+
+        let platform: Platform = args.get("quirks").as_str().unwrap_or("default").parse()?;
+        let mut cpu = Cpu::with_quirks(platform.quirks());
+        cpu.set_memory_size(
+            args.get("memory")
+                .as_usize()
+                .unwrap_or_else(|| platform.memory_size()),
+        );
+        cpu.seed_rng(args.get("seed").as_i64().unwrap_or(0) as u64);
+        cpu.load_rom_bytes(&rom).map_err(|e| e.to_string())?;
+
+        // The source's own labels beat made up ones
+        let mut disassembly = disassemble_analyzed(&rom, 0x200, &analyze(&rom, 0x200));
+        if let Some((_, assembly)) = &source {
+            disassembly.labels.clear();
+            for (name, address) in &assembly.symbols {
+                disassembly
+                    .labels
+                    .entry(*address)
+                    .or_insert_with(|| name.clone());
+            }
+        }
+        self.stop_on_entry = args.get("stopOnEntry").as_bool().unwrap_or(false);
+        self.target = Some(Target {
+            cpu,
+            debugger: Debugger::new(),
+            ipf: args.get("ipf").as_usize().unwrap_or(11),
+            disassembly,
+            source,
+            source_breakpoints: BTreeSet::new(),
+            instruction_breakpoints: BTreeSet::new(),
+        });
+        Ok(Json::object(vec![]))
+    }
+
+    // Line breakpoints go on the first instruction at or after the line
+    fn set_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        let target = self.target()?;
+        let path = args.get("source").get("path").as_str().unwrap_or("");
+        let first_addresses: BTreeMap<usize, usize> = match &target.source {
+            Some((source, assembly)) if same_file(source, Path::new(path)) => {
+                let mut lines = BTreeMap::new();
+                for (address, line) in &assembly.lines {
+                    lines.entry(*line).or_insert(*address);
+                }
+                lines
+            }
+            _ => BTreeMap::new(),
+        };
+        target.source_breakpoints.clear();
+        let mut breakpoints = Vec::new();
+        for breakpoint in args.get("breakpoints").as_array().unwrap_or(&[]) {
+            let line = breakpoint.get("line").as_usize().unwrap_or(0);
+            breakpoints.push(match first_addresses.range(line..).next() {
+                Some((line, address)) => {
+                    target.source_breakpoints.insert(*address);
+                    Json::object(vec![
+                        ("verified", true.into()),
+                        ("line", (*line).into()),
+                        ("instructionReference", format!("{:#05X}", address).into()),
+                    ])
+                }
+                None => Json::object(vec![
+                    ("verified", false.into()),
+                    ("message", "no code at or after this line".into()),
+                ]),
+            });
+        }
+        update_breakpoints(target);
+        Ok(Json::object(vec![("breakpoints", breakpoints.into())]))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        let target = self.target()?;
+        target.instruction_breakpoints.clear();
+        let mut breakpoints = Vec::new();
+        for breakpoint in args.get("breakpoints").as_array().unwrap_or(&[]) {
+            let reference = breakpoint.get("instructionReference").as_str();
+            let offset = breakpoint.get("offset").as_i64().unwrap_or(0);
+            breakpoints.push(match reference.map(parse_address).and_then(Result::ok) {
+                Some(address) => {
+                    let address = (address as i64 + offset) as usize;
+                    target.instruction_breakpoints.insert(address);
+                    Json::object(vec![
+                        ("verified", true.into()),
+                        ("instructionReference", format!("{:#05X}", address).into()),
+                    ])
+                }
+                None => Json::object(vec![
+                    ("verified", false.into()),
+                    ("message", "not an address".into()),
+                ]),
+            });
+        }
+        update_breakpoints(target);
+        Ok(Json::object(vec![("breakpoints", breakpoints.into())]))
+    }
+
+    fn configuration_done(&mut self) -> Result<Json, String> {
+        let stop_on_entry = self.stop_on_entry;
+        let target = self.target()?;
+        if stop_on_entry {
+            target.debugger.pause(&target.cpu);
+            self.stopped_event("entry", "stopped on entry".to_string());
+        } else {
+            self.running = true;
+        }
+        Ok(Json::object(vec![]))
+    }
+
+    fn stack_trace(&mut self) -> Result<Json, String> {
+        let target = self.target()?;
+        let cpu = &target.cpu;
+
+        // The PC, then each caller at its call instruction
+        let mut addresses = vec![cpu.pc];
+        addresses.extend(cpu.stack[..cpu.sp].iter().rev().map(|ret| ret - 2));
+        let frames: Vec<Json> = addresses
+            .iter()
+            .enumerate()
+            .map(|(id, address)| {
+                Json::object(vec![
+                    ("id", id.into()),
+                    ("name", target.disassembly.locate(*address).into()),
+                    ("line", target.line(*address).unwrap_or(0).into()),
+                    ("column", 0.into()),
+                    ("source", target.source_json()),
+                    (
+                        "instructionPointerReference",
+                        format!("{:#05X}", address).into(),
+                    ),
+                ])
+            })
+            .collect();
+        Ok(Json::object(vec![
+            ("totalFrames", frames.len().into()),
+            ("stackFrames", frames.into()),
+        ]))
+    }
+
+    fn variables(&mut self, args: &Json) -> Result<Json, String> {
+        let cpu = &self.target()?.cpu;
+        let variable = |name: String, value: String| {
+            Json::object(vec![
+                ("name", name.into()),
+                ("value", value.into()),
+                ("variablesReference", 0.into()),
+            ])
+        };
+        let variables: Vec<Json> = match args.get("variablesReference").as_usize() {
+            Some(REGISTERS) => {
+                let mut variables = vec![
+                    variable("PC".to_string(), format!("{:#05X}", cpu.pc)),
+                    Json::object(vec![
+                        ("name", "I".into()),
+                        ("value", format!("{:#05X}", cpu.i).into()),
+                        ("variablesReference", 0.into()),
+                        ("memoryReference", format!("{:#05X}", cpu.i).into()),
+                    ]),
+                ];
+                for (x, value) in cpu.v.iter().enumerate() {
+                    variables.push(variable(format!("V{:X}", x), format!("{:#04X}", value)));
+                }
+                variables.push(variable("DT".to_string(), cpu.delay_timer.to_string()));
+                variables.push(variable("ST".to_string(), cpu.sound_timer.to_string()));
+                variables
+            }
+            Some(STACK) => cpu.stack[..cpu.sp]
+                .iter()
+                .enumerate()
+                .map(|(n, ret)| variable(format!("[{}]", n), format!("{:#05X}", ret)))
+                .collect(),
+            _ => return Err("no such variables".to_string()),
+        };
+        Ok(Json::object(vec![("variables", variables.into())]))
+    }
+
+    fn set_variable(&mut self, args: &Json) -> Result<Json, String> {
+        let cpu = &mut self.target()?.cpu;
+        if args.get("variablesReference").as_usize() != Some(REGISTERS) {
+            return Err("only registers can be set".to_string());
+        }
+        let name = args.get("name").as_str().unwrap_or("");
+        let value = parse_value(args.get("value").as_str().unwrap_or(""))?;
+        set_register(cpu, name, value)?;
+        Ok(Json::object(vec![(
+            "value",
+            format!("{:#X}", value).into(),
+        )]))
+    }
+
+    fn read_memory(&mut self, args: &Json) -> Result<Json, String> {
+        let cpu = &self.target()?.cpu;
+        let start = memory_reference(args)?;
+        let count = args.get("count").as_usize().unwrap_or(0);
+        let end = (start + count).min(cpu.memory.len()).max(start);
+        let bytes = cpu.memory.get(start..end).unwrap_or(&[]);
+        Ok(Json::object(vec![
+            ("address", format!("{:#05X}", start).into()),
+            ("data", base64(bytes).into()),
+            ("unreadableBytes", (count - bytes.len()).into()),
+        ]))
+    }
+
+    fn disassemble(&mut self, args: &Json) -> Result<Json, String> {
+        let target = self.target()?;
+        let start = memory_reference(args)? as i64;
+        let skip = args.get("instructionOffset").as_i64().unwrap_or(0);
+        let count = args.get("instructionCount").as_usize().unwrap_or(0);
+
+        // Instructions are mostly two bytes, which will do for going back
+        let mut address = start + skip.min(0) * 2;
+        let mut instructions = Vec::new();
+        for n in 0..count as i64 + skip.max(0) {
+            let here = address;
+            address += match Instruction::decode(target.cpu.word_at(here.max(0) as usize)) {
+                Some(instruction) => instruction.size() as i64,
+                None => 2,
+            };
+            if n < skip.max(0) {
+                continue;
+            }
+            let text = match usize::try_from(here) {
+                Ok(here) if here < target.cpu.memory.len() => {
+                    target.disassembly.describe(&target.cpu.memory, here)
+                }
+                _ => "???".to_string(),
+            };
+            let mut instruction = vec![
+                ("address", format!("{:#05X}", here).into()),
+                ("instruction", text.into()),
+            ];
+            if let Some(line) = usize::try_from(here).ok().and_then(|a| target.line(a)) {
+                instruction.push(("line", line.into()));
+                instruction.push(("location", target.source_json()));
+            }
+            instructions.push(Json::object(instruction));
+        }
+        Ok(Json::object(vec![("instructions", instructions.into())]))
+    }
+
+    fn resume(&mut self, how: Option<&str>) -> Result<Json, String> {
+        let target = self.target()?;
+        if target.cpu.exited {
+            return Err("the rom has exited".to_string());
+        }
+        let cpu = &target.cpu;
+        let run = match how {
+            None => true,
+            Some("next") => target.debugger.step_over(cpu),
+            Some("stepOut") => target.debugger.step_out(cpu),
+            _ => false,
+        };
+        if run {
+            target.debugger.resume();
+            self.running = true;
+        } else {
+            let stop = target
+                .debugger
+                .step(&mut target.cpu)
+                .unwrap_or_else(StopReason::Fault);
+            self.stopped(stop);
+        }
+        Ok(Json::object(vec![("allThreadsContinued", true.into())]))
+    }
+
+    fn pause(&mut self) -> Result<Json, String> {
+        let target = self.target()?;
+        target.debugger.pause(&target.cpu);
+        self.stopped(StopReason::Paused);
+        Ok(Json::object(vec![]))
+    }
+
+    fn run_frame(&mut self) {
+        let target = match self.target.as_mut() {
+            Some(target) => target,
+            None => return,
+        };
+        match target.debugger.run_frame(&mut target.cpu, target.ipf) {
+            Ok(None) => (),
+            Ok(Some(stop)) => self.stopped(stop),
+            Err(err) => self.stopped(StopReason::Fault(err)),
+        }
+        self.send_events();
+    }
+
+    fn stopped(&mut self, stop: StopReason) {
+        self.running = false;
+        let instruction = match self.target.as_ref() {
+            Some(target) => target.instruction_breakpoints.contains(&target.cpu.pc),
+            None => false,
+        };
+        let reason = match stop {
+            StopReason::Exited => {
+                self.event("exited", Json::object(vec![("exitCode", 0.into())]));
+                self.event("terminated", Json::object(vec![]));
+                return;
+            }
+            StopReason::Paused => "pause",
+            StopReason::Breakpoint(_) if instruction => "instruction breakpoint",
+            StopReason::Breakpoint(_) => "breakpoint",
+            StopReason::Read { .. } | StopReason::Write { .. } | StopReason::Register { .. } => {
+                "data breakpoint"
+            }
+            StopReason::Fault(_) => "exception",
+            _ => "step",
+        };
+        self.stopped_event(reason, stop.to_string());
+    }
+
+    fn stopped_event(&mut self, reason: &str, description: String) {
+        self.event(
+            "stopped",
+            Json::object(vec![
+                ("reason", reason.into()),
+                ("description", description.into()),
+                ("threadId", THREAD.into()),
+                ("allThreadsStopped", true.into()),
+            ]),
+        );
+    }
+}
+
+fn scope(name: &str, reference: usize) -> Json {
+    Json::object(vec![
+        ("name", name.into()),
+        ("variablesReference", reference.into()),
+        ("expensive", false.into()),
+    ])
+}
+
+fn update_breakpoints(target: &mut Target) {
+    target.debugger.breakpoints = target
+        .source_breakpoints
+        .union(&target.instruction_breakpoints)
+        .copied()
+        .collect();
+}
+
+fn assemble_file(path: &Path) -> Result<(PathBuf, Assembly), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let assembly = assemble(&text).map_err(|e| format!("{}:{}", path.display(), e))?;
+    Ok((path.to_path_buf(), assembly))
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+// The memoryReference and offset of a readMemory or disassemble request
+fn memory_reference(args: &Json) -> Result<usize, String> {
+    let reference = args.get("memoryReference").as_str().unwrap_or("");
+    let address = parse_address(reference)? as i64 + args.get("offset").as_i64().unwrap_or(0);
+    usize::try_from(address).map_err(|_| format!("{} is before the start of memory", address))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const DIGITS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, byte)| n | (u32::from(*byte) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(DIGITS[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,431 @@
+use crate::cpu::{Cpu, CpuError};
+use crate::headless::parse_address;
+use crate::instruction::Instruction;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+// Breakpoints, watchpoints and the stepping that goes further than one
+// instruction.  The debugger looks at the cpu between instructions: before()
+// notes what the next one is about to touch, and after() checks what it did
+// and whether to stop there, which makes it a good fit for the stop check
+// in Cpu::run_frame_until().
+
+// Something to stop on when it is touched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watchpoint {
+    Read(usize, usize),  // Memory reads from the first address to the last
+    Write(usize, usize), // Memory writes, likewise
+    V(usize),            // Vx changing
+    I,                   // I changing
+}
+
+// Written the way they are parsed: "r:300", "w:300-30F", "v3" or "i"
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let range = |f: &mut fmt::Formatter, kind, start, end| {
+            if start == end {
+                write!(f, "{}:{:03X}", kind, start)
+            } else {
+                write!(f, "{}:{:03X}-{:03X}", kind, start, end)
+            }
+        };
+        match *self {
+            Watchpoint::Read(start, end) => range(f, "r", start, end),
+            Watchpoint::Write(start, end) => range(f, "w", start, end),
+            Watchpoint::V(x) => write!(f, "v{:X}", x),
+            Watchpoint::I => write!(f, "i"),
+        }
+    }
+}
+
+impl FromStr for Watchpoint {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Watchpoint, String> {
+        let bad = || {
+            format!(
+                "'{}' is not a watchpoint, try r:ADDR, w:ADDR, w:ADDR-ADDR, v0 - vF or i",
+                s
+            )
+        };
+        let lower = s.to_ascii_lowercase();
+        if lower == "i" {
+            return Ok(Watchpoint::I);
+        }
+        if let Some(x) = lower.strip_prefix('v') {
+            return match (x.len(), usize::from_str_radix(x, 16)) {
+                (1, Ok(x)) => Ok(Watchpoint::V(x)),
+                _ => Err(bad()),
+            };
+        }
+        let (kind, range) = lower.split_at(lower.find(':').ok_or_else(bad)?);
+        let range = &range[1..];
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+            None => (parse_address(range)?, parse_address(range)?),
+        };
+        if end < start {
+            return Err(bad());
+        }
+        match kind {
+            "r" => Ok(Watchpoint::Read(start, end)),
+            "w" => Ok(Watchpoint::Write(start, end)),
+            _ => Err(bad()),
+        }
+    }
+}
+
+// Why the debugger stopped the cpu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Paused,
+    Step,
+    Breakpoint(usize),
+    Read {
+        pc: usize,
+        address: usize,
+    },
+    Write {
+        pc: usize,
+        address: usize,
+        old: u8,
+        new: u8,
+    },
+    Register {
+        pc: usize,
+        watch: Watchpoint,
+        old: usize,
+        new: usize,
+    },
+    StepOver(usize),
+    StepOut(usize),
+    Cursor(usize),
+    Exited,
+    Fault(CpuError),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StopReason::Paused => write!(f, "paused"),
+            StopReason::Step => write!(f, "stepped"),
+            StopReason::Breakpoint(pc) => write!(f, "breakpoint at {:#05X}", pc),
+            StopReason::Read { pc, address } => {
+                write!(f, "read of {:#05X} by {:#05X}", address, pc)
+            }
+            StopReason::Write {
+                pc,
+                address,
+                old,
+                new,
+            } => write!(
+                f,
+                "write of {:#05X} by {:#05X}: {:#04X} -> {:#04X}",
+                address, pc, old, new
+            ),
+            StopReason::Register {
+                pc,
+                watch,
+                old,
+                new,
+            } => write!(
+                f,
+                "{} changed by {:#05X}: {:#04X} -> {:#04X}",
+                watch.to_string().to_ascii_uppercase(),
+                pc,
+                old,
+                new
+            ),
+            StopReason::StepOver(pc) => write!(f, "stepped over to {:#05X}", pc),
+            StopReason::StepOut(pc) => write!(f, "stepped out to {:#05X}", pc),
+            StopReason::Cursor(pc) => write!(f, "ran to {:#05X}", pc),
+            StopReason::Exited => write!(f, "the rom exited"),
+            StopReason::Fault(err) => write!(f, "fault: {}", err),
+        }
+    }
+}
+
+// Where a step over, step out or run to cursor is headed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Goal {
+    Over { pc: usize, sp: usize }, // Back from the call at this depth
+    Out { sp: usize },             // Returned from this depth
+    Cursor(usize),
+}
+
+// The state of the cpu before an instruction, to compare with after it
+#[derive(Debug, Clone)]
+struct Before {
+    pc: usize,
+    v: [u8; 16],
+    i: usize,
+    exited: bool,
+    reads: Option<(usize, usize)>,
+    writes: Option<(usize, usize)>,
+    old: Vec<u8>, // The bytes about to be written
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+    pub breakpoints: BTreeSet<usize>,
+    pub watchpoints: Vec<Watchpoint>,
+    pub stop: Option<StopReason>, // Why it last stopped, until it runs again
+    goal: Option<Goal>,
+    before: Option<Before>,
+    left_off: Option<usize>, // The PC as of the last instruction it saw run
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger::default()
+    }
+
+    // Adds a breakpoint, or takes it away if it's there.  True if added.
+    pub fn toggle_breakpoint(&mut self, address: usize) -> bool {
+        if self.breakpoints.remove(&address) {
+            false
+        } else {
+            self.breakpoints.insert(address);
+            true
+        }
+    }
+
+    pub fn toggle_watchpoint(&mut self, watch: Watchpoint) -> bool {
+        match self.watchpoints.iter().position(|w| *w == watch) {
+            Some(index) => {
+                self.watchpoints.remove(index);
+                false
+            }
+            None => {
+                self.watchpoints.push(watch);
+                true
+            }
+        }
+    }
+
+    // Stops on the instruction after this one, running the whole of a
+    // call.  False if the next instruction isn't a call, in which case a
+    // single step does the job.
+    pub fn step_over(&mut self, cpu: &Cpu) -> bool {
+        match Instruction::decode(cpu.word_at(cpu.pc)) {
+            Some(Instruction::Call(_)) => {
+                self.goal = Some(Goal::Over {
+                    pc: cpu.pc + 2,
+                    sp: cpu.sp,
+                });
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Stops once the current subroutine returns.  False at the top level.
+    pub fn step_out(&mut self, cpu: &Cpu) -> bool {
+        if cpu.sp == 0 {
+            return false;
+        }
+        self.goal = Some(Goal::Out { sp: cpu.sp });
+        true
+    }
+
+    pub fn run_to(&mut self, address: usize) {
+        self.goal = Some(Goal::Cursor(address));
+    }
+
+    // Drops any step over, step out or run to cursor in progress
+    pub fn cancel(&mut self) {
+        self.goal = None;
+    }
+
+    // Stops from outside a run, as pausing or stopping on entry does.
+    // Carrying on won't then stop on a breakpoint at the PC straight away.
+    pub fn pause(&mut self, cpu: &Cpu) {
+        self.goal = None;
+        self.stop = Some(StopReason::Paused);
+        self.left_off = Some(cpu.pc);
+    }
+
+    pub fn resume(&mut self) {
+        self.stop = None;
+    }
+
+    // Notes what the instruction at the PC is about to touch
+    pub fn before(&mut self, cpu: &Cpu) {
+        let (reads, writes) = accesses(cpu);
+        let old = match writes {
+            Some((start, end)) => cpu
+                .memory
+                .get(start..=end.min(cpu.memory.len().saturating_sub(1)))
+                .unwrap_or(&[])
+                .to_vec(),
+            None => Vec::new(),
+        };
+        self.before = Some(Before {
+            pc: cpu.pc,
+            v: cpu.v,
+            i: cpu.i,
+            exited: cpu.exited,
+            reads,
+            writes,
+            old,
+        });
+    }
+
+    // Checks what the last instruction did, and whether to stop before the
+    // next one.  True if stopped, with the reason in `stop`.
+    pub fn after(&mut self, cpu: &Cpu) -> bool {
+        let stop = self.check(cpu);
+        if stop.is_some() {
+            self.stop = stop;
+            self.goal = None;
+        }
+        self.before(cpu);
+        self.left_off = Some(cpu.pc);
+        stop.is_some()
+    }
+
+    // Checks for a breakpoint before the first instruction of a run, which
+    // after() won't have seen.  The address it left off at doesn't count,
+    // or it could never carry on from a breakpoint.  True if stopped.
+    pub fn start(&mut self, cpu: &Cpu) -> bool {
+        if self.left_off == Some(cpu.pc) || !self.breakpoints.contains(&cpu.pc) {
+            return false;
+        }
+        self.stop = Some(StopReason::Breakpoint(cpu.pc));
+        self.goal = None;
+        self.left_off = Some(cpu.pc);
+        true
+    }
+
+    fn check(&mut self, cpu: &Cpu) -> Option<StopReason> {
+        if let Some(before) = &self.before {
+            if cpu.exited && !before.exited {
+                return Some(StopReason::Exited);
+            }
+            for watch in &self.watchpoints {
+                if let Some(stop) = watch_hit(*watch, before, cpu) {
+                    return Some(stop);
+                }
+            }
+        }
+        match self.goal {
+            Some(Goal::Over { pc, sp }) if cpu.pc == pc && cpu.sp == sp => {
+                return Some(StopReason::StepOver(pc))
+            }
+            Some(Goal::Out { sp }) if cpu.sp < sp => return Some(StopReason::StepOut(cpu.pc)),
+            Some(Goal::Cursor(pc)) if cpu.pc == pc => return Some(StopReason::Cursor(pc)),
+            _ => (),
+        }
+        if self.breakpoints.contains(&cpu.pc) {
+            return Some(StopReason::Breakpoint(cpu.pc));
+        }
+        None
+    }
+
+    // Runs one instruction, stopping with the reason it would have stopped
+    // for, or just Step
+    pub fn step(&mut self, cpu: &mut Cpu) -> Result<StopReason, CpuError> {
+        self.before(cpu);
+        let result = cpu.tick(false);
+        self.stop = match result {
+            Err(err) => Some(StopReason::Fault(err)),
+            Ok(_) => self.check(cpu).or(Some(StopReason::Step)),
+        };
+        self.goal = None;
+        self.left_off = Some(cpu.pc);
+        result.map(|_| self.stop.unwrap_or(StopReason::Step))
+    }
+
+    // Cpu::run_frame(), stopping wherever the debugger says to.  Returns
+    // why it stopped, or None if it ran the whole frame.
+    pub fn run_frame(
+        &mut self,
+        cpu: &mut Cpu,
+        cycles: usize,
+    ) -> Result<Option<StopReason>, CpuError> {
+        self.resume();
+        if self.start(cpu) {
+            return Ok(self.stop);
+        }
+        self.before(cpu);
+        match cpu.run_frame_until(cycles, |cpu| self.after(cpu)) {
+            Ok(true) => Ok(self.stop),
+            Ok(false) => Ok(None),
+            Err(err) => {
+                self.stop = Some(StopReason::Fault(err));
+                self.goal = None;
+                Err(err)
+            }
+        }
+    }
+}
+
+fn overlaps((start, end): (usize, usize), (from, to): (usize, usize)) -> Option<usize> {
+    if start <= to && from <= end {
+        Some(start.max(from))
+    } else {
+        None
+    }
+}
+
+fn watch_hit(watch: Watchpoint, before: &Before, cpu: &Cpu) -> Option<StopReason> {
+    let pc = before.pc;
+    // A sprite held back by the display wait quirk hasn't been drawn yet
+    let ran = cpu.pc != before.pc;
+    match watch {
+        Watchpoint::Read(from, to) if ran => {
+            let address = overlaps(before.reads?, (from, to))?;
+            Some(StopReason::Read { pc, address })
+        }
+        Watchpoint::Write(from, to) if ran => {
+            let (start, _) = before.writes?;
+            let address = overlaps(before.writes?, (from, to))?;
+            Some(StopReason::Write {
+                pc,
+                address,
+                old: *before.old.get(address - start)?,
+                new: *cpu.memory.get(address)?,
+            })
+        }
+        Watchpoint::V(x) if before.v[x] != cpu.v[x] => Some(StopReason::Register {
+            pc,
+            watch,
+            old: before.v[x].into(),
+            new: cpu.v[x].into(),
+        }),
+        Watchpoint::I if before.i != cpu.i => Some(StopReason::Register {
+            pc,
+            watch,
+            old: before.i,
+            new: cpu.i,
+        }),
+        _ => None,
+    }
+}
+
+// The memory the instruction at the PC reads and writes through I, as
+// inclusive ranges
+#[allow(clippy::type_complexity)]
+fn accesses(cpu: &Cpu) -> (Option<(usize, usize)>, Option<(usize, usize)>) {
+    let span = |len: usize| match len {
+        0 => None,
+        _ => Some((cpu.i, cpu.i + len - 1)),
+    };
+    let instruction = match Instruction::decode(cpu.word_at(cpu.pc)) {
+        Some(instruction) => instruction,
+        None => return (None, None),
+    };
+    match instruction {
+        Instruction::Drw(_, _, n) => {
+            let bytes = if n == 0 { 32 } else { n };
+            (span(bytes * cpu.planes.count_ones() as usize), None)
+        }
+        Instruction::LdVxI(x) => (span(x + 1), None),
+        Instruction::LoadRange(x, y) => (span(x.abs_diff(y) + 1), None),
+        Instruction::Audio => (span(16), None),
+        Instruction::LdIVx(x) => (None, span(x + 1)),
+        Instruction::SaveRange(x, y) => (None, span(x.abs_diff(y) + 1)),
+        Instruction::LdBVx(_) => (None, span(3)),
+        _ => (None, None),
+    }
+}
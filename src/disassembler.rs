@@ -0,0 +1,429 @@
+use crate::analyzer::{Analysis, ByteClass};
+use crate::instruction::Instruction;
+use std::collections::BTreeMap;
+
+// Turns a rom, or any other stretch of memory, back into instructions.  The
+// bytes are swept in order from the start, with labels made up for the
+// targets of jumps (1nnn), calls (2nnn) and jump tables (Bnnn).  Anything
+// I is pointed at (Annn, F000 nnnn) that isn't also jumped to is taken to
+// be sprite data, running up to the next jump target, and is shown as
+// bytes rather than decoded.  Given an Analysis, what it found to be code
+// or data is taken as read, and the guessing is left to the rest.
+
+// What a stretch of bytes was taken to be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Item {
+    Code(Instruction),
+    Sprite, // One byte, pointed at by I
+    Data,   // Bytes that don't decode, or would run into a label
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+    pub item: Item,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Target {
+    Sprite,
+    Table,
+    Jump,
+    Call,
+}
+
+impl Target {
+    fn prefix(self) -> &'static str {
+        match self {
+            Target::Sprite => "sprite",
+            Target::Table => "table",
+            Target::Jump => "label",
+            Target::Call => "sub",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Disassembly {
+    pub lines: Vec<Line>,
+    pub labels: BTreeMap<usize, String>, // Address -> made up label
+    pub notes: BTreeMap<usize, String>,  // Address -> remarks from the analysis
+}
+
+pub fn disassemble(bytes: &[u8], origin: usize) -> Disassembly {
+    disassemble_with(bytes, origin, None)
+}
+
+pub fn disassemble_analyzed(bytes: &[u8], origin: usize, analysis: &Analysis) -> Disassembly {
+    disassemble_with(bytes, origin, Some(analysis))
+}
+
+fn disassemble_with(bytes: &[u8], origin: usize, analysis: Option<&Analysis>) -> Disassembly {
+    // Bytes taken for sprites decode as nonsense that points all over the
+    // place, so sweep again without them until the guesses settle down.
+    let mut targets = BTreeMap::new();
+    let mut lines = sweep(bytes, origin, &targets, analysis);
+    for _ in 0..8 {
+        let found = find_targets(&lines, origin..origin + bytes.len(), analysis);
+        if found == targets {
+            break;
+        }
+        targets = found;
+        lines = sweep(bytes, origin, &targets, analysis);
+    }
+
+    let labels = targets
+        .iter()
+        .map(|(address, target)| (*address, format!("{}_{:03X}", target.prefix(), address)))
+        .collect();
+    Disassembly {
+        lines,
+        labels,
+        notes: analysis.map_or_else(BTreeMap::new, Analysis::annotations),
+    }
+}
+
+fn class_at(analysis: Option<&Analysis>, address: usize) -> ByteClass {
+    analysis.map_or(ByteClass::Unknown, |analysis| analysis.class_at(address))
+}
+
+// Where the code in these lines jumps, calls and points I, leaving out the
+// guesses where the analysis knows better
+fn find_targets(
+    lines: &[Line],
+    range: std::ops::Range<usize>,
+    analysis: Option<&Analysis>,
+) -> BTreeMap<usize, Target> {
+    let mut targets = BTreeMap::new();
+    for line in lines {
+        if analysis.is_some() && class_at(analysis, line.address) != ByteClass::Code {
+            continue;
+        }
+        let (address, target) = match line.item {
+            Item::Code(Instruction::Jp(a)) => (a, Target::Jump),
+            Item::Code(Instruction::Call(a)) => (a, Target::Call),
+            Item::Code(Instruction::JpV0(a)) => (a, Target::Table),
+            Item::Code(Instruction::LdI(a)) => (a, Target::Sprite),
+            Item::Code(Instruction::LdILong) => (long_address(&line.bytes), Target::Sprite),
+            _ => continue,
+        };
+        // I pointed at code is most likely about to patch it
+        let target = match target {
+            Target::Sprite if class_at(analysis, address) == ByteClass::Code => Target::Jump,
+            _ => target,
+        };
+        if range.contains(&address) {
+            let best = targets.entry(address).or_insert(target);
+            *best = target.max(*best);
+        }
+    }
+    targets
+}
+
+fn long_address(bytes: &[u8]) -> usize {
+    usize::from(bytes[2]) << 8 | usize::from(bytes[3])
+}
+
+fn sweep(
+    bytes: &[u8],
+    origin: usize,
+    targets: &BTreeMap<usize, Target>,
+    analysis: Option<&Analysis>,
+) -> Vec<Line> {
+    let end = origin + bytes.len();
+    let byte_at = |address: usize| bytes[address - origin];
+    let mut lines = Vec::new();
+    let mut in_sprite = false;
+    let mut address = origin;
+    while address < end {
+        match targets.get(&address) {
+            Some(Target::Sprite) => in_sprite = true,
+            Some(_) => in_sprite = false,
+            None => (),
+        }
+        let class = class_at(analysis, address);
+        let item = match class {
+            ByteClass::Sprite => Some(Item::Sprite),
+            ByteClass::Data => Some(Item::Data),
+            ByteClass::Code => {
+                in_sprite = false;
+                None
+            }
+            ByteClass::Unknown if in_sprite => Some(Item::Sprite),
+            ByteClass::Unknown => None,
+        };
+        if let Some(item) = item {
+            lines.push(Line {
+                address,
+                bytes: vec![byte_at(address)],
+                item,
+            });
+            address += 1;
+            continue;
+        }
+
+        // Code can't run over the end, into the next label, or into bytes
+        // the analysis says are something else
+        let next_label = targets
+            .range(address + 1..)
+            .next()
+            .map_or(end, |(label, _)| *label);
+        let next_class = (address + 1..next_label.min(end))
+            .find(|next| class_at(analysis, *next) != class)
+            .unwrap_or(end);
+        let room = next_label.min(next_class).min(end) - address;
+        let instruction = if room >= 2 {
+            Instruction::decode(u16::from_be_bytes([byte_at(address), byte_at(address + 1)]))
+                .filter(|instruction| instruction.size() <= room)
+        } else {
+            None
+        };
+        let line = match instruction {
+            Some(instruction) => Line {
+                address,
+                bytes: bytes[address - origin..address - origin + instruction.size()].to_vec(),
+                item: Item::Code(instruction),
+            },
+            None => Line {
+                address,
+                bytes: bytes[address - origin..address - origin + room.min(2)].to_vec(),
+                item: Item::Data,
+            },
+        };
+        address += line.bytes.len();
+        lines.push(line);
+    }
+    lines
+}
+
+impl Disassembly {
+    // A listing with addresses, raw bytes and Cowgod mnemonics, and sprites
+    // drawn out a row at a time
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for line in &self.lines {
+            if let Some(label) = self.labels.get(&line.address) {
+                out += &format!("{}:\n", label);
+            }
+            let hex: String = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let text = match line.item {
+                Item::Code(instruction) => self.mnemonic(instruction, &line.bytes),
+                Item::Sprite => (0..8)
+                    .map(|bit| {
+                        if line.bytes[0] & (0x80 >> bit) != 0 {
+                            '#'
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect(),
+                Item::Data => {
+                    let bytes: Vec<String> =
+                        line.bytes.iter().map(|b| format!("{:#04X}", b)).collect();
+                    format!("DB {}", bytes.join(", "))
+                }
+            };
+            let line_text = format!("  {:#05X}  {:<8}  {}", line.address, hex, text);
+            match self.notes.get(&line.address) {
+                Some(note) => out += &format!("{:<40}; {}\n", line_text, note),
+                None => out += &format!("{}\n", line_text),
+            }
+        }
+        out
+    }
+
+    // Octo source that assembles back into the same bytes
+    pub fn to_octo(&self) -> String {
+        let mut statements: Vec<Option<String>> = self
+            .lines
+            .iter()
+            .map(|line| match line.item {
+                Item::Code(instruction) => {
+                    let statement = octo(instruction, &line.bytes, &self.labels)?;
+                    Some(match self.notes.get(&line.address) {
+                        Some(note) => format!("{}  # {}", statement, note),
+                        None => statement,
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        // A skip has to be followed by the one plain instruction it skips,
+        // so fall back to bytes when that isn't so
+        for n in 0..self.lines.len() {
+            let skips_one = match (self.lines.get(n + 1), statements.get(n + 1)) {
+                (Some(next), Some(Some(_))) => match next.item {
+                    Item::Code(i) => {
+                        i.size() == 2 && !is_skip(i) && !self.labels.contains_key(&next.address)
+                    }
+                    _ => false,
+                },
+                _ => false,
+            };
+            if matches!(self.lines[n].item, Item::Code(i) if is_skip(i)) && !skips_one {
+                statements[n] = None;
+            }
+        }
+
+        let mut out = String::new();
+        let mut data: Vec<String> = Vec::new();
+        for (line, statement) in self.lines.iter().zip(statements) {
+            let label = self.labels.get(&line.address);
+            if !data.is_empty() && (label.is_some() || statement.is_some() || data.len() == 8) {
+                out += &format!("  {}\n", data.join(" "));
+                data.clear();
+            }
+            if let Some(label) = label {
+                out += &format!(": {}\n", label);
+            }
+            match statement {
+                // The skipped instruction goes on the same line as its `if`
+                Some(statement) if statement.ends_with(" then") => {
+                    out += &format!("  {} ", statement)
+                }
+                Some(statement) if out.ends_with(" then ") => out += &format!("{}\n", statement),
+                Some(statement) => out += &format!("  {}\n", statement),
+                None => data.extend(line.bytes.iter().map(|b| format!("{:#04X}", b))),
+            }
+        }
+        if !data.is_empty() {
+            out += &format!("  {}\n", data.join(" "));
+        }
+        out
+    }
+
+    // The nearest label at or before an address, "main+6", for naming
+    // stack frames and return addresses
+    pub fn locate(&self, address: usize) -> String {
+        match self.labels.range(..=address).next_back() {
+            Some((start, label)) if *start == address => label.to_string(),
+            Some((start, label)) => format!("{}+{}", label, address - start),
+            None => format!("{:#05X}", address),
+        }
+    }
+
+    // The instruction at an address in live memory, which may have changed
+    // since the disassembly, named with this disassembly's labels
+    pub fn describe(&self, memory: &[u8], address: usize) -> String {
+        let bytes = match memory.get(address..address + 4) {
+            Some(bytes) => bytes,
+            None => memory.get(address..).unwrap_or(&[]),
+        };
+        let label = match self.labels.get(&address) {
+            Some(label) => format!("{}: ", label),
+            None => String::new(),
+        };
+        let text = match bytes {
+            [high, low, ..] => match Instruction::decode(u16::from_be_bytes([*high, *low])) {
+                Some(instruction) if instruction.size() <= bytes.len() => {
+                    self.mnemonic(instruction, bytes)
+                }
+                _ => format!("DB {:#04X}, {:#04X}", high, low),
+            },
+            _ => "???".to_string(),
+        };
+        format!("{}{}", label, text)
+    }
+
+    // The Cowgod mnemonic with addresses swapped for labels where there are
+    // any, and the address of a long load filled in
+    fn mnemonic(&self, instruction: Instruction, bytes: &[u8]) -> String {
+        let name = |address: usize| match self.labels.get(&address) {
+            Some(label) => label.clone(),
+            None => format!("{:#05X}", address),
+        };
+        match instruction {
+            Instruction::Jp(a) => format!("JP {}", name(a)),
+            Instruction::Call(a) => format!("CALL {}", name(a)),
+            Instruction::LdI(a) => format!("LD I, {}", name(a)),
+            Instruction::JpV0(a) => format!("JP V0, {}", name(a)),
+            Instruction::LdILong => format!("LD I, LONG {}", name(long_address(bytes))),
+            _ => instruction.to_string(),
+        }
+    }
+}
+
+fn is_skip(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::SeByte(..)
+            | Instruction::SneByte(..)
+            | Instruction::SeReg(..)
+            | Instruction::SneReg(..)
+            | Instruction::Skp(_)
+            | Instruction::Sknp(_)
+    )
+}
+
+// The Octo statement for an instruction, or None for the few that Octo
+// can't write other than as bytes
+fn octo(
+    instruction: Instruction,
+    bytes: &[u8],
+    labels: &BTreeMap<usize, String>,
+) -> Option<String> {
+    let name = |address: usize| match labels.get(&address) {
+        Some(label) => label.clone(),
+        None => format!("{:#05X}", address),
+    };
+    Some(match instruction {
+        Instruction::Sys(_) => return None,
+        Instruction::ScrollDown(n) => format!("scroll-down {}", n),
+        Instruction::ScrollUp(n) => format!("scroll-up {}", n),
+        Instruction::Cls => "clear".to_string(),
+        Instruction::Ret => "return".to_string(),
+        Instruction::ScrollRight => "scroll-right".to_string(),
+        Instruction::ScrollLeft => "scroll-left".to_string(),
+        Instruction::Exit => "exit".to_string(),
+        Instruction::Low => "lores".to_string(),
+        Instruction::High => "hires".to_string(),
+        Instruction::Jp(a) => format!("jump {}", name(a)),
+        Instruction::Call(a) => match labels.get(&a) {
+            Some(label) => label.clone(),
+            None => return None,
+        },
+        // Skips become `if` on the opposite condition
+        Instruction::SeByte(x, kk) => format!("if v{:x} != {:#04X} then", x, kk),
+        Instruction::SneByte(x, kk) => format!("if v{:x} == {:#04X} then", x, kk),
+        Instruction::SeReg(x, y) => format!("if v{:x} != v{:x} then", x, y),
+        Instruction::SneReg(x, y) => format!("if v{:x} == v{:x} then", x, y),
+        Instruction::Skp(x) => format!("if v{:x} -key then", x),
+        Instruction::Sknp(x) => format!("if v{:x} key then", x),
+        Instruction::SaveRange(x, y) => format!("save v{:x} - v{:x}", x, y),
+        Instruction::LoadRange(x, y) => format!("load v{:x} - v{:x}", x, y),
+        Instruction::LdByte(x, kk) => format!("v{:x} := {:#04X}", x, kk),
+        Instruction::AddByte(x, kk) => format!("v{:x} += {:#04X}", x, kk),
+        Instruction::LdReg(x, y) => format!("v{:x} := v{:x}", x, y),
+        Instruction::Or(x, y) => format!("v{:x} |= v{:x}", x, y),
+        Instruction::And(x, y) => format!("v{:x} &= v{:x}", x, y),
+        Instruction::Xor(x, y) => format!("v{:x} ^= v{:x}", x, y),
+        Instruction::AddReg(x, y) => format!("v{:x} += v{:x}", x, y),
+        Instruction::Sub(x, y) => format!("v{:x} -= v{:x}", x, y),
+        Instruction::Shr(x, y) => format!("v{:x} >>= v{:x}", x, y),
+        Instruction::Subn(x, y) => format!("v{:x} =- v{:x}", x, y),
+        Instruction::Shl(x, y) => format!("v{:x} <<= v{:x}", x, y),
+        Instruction::LdI(a) => format!("i := {}", name(a)),
+        Instruction::JpV0(a) => format!("jump0 {}", name(a)),
+        Instruction::Rnd(x, kk) => format!("v{:x} := random {:#04X}", x, kk),
+        Instruction::Drw(x, y, n) => format!("sprite v{:x} v{:x} {}", x, y, n),
+        Instruction::LdILong => format!("i := long {}", name(long_address(bytes))),
+        // Octo only takes the planes it knows about
+        Instruction::Plane(n) if n > 3 => return None,
+        Instruction::Plane(n) => format!("plane {}", n),
+        Instruction::Audio => "audio".to_string(),
+        Instruction::LdVxDt(x) => format!("v{:x} := delay", x),
+        Instruction::LdVxK(x) => format!("v{:x} := key", x),
+        Instruction::LdDtVx(x) => format!("delay := v{:x}", x),
+        Instruction::LdStVx(x) => format!("buzzer := v{:x}", x),
+        Instruction::Pitch(x) => format!("pitch := v{:x}", x),
+        Instruction::AddIVx(x) => format!("i += v{:x}", x),
+        Instruction::LdFVx(x) => format!("i := hex v{:x}", x),
+        Instruction::LdHfVx(x) => format!("i := bighex v{:x}", x),
+        Instruction::LdBVx(x) => format!("bcd v{:x}", x),
+        Instruction::LdIVx(x) => format!("save v{:x}", x),
+        Instruction::LdVxI(x) => format!("load v{:x}", x),
+        Instruction::LdRVx(x) => format!("saveflags v{:x}", x),
+        Instruction::LdVxR(x) => format!("loadflags v{:x}", x),
+    })
+}
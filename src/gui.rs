@@ -0,0 +1,1479 @@
+use crate::analyzer::analyze;
+use crate::codeview::{self, line_at, CodeView};
+use crate::commands::Cli;
+use crate::cpu::{Cpu, CpuError};
+use crate::debugger::{Debugger, StopReason, Watchpoint};
+use crate::disassembler::{disassemble_analyzed, Disassembly};
+use crate::inspector::{self, call_stack, register_at, Inspector};
+use crate::keymap::{default_keymap_path, Keymap, KeymapConfig, Rebinder, KEYPAD};
+use crate::memview::{self, MemoryView, Region};
+use crate::movie::Movie;
+use crate::rewind::Rewind;
+use crate::sound::Beeper;
+use crate::trace::open_trace;
+use crate::{
+    DISP_HEIGHT, DISP_HEIGHT_INFO_AREA, DISP_SCALE, DISP_WIDTH, DISP_WIDTH_CODE_PANEL,
+    DISP_WIDTH_MEMORY_PANEL,
+};
+//use ggez::conf::{WindowMode, WindowSetup};
+//use ggez::event;
+use ggez::audio::SoundSource;
+use ggez::event::{self, KeyCode, KeyMods, MouseButton};
+use ggez::graphics::{self, DrawParam, Text};
+//use ggez::input::keyboard;
+use ggez::*;
+use glam::Vec2;
+use nalgebra as na;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path;
+
+// The r8 window: the display, the debug panels beside and under it, the
+// buzzer and the keypad.  Only built with the gui feature.
+
+const CODE_ROWS: usize = 21;
+const CODE_PANEL_X: f32 = DISP_WIDTH + 10.0;
+const MEMORY_ROWS: usize = 34;
+const MEMORY_PANEL_X: f32 = DISP_WIDTH + DISP_WIDTH_CODE_PANEL + 10.0;
+const PANEL_ROWS_Y: f32 = 24.0; // Below the panels' headings
+const PANEL_TEXT_SCALE: f32 = 13.0;
+const INSPECTOR_Y: f32 = DISP_HEIGHT + 4.0;
+
+// Backgrounds for the memory panel's regions
+const FONT_COLOR: (f32, f32, f32) = (0.85, 0.92, 1.0);
+const ROM_COLOR: (f32, f32, f32) = (1.0, 0.97, 0.82);
+const I_COLOR: (f32, f32, f32) = (0.6, 0.9, 0.6);
+const STACK_COLOR: (f32, f32, f32) = (1.0, 0.8, 0.55);
+
+// Pixel colours by XO-CHIP plane mask: plane 1, plane 2, and both
+const PALETTE: [(f32, f32, f32); 3] = [(0.0, 0.0, 0.0), (0.8, 0.2, 0.2), (0.5, 0.5, 0.5)];
+
+//type Point2 = na::Point2<f32>;
+
+// A movie being recorded to a file, or one being played back and the
+// frame it is up to
+enum MovieMode {
+    Recording(Movie, String),
+    Replaying(Movie, usize),
+}
+
+pub struct App {
+    dt: std::time::Duration,
+    cpu: Cpu,
+    rom_file: String,
+    disassembly: Disassembly, // Of the rom as loaded, for naming addresses
+    cell: graphics::Mesh,
+    texts: BTreeMap<&'static str, Text>,
+    tick_once: bool,
+    fault: Option<CpuError>,
+    ipf: usize,
+    beeper: Beeper,
+    buzzer: Option<audio::Source>,
+    muted: bool,
+    rewind: Rewind,
+    rewinding: bool,
+    debugger: Debugger,
+    cursor: usize, // The address the debugger keys work on
+    code_view: CodeView,
+    memory_view: MemoryView,
+    inspector: Inspector,
+    debug_area: bool, // The panels and info area are shown
+    keymap: Keymap,
+    keymap_config: KeymapConfig,
+    keymap_path: Option<path::PathBuf>, // Where rebinding saves to
+    rom_name: String,                   // For the rom's keymap section
+    rebinder: Option<Rebinder>,         // The rebinding screen is up
+    rebinding_paused: bool,             // Whether it was paused before it
+    movie: Option<MovieMode>,
+}
+
+impl App {
+    fn new(ctx: &mut Context, args: Cli, rom: String) -> GameResult<App> {
+        let dt = std::time::Duration::new(0, 0);
+
+        // Generate our CPU
+        let mut cpu = Cpu::with_quirks(args.platform.quirks());
+        cpu.set_memory_size(args.memory.unwrap_or_else(|| args.platform.memory_size()));
+
+        // Print the seed so a run can be repeated
+        let seed = args.seed.unwrap_or_else(rand::random);
+        cpu.seed_rng(seed);
+        println!("RNG seed: {}", seed);
+
+        // Load the ROM intro the CPU
+        let mut rom_file = "./data/".to_string();
+        rom_file += &rom;
+        let rom_bytes = match fs::read(&rom_file).and_then(|rom| {
+            cpu.load_rom_bytes(&rom)?;
+            Ok(rom)
+        }) {
+            Ok(rom) => {
+                println!("Loaded rom file: {}", rom_file);
+                rom
+            }
+            Err(err) => {
+                panic!("Unable to load rom file: {}", err);
+            }
+        };
+        let disassembly = disassemble_analyzed(&rom_bytes, 0x200, &analyze(&rom_bytes, 0x200));
+        if let Some(path) = &args.trace {
+            match open_trace(path::Path::new(path)) {
+                Ok(trace) => cpu.trace = Some(trace),
+                Err(err) => panic!("Unable to write trace {}: {}", path, err),
+            }
+            println!("Tracing to: {}", path);
+        }
+
+        // Movies start from here, once the rom is in place
+        let mut ipf = args.ipf;
+        let movie = if let Some(path) = &args.replay {
+            let movie = match fs::read(path).map_err(|e| e.to_string()).and_then(|bytes| {
+                let movie = Movie::from_bytes(&bytes).map_err(|e| e.to_string())?;
+                movie.start(&mut cpu).map_err(|e| e.to_string())?;
+                Ok(movie)
+            }) {
+                Ok(movie) => movie,
+                Err(err) => panic!("Unable to replay movie {}: {}", path, err),
+            };
+            println!(
+                "Replaying movie: {} ({} frames, RNG seed {})",
+                path,
+                movie.frames.len(),
+                movie.seed
+            );
+            ipf = movie.ipf;
+            Some(MovieMode::Replaying(movie, 0))
+        } else if let Some(path) = &args.record {
+            println!("Recording movie: {}", path);
+            Some(MovieMode::Recording(
+                Movie::new(&cpu, seed, ipf),
+                path.clone(),
+            ))
+        } else {
+            None
+        };
+
+        // Setup a "cell"/pixel for the engine to use, it is white so
+        // that it can be tinted with the palette colours when drawn
+        let cell = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::fill(),
+            graphics::Rect::new(0.0, 0.0, DISP_SCALE, DISP_SCALE),
+            graphics::WHITE,
+        )?;
+
+        let mut beeper = Beeper::new(args.tone, args.volume, 44_100);
+        let buzzer = App::make_buzzer(ctx, &mut beeper)?;
+
+        let mut debugger = Debugger::new();
+        debugger.breakpoints.extend(args.breakpoints);
+        debugger.watchpoints = args.watchpoints;
+
+        let mut memory_view = MemoryView::new(0x200..0x200 + rom_bytes.len(), MEMORY_ROWS);
+        memory_view.observe(&cpu.memory, cpu.cycles);
+
+        let inspector = Inspector::new(&cpu);
+
+        // The user's keymap, with any overrides for this rom
+        let keymap_path = args
+            .keymap
+            .clone()
+            .map(path::PathBuf::from)
+            .or_else(default_keymap_path);
+        let keymap_config = match &keymap_path {
+            Some(path) => match KeymapConfig::load(path) {
+                Ok(config) => config,
+                Err(err) => panic!("Unable to load keymap {}: {}", path.display(), err),
+            },
+            None => KeymapConfig::default(),
+        };
+        let rom_name = path::Path::new(&rom)
+            .file_name()
+            .map_or_else(|| rom.clone(), |name| name.to_string_lossy().to_string());
+        let keymap = keymap_config.keymap(&rom_name);
+        if let Some(path) = &keymap_path {
+            println!("Keymap: {} ({} layout)", path.display(), keymap.layout);
+        }
+
+        // Setup some texts for update later
+        let mut texts = BTreeMap::new();
+        // Store the text in `App`s map, for drawing in main loop.
+        texts.insert("1_romname", Text::new(format!("ROM Loaded: {}", rom_file)));
+
+        // Return a good version of the app object
+        Ok(App {
+            dt,
+            cpu,
+            rom_file,
+            disassembly,
+            cell,
+            texts,
+            tick_once: false,
+            fault: None,
+            ipf,
+            beeper,
+            buzzer,
+            muted: args.mute,
+            rewind: Rewind::new(args.rewind, args.rewind_interval),
+            rewinding: false,
+            movie,
+            debugger,
+            cursor: 0x200,
+            code_view: CodeView::new(CODE_ROWS),
+            memory_view,
+            inspector,
+            debug_area: true,
+            keymap,
+            keymap_config,
+            keymap_path,
+            rom_name,
+            rebinder: None,
+            rebinding_paused: false,
+        })
+    }
+
+    // Render a short loop of the buzzer tone, it is paused and resumed
+    // as the sound timer runs.  No audio device just means no sound.
+    fn make_buzzer(ctx: &mut Context, beeper: &mut Beeper) -> GameResult<Option<audio::Source>> {
+        let samples = beeper.period_samples(50);
+        match audio::Source::from_data(ctx, audio::SoundData::from_bytes(&beeper.to_wav(samples))) {
+            Ok(mut source) => {
+                source.set_repeat(true);
+                source.play()?;
+                source.pause();
+                Ok(Some(source))
+            }
+            Err(err) => {
+                println!("Unable to set up the buzzer: {}", err);
+                Ok(None)
+            }
+        }
+    }
+
+    // Save states live next to the rom, one file per slot
+    fn state_path(&self, slot: usize) -> String {
+        format!("{}.state{}", self.rom_file, slot)
+    }
+
+    fn save_slot(&mut self, slot: usize) {
+        let path = self.state_path(slot);
+        let status = match fs::write(&path, self.cpu.save_state()) {
+            Ok(_) => format!("Saved state to slot {}", slot),
+            Err(err) => format!("Unable to save {}: {}", path, err),
+        };
+        println!("{}", status);
+        self.texts.insert("7_state", Text::new(status));
+    }
+
+    fn load_slot(&mut self, slot: usize) {
+        let path = self.state_path(slot);
+        let status = match fs::read(&path) {
+            Ok(state) => match self.cpu.load_state(&state) {
+                Ok(_) => {
+                    // The loaded state starts afresh, without any old history
+                    self.clear_fault();
+                    self.rewind.clear();
+                    self.update_info_text();
+                    format!("Loaded state from slot {}", slot)
+                }
+                Err(err) => format!("Unable to load slot {}: {}", slot, err),
+            },
+            Err(err) => format!("Unable to load {}: {}", path, err),
+        };
+        println!("{}", status);
+        self.texts.insert("7_state", Text::new(status));
+    }
+
+    // Records or feeds in the keys for the frame about to run.  False once
+    // a replay has run out, which pauses so the end can be looked over.
+    fn movie_frame(&mut self) -> bool {
+        let finished = match &mut self.movie {
+            Some(MovieMode::Recording(movie, _)) => {
+                movie.record_frame(&self.cpu);
+                None
+            }
+            Some(MovieMode::Replaying(movie, frame)) => {
+                if movie.apply_frame(*frame, &mut self.cpu) {
+                    *frame += 1;
+                    None
+                } else {
+                    Some(match movie.verify(&self.cpu) {
+                        Ok(_) => format!("Replay finished after {} frames, display matches", frame),
+                        Err(err) => format!("Replay failed: {}", err),
+                    })
+                }
+            }
+            None => None,
+        };
+
+        match finished {
+            Some(status) => {
+                println!("{}", status);
+                self.texts.insert("8_movie", Text::new(status));
+                self.movie = None;
+                self.cpu.pause_tick = true;
+                false
+            }
+            None => true,
+        }
+    }
+
+    // Writes out the movie being recorded, if there is one
+    fn stop_recording(&mut self) {
+        if let Some(trace) = &mut self.cpu.trace {
+            if let Err(err) = trace.flush() {
+                println!("Unable to finish the trace: {}", err);
+            }
+        }
+        match self.movie.take() {
+            Some(MovieMode::Recording(mut movie, path)) => {
+                movie.finish(&self.cpu);
+                match fs::write(&path, movie.to_bytes()) {
+                    Ok(_) => println!("Saved movie: {} ({} frames)", path, movie.frames.len()),
+                    Err(err) => println!("Unable to save movie {}: {}", path, err),
+                }
+            }
+            other => self.movie = other,
+        }
+    }
+
+    // Going back in time undoes any fault
+    fn clear_fault(&mut self) {
+        self.fault = None;
+        self.texts.remove("0_fault");
+    }
+
+    // Memory can only be typed over while paused, and not under a movie
+    fn editing_memory(&self) -> bool {
+        self.memory_view.selected.is_some() && self.cpu.pause_tick && self.movie.is_none()
+    }
+
+    fn replaying(&self) -> bool {
+        matches!(self.movie, Some(MovieMode::Replaying(..)))
+    }
+
+    fn step_back(&mut self) {
+        if self.rewind.step_back(&mut self.cpu) {
+            self.clear_fault();
+            self.update_info_text();
+        }
+    }
+
+    // Keyboard input, which comes with its scancode from run() below
+    fn key_down(&mut self, ctx: &mut Context, key: Option<KeyCode>, scancode: u32, mods: KeyMods) {
+        if self.rebinder.is_some() {
+            self.rebind_key(key, scancode, mods);
+            return;
+        }
+        let bound = self.keymap.key(key, scancode);
+        if let Some(key) = key {
+            if self.control_key(ctx, key, mods, bound.is_some()) {
+                return;
+            }
+        }
+
+        // Record the rest into the input array for the cpu, unless a movie
+        // is doing the typing
+        if let (Some(p), false) = (bound, self.replaying()) {
+            self.cpu.input.keys[p] = true
+        }
+    }
+
+    fn key_up(&mut self, key: Option<KeyCode>, scancode: u32) {
+        if key == Some(KeyCode::Back) {
+            self.rewinding = false;
+        }
+        if let (Some(p), false) = (self.keymap.key(key, scancode), self.replaying()) {
+            self.cpu.input.keys[p] = false
+        }
+    }
+
+    // Our application control keys, returning whether the key was taken.
+    // The function keys always are, but the rest leave the keys bound to
+    // the keypad alone.
+    fn control_key(&mut self, ctx: &mut Context, key: KeyCode, mods: KeyMods, bound: bool) -> bool {
+        match key {
+            // Quit if Shift+Ctrl+Q is pressed.
+            KeyCode::Escape => {
+                println!("Terminating!");
+                self.stop_recording();
+                event::quit(ctx);
+            }
+            KeyCode::F1 => {
+                self.cpu.pause_tick = !self.cpu.pause_tick;
+                if self.cpu.pause_tick {
+                    self.debugger.pause(&self.cpu);
+                } else {
+                    self.debugger.resume();
+                }
+            }
+            KeyCode::F2 => {
+                self.muted = !self.muted;
+            }
+            KeyCode::F3 => {
+                self.show_debug_area(ctx, !self.debug_area);
+            }
+            // Space steps forward one instruction, Shift+Space steps back.
+            // Neither, nor rewinding, can be part of a movie.
+            KeyCode::Space if self.movie.is_none() && !bound => {
+                if mods.contains(KeyMods::SHIFT) {
+                    self.step_back();
+                } else {
+                    self.tick_once = true;
+                }
+            }
+            KeyCode::Back if self.movie.is_none() && !bound => {
+                self.rewinding = true;
+            }
+            // F5 - F8 save to slots 1 - 4, and load with Shift held
+            KeyCode::F5 | KeyCode::F6 | KeyCode::F7 | KeyCode::F8 => {
+                let slot = match key {
+                    KeyCode::F5 => 1,
+                    KeyCode::F6 => 2,
+                    KeyCode::F7 => 3,
+                    _ => 4,
+                };
+                if !mods.contains(KeyMods::SHIFT) {
+                    self.save_slot(slot);
+                } else if self.movie.is_none() {
+                    self.load_slot(slot);
+                }
+            }
+            // The debugger: the arrows and Page Up/Down move the cursor and
+            // Home puts it back on the PC.  F9 toggles a breakpoint at the
+            // cursor, Ctrl+F9 a write watchpoint and Alt+F9 a read one.
+            KeyCode::Up | KeyCode::Down | KeyCode::PageUp | KeyCode::PageDown if !bound => {
+                let step = match key {
+                    KeyCode::Up => -2,
+                    KeyCode::Down => 2,
+                    KeyCode::PageUp => -0x10,
+                    _ => 0x10,
+                };
+                let size = self.cpu.memory.len() as isize;
+                self.cursor = (self.cursor as isize + step).rem_euclid(size) as usize;
+            }
+            KeyCode::Home if !bound => {
+                self.cursor = self.cpu.pc;
+            }
+            KeyCode::F9 => {
+                let cursor = self.cursor;
+                if mods.contains(KeyMods::CTRL) {
+                    self.debugger
+                        .toggle_watchpoint(Watchpoint::Write(cursor, cursor));
+                } else if mods.contains(KeyMods::ALT) {
+                    self.debugger
+                        .toggle_watchpoint(Watchpoint::Read(cursor, cursor));
+                } else {
+                    self.debugger.toggle_breakpoint(cursor);
+                }
+            }
+            // F10 steps over a call, F11 steps out of the current one and
+            // F4 runs to the cursor
+            KeyCode::F10 | KeyCode::F11 | KeyCode::F4 if self.movie.is_none() => {
+                let run = match key {
+                    KeyCode::F10 => self.debugger.step_over(&self.cpu),
+                    KeyCode::F11 => self.debugger.step_out(&self.cpu),
+                    _ => {
+                        self.debugger.run_to(self.cursor);
+                        true
+                    }
+                };
+                if run {
+                    self.debugger.resume();
+                    self.cpu.pause_tick = false;
+                } else if key == KeyCode::F10 {
+                    self.tick_once = true;
+                }
+            }
+            KeyCode::Delete if !bound => {
+                self.memory_view.select(None);
+            }
+            KeyCode::F12 => {
+                self.start_rebinding();
+            }
+            _ => (),
+        }
+        if matches!(
+            key,
+            KeyCode::F1
+                | KeyCode::F2
+                | KeyCode::F3
+                | KeyCode::F4
+                | KeyCode::F5
+                | KeyCode::F6
+                | KeyCode::F7
+                | KeyCode::F8
+                | KeyCode::F9
+                | KeyCode::F10
+                | KeyCode::F11
+                | KeyCode::F12
+        ) {
+            return true;
+        }
+
+        // While paused, the hex digits type over the byte selected in the
+        // memory panel rather than pressing keys on the keypad
+        if let (Some(digit), true) = (hex_digit(key), self.editing_memory()) {
+            if !mods.contains(KeyMods::CTRL) {
+                if let Some(address) = self
+                    .memory_view
+                    .type_digit(&mut self.cpu.memory, digit as u8)
+                {
+                    println!(
+                        "Wrote {:#04X} to {:#05X}",
+                        self.cpu.memory[address], address
+                    );
+                    self.rewind.edited(&self.cpu);
+                }
+                return true;
+            }
+        }
+
+        // Ctrl with a hex digit toggles a watchpoint on that V register,
+        // and Ctrl+I on I, rather than pressing a key on the keypad
+        if mods.contains(KeyMods::CTRL) {
+            let watch = match key {
+                KeyCode::I => Some(Watchpoint::I),
+                _ => hex_digit(key).map(Watchpoint::V),
+            };
+            if let Some(watch) = watch {
+                self.debugger.toggle_watchpoint(watch);
+                return true;
+            }
+        }
+        false
+    }
+
+    // F12 brings up the rebinding screen, pausing the game under it
+    fn start_rebinding(&mut self) {
+        self.rebinder = Some(Rebinder::new(self.keymap.clone()));
+        self.rebinding_paused = self.cpu.pause_tick;
+        self.cpu.pause_tick = true;
+        if !self.replaying() {
+            self.cpu.input.keys = [false; 16];
+        }
+    }
+
+    // On the rebinding screen the keys pressed are bound to the highlighted
+    // CHIP-8 key.  Tab moves on (Shift+Tab back), Delete clears it, Return
+    // saves and Escape or F12 give up.
+    fn rebind_key(&mut self, key: Option<KeyCode>, scancode: u32, mods: KeyMods) {
+        let rebinder = match &mut self.rebinder {
+            Some(rebinder) => rebinder,
+            None => return,
+        };
+        match key {
+            Some(KeyCode::Tab) if mods.contains(KeyMods::SHIFT) => rebinder.advance(-1),
+            Some(KeyCode::Tab) => rebinder.advance(1),
+            Some(KeyCode::Delete) => rebinder.clear(),
+            Some(KeyCode::Return) => {
+                let keymap = rebinder.keymap.clone();
+                self.finish_rebinding(Some(keymap), mods.contains(KeyMods::SHIFT));
+            }
+            Some(KeyCode::Escape) | Some(KeyCode::F12) => self.finish_rebinding(None, false),
+            _ => {
+                let physical = rebinder.keymap.physical(key, scancode);
+                rebinder.press(physical);
+            }
+        }
+    }
+
+    // Saves to this rom's section if asked to or if it already has one, or
+    // else for every rom
+    fn finish_rebinding(&mut self, keymap: Option<Keymap>, for_rom: bool) {
+        self.rebinder = None;
+        self.cpu.pause_tick = self.rebinding_paused;
+        let keymap = match keymap {
+            Some(keymap) => keymap,
+            None => return,
+        };
+        let section = keymap.section();
+        if for_rom || self.keymap_config.roms.contains_key(&self.rom_name) {
+            self.keymap_config
+                .roms
+                .insert(self.rom_name.clone(), section);
+        } else {
+            self.keymap_config.global = section;
+        }
+        self.keymap = keymap;
+
+        let status = match &self.keymap_path {
+            Some(path) => match self.keymap_config.save(path) {
+                Ok(_) => format!("Saved keymap to {}", path.display()),
+                Err(err) => format!("Unable to save keymap {}: {}", path.display(), err),
+            },
+            None => "Keymap changed, but there is nowhere to save it (try --keymap)".to_string(),
+        };
+        println!("{}", status);
+        self.texts.insert("7_keymap", Text::new(status));
+    }
+
+    // The rebinding screen, over the display: the keypad with what is bound
+    // to each key
+    fn draw_rebinding(&mut self, ctx: &mut Context) -> GameResult {
+        let rebinder = match &self.rebinder {
+            Some(rebinder) => rebinder,
+            None => return Ok(()),
+        };
+        let black = graphics::Color::new(0.0, 0.0, 0.0, 1.0);
+        let (title_height, cell_width) = (40.0, DISP_WIDTH / 4.0);
+        let cell_height = (DISP_HEIGHT - title_height) / 4.0;
+
+        let mut marks = graphics::MeshBuilder::new();
+        marks.rectangle(
+            graphics::DrawMode::fill(),
+            graphics::Rect::new(0.0, 0.0, DISP_WIDTH, DISP_HEIGHT),
+            graphics::Color::new(0.95, 0.95, 0.95, 1.0),
+        );
+        graphics::queue_text(
+            ctx,
+            &panel_text(format!(
+                "Press the keys for CHIP-8 key {:X} ({} layout).  Tab moves on, Delete clears it,",
+                rebinder.current(),
+                rebinder.keymap.layout
+            )),
+            Vec2::new(6.0, 4.0),
+            Some(black),
+        );
+        graphics::queue_text(
+            ctx,
+            &panel_text(
+                "Return saves, Shift+Return saves for this rom only, Escape cancels".to_string(),
+            ),
+            Vec2::new(6.0, 20.0),
+            Some(black),
+        );
+        for (position, &key) in KEYPAD.iter().enumerate() {
+            let x = (position % 4) as f32 * cell_width;
+            let y = title_height + (position / 4) as f32 * cell_height;
+            let bounds = graphics::Rect::new(x + 3.0, y + 3.0, cell_width - 6.0, cell_height - 6.0);
+            if position == rebinder.position {
+                marks.rectangle(
+                    graphics::DrawMode::fill(),
+                    bounds,
+                    graphics::Color::new(1.0, 0.9, 0.4, 1.0),
+                );
+            }
+            marks.rectangle(graphics::DrawMode::stroke(1.0), bounds, black);
+
+            let name = Text::new(
+                graphics::TextFragment::new(format!("{:X}", key))
+                    .scale(graphics::Scale::uniform(24.0)),
+            );
+            graphics::queue_text(ctx, &name, Vec2::new(x + 10.0, y + 8.0), Some(black));
+            let keys: Vec<String> = rebinder
+                .keymap
+                .keys_for(key)
+                .iter()
+                .map(|k| k.to_string())
+                .collect();
+            let keys = if keys.is_empty() {
+                "-".to_string()
+            } else {
+                keys.join(", ")
+            };
+            graphics::queue_text(
+                ctx,
+                &panel_text(keys),
+                Vec2::new(x + 36.0, y + 14.0),
+                Some(black),
+            );
+        }
+
+        let marks = marks.build(ctx)?;
+        graphics::draw(ctx, &marks, DrawParam::default())?;
+        graphics::draw_queued_text(
+            ctx,
+            DrawParam::default(),
+            None,
+            graphics::FilterMode::Linear,
+        )
+    }
+
+    // The window shrinks to just the display without the debug area
+    fn show_debug_area(&mut self, ctx: &mut Context, shown: bool) {
+        let (width, height) = if shown {
+            (
+                DISP_WIDTH + DISP_WIDTH_CODE_PANEL + DISP_WIDTH_MEMORY_PANEL,
+                DISP_HEIGHT + DISP_HEIGHT_INFO_AREA,
+            )
+        } else {
+            (DISP_WIDTH, DISP_HEIGHT)
+        };
+        let result = graphics::set_drawable_size(ctx, width, height).and_then(|_| {
+            graphics::set_screen_coordinates(ctx, graphics::Rect::new(0.0, 0.0, width, height))
+        });
+        match result {
+            Ok(_) => self.debug_area = shown,
+            Err(err) => println!("Unable to resize the window: {}", err),
+        }
+    }
+
+    // The registers, stack and timers, in the info area under the display
+    fn draw_inspector(&mut self, ctx: &mut Context) -> GameResult {
+        let cpu = &self.cpu;
+        let inspector = &self.inspector;
+        let black = graphics::Color::new(0.0, 0.0, 0.0, 1.0);
+        let changed = graphics::Color::new(1.0, 0.85, 0.45, 1.0);
+        let row_y = |row: usize| INSPECTOR_Y + row as f32 * inspector::ROW_HEIGHT;
+        let mut marks = graphics::MeshBuilder::new();
+
+        // PC, and I with what it points at
+        let mut x = 4.0;
+        let pc = panel_text(format!("PC {:#05X}", cpu.pc));
+        graphics::queue_text(ctx, &pc, Vec2::new(x, row_y(0)), Some(black));
+        x += pc.width(ctx) as f32 + 16.0;
+        let i = panel_text(format!("I {:#05X}", cpu.i));
+        let i_width = i.width(ctx) as f32;
+        let i_background = if inspector.i_changed {
+            changed
+        } else {
+            graphics::WHITE
+        };
+        marks.rectangle(
+            graphics::DrawMode::fill(),
+            graphics::Rect::new(x - 2.0, row_y(0), i_width + 4.0, inspector::ROW_HEIGHT),
+            i_background,
+        );
+        graphics::queue_text(ctx, &i, Vec2::new(x, row_y(0)), Some(black));
+        x += i_width + 8.0;
+        let modes = format!(
+            "-> {}{}{}",
+            inspector.i_bytes(cpu),
+            if cpu.hires { "   HIRES" } else { "" },
+            if cpu.exited { "   EXITED" } else { "" }
+        );
+        graphics::queue_text(ctx, &panel_text(modes), Vec2::new(x, row_y(0)), Some(black));
+
+        // V0 - VF, eight to a row
+        for (r, value) in cpu.v.iter().enumerate() {
+            let cell_x = 4.0 + (r % 8) as f32 * inspector::CELL_WIDTH;
+            let cell_y = row_y(1 + r / 8);
+            if inspector.changed[r] {
+                marks.rectangle(
+                    graphics::DrawMode::fill(),
+                    graphics::Rect::new(
+                        cell_x - 2.0,
+                        cell_y,
+                        inspector::CELL_WIDTH - 6.0,
+                        inspector::ROW_HEIGHT,
+                    ),
+                    changed,
+                );
+            }
+            let text = panel_text(format!("V{:X}  {}", r, inspector.value(*value)));
+            graphics::queue_text(ctx, &text, Vec2::new(cell_x, cell_y), Some(black));
+        }
+
+        // The timers as bars out of 255
+        let bar_width = 150.0;
+        for (t, (name, value)) in [("DT", cpu.delay_timer), ("ST", cpu.sound_timer)]
+            .iter()
+            .enumerate()
+        {
+            let bar_x = 4.0 + t as f32 * (bar_width + 110.0);
+            let label = match (t, self.muted) {
+                (1, true) => format!("{} {} (muted)", name, inspector.value(*value)),
+                _ => format!("{} {}", name, inspector.value(*value)),
+            };
+            graphics::queue_text(
+                ctx,
+                &panel_text(label),
+                Vec2::new(bar_x, row_y(3)),
+                Some(black),
+            );
+            let bounds = graphics::Rect::new(
+                bar_x + 44.0,
+                row_y(3) + 3.0,
+                bar_width,
+                inspector::ROW_HEIGHT - 6.0,
+            );
+            marks.rectangle(graphics::DrawMode::stroke(1.0), bounds, black);
+            if *value > 0 {
+                marks.rectangle(
+                    graphics::DrawMode::fill(),
+                    graphics::Rect::new(
+                        bounds.x,
+                        bounds.y,
+                        bar_width * *value as f32 / 255.0,
+                        bounds.h,
+                    ),
+                    graphics::Color::new(0.3, 0.5, 0.9, 1.0),
+                );
+            }
+        }
+
+        let stack = call_stack(cpu, &self.disassembly);
+        let stack = if stack.is_empty() {
+            "stack: empty".to_string()
+        } else {
+            format!("stack: {}", stack.join("  <  "))
+        };
+        graphics::queue_text(
+            ctx,
+            &panel_text(stack),
+            Vec2::new(4.0, row_y(4)),
+            Some(black),
+        );
+
+        let keys = match cpu.waiting_for_key() {
+            Some(x) => format!(
+                "keys: {}   waiting for a key for V{:X}",
+                cpu.input.dump_keys(),
+                x
+            ),
+            None => format!("keys: {}", cpu.input.dump_keys()),
+        };
+        graphics::queue_text(
+            ctx,
+            &panel_text(keys),
+            Vec2::new(4.0, row_y(5)),
+            Some(black),
+        );
+
+        let marks = marks.build(ctx)?;
+        graphics::draw(ctx, &marks, DrawParam::default())?;
+        graphics::draw_queued_text(
+            ctx,
+            DrawParam::default(),
+            None,
+            graphics::FilterMode::Linear,
+        )
+    }
+
+    // The disassembly panel, following the PC
+    fn draw_code(&mut self, ctx: &mut Context) -> GameResult {
+        let lines = self
+            .code_view
+            .lines(&self.cpu, &self.disassembly, &self.debugger.breakpoints);
+        let black = graphics::Color::new(0.0, 0.0, 0.0, 1.0);
+        let grey = graphics::Color::new(0.45, 0.45, 0.45, 1.0);
+        let red = graphics::Color::new(0.85, 0.1, 0.1, 1.0);
+        let (x, y) = (CODE_PANEL_X, PANEL_ROWS_Y);
+        let width = DISP_WIDTH_CODE_PANEL - 12.0;
+
+        graphics::queue_text(
+            ctx,
+            &panel_text("Disassembly  (click a line for a breakpoint)".to_string()),
+            Vec2::new(x, 4.0),
+            Some(black),
+        );
+
+        let mut marks = graphics::MeshBuilder::new();
+        marks.rectangle(
+            graphics::DrawMode::stroke(1.0),
+            graphics::Rect::new(
+                x - 4.0,
+                y - 2.0,
+                width,
+                CODE_ROWS as f32 * codeview::ROW_HEIGHT + 4.0,
+            ),
+            black,
+        );
+        for (row, line) in lines.iter().enumerate() {
+            let row_y = y + row as f32 * codeview::ROW_HEIGHT;
+            let bounds = graphics::Rect::new(x - 2.0, row_y, width - 4.0, codeview::ROW_HEIGHT);
+            if line.current {
+                marks.rectangle(
+                    graphics::DrawMode::fill(),
+                    bounds,
+                    graphics::Color::new(1.0, 0.9, 0.4, 1.0),
+                );
+            } else if line.target {
+                marks.rectangle(
+                    graphics::DrawMode::fill(),
+                    bounds,
+                    graphics::Color::new(0.88, 0.95, 0.88, 1.0),
+                );
+            }
+            if line.address == self.cursor {
+                marks.rectangle(
+                    graphics::DrawMode::stroke(1.0),
+                    bounds,
+                    graphics::Color::new(0.1, 0.2, 0.9, 1.0),
+                );
+            }
+            if line.breakpoint {
+                marks.circle(
+                    graphics::DrawMode::fill(),
+                    na::Point2::new(x + 5.0, row_y + codeview::ROW_HEIGHT / 2.0),
+                    4.5,
+                    0.5,
+                    red,
+                );
+            }
+
+            let gutter = match (line.current, line.target) {
+                (true, _) => ">",
+                (false, true) => "<",
+                _ => "",
+            };
+            graphics::queue_text(
+                ctx,
+                &panel_text(gutter.to_string()),
+                Vec2::new(x + 12.0, row_y),
+                Some(black),
+            );
+            let text = panel_text(format!("{:03X}  {}", line.address, line.text));
+            graphics::queue_text(
+                ctx,
+                &text,
+                Vec2::new(x + codeview::GUTTER_WIDTH, row_y),
+                Some(black),
+            );
+            if let Some(note) = &line.note {
+                let note = panel_text(note.clone());
+                let note_x = x + width - 8.0 - note.width(ctx) as f32;
+                graphics::queue_text(ctx, &note, Vec2::new(note_x, row_y), Some(grey));
+            }
+        }
+
+        let marks = marks.build(ctx)?;
+        graphics::draw(ctx, &marks, DrawParam::default())?;
+        graphics::draw_queued_text(
+            ctx,
+            DrawParam::default(),
+            None,
+            graphics::FilterMode::Linear,
+        )
+    }
+
+    // The memory panel: region backgrounds first, then the bytes over them
+    fn draw_memory(&mut self, ctx: &mut Context) -> GameResult {
+        let view = &self.memory_view;
+        let memory = &self.cpu.memory;
+        let color = |(r, g, b): (f32, f32, f32)| graphics::Color::new(r, g, b, 1.0);
+        let text = panel_text;
+        let black = graphics::Color::new(0.0, 0.0, 0.0, 1.0);
+        let (x, y) = (MEMORY_PANEL_X, PANEL_ROWS_Y);
+
+        let heading = match view.selected {
+            Some(address) => format!(
+                "Memory  I {:#05X}  {:#05X} = {:02X}{}",
+                self.cpu.i,
+                address,
+                memory[address],
+                match (view.nibble, self.editing_memory()) {
+                    (Some(high), _) => format!("  new value {:X}_", high),
+                    (None, true) => "  type hex to change it".to_string(),
+                    (None, false) => "  pause (F1) to change it".to_string(),
+                }
+            ),
+            None => format!(
+                "Memory  I {:#05X}  (scroll with the wheel, click a byte to select it)",
+                self.cpu.i
+            ),
+        };
+        graphics::queue_text(ctx, &text(heading), Vec2::new(x, 4.0), Some(black));
+
+        let mut backgrounds = graphics::MeshBuilder::new();
+        // A MeshBuilder can't be built empty
+        backgrounds.rectangle(
+            graphics::DrawMode::stroke(1.0),
+            graphics::Rect::new(
+                x - 4.0,
+                y - 2.0,
+                DISP_WIDTH_MEMORY_PANEL - 12.0,
+                MEMORY_ROWS as f32 * memview::ROW_HEIGHT + 4.0,
+            ),
+            black,
+        );
+        for row in 0..view.rows {
+            let address = view.top + row * memview::BYTES_PER_ROW;
+            if address >= memory.len() {
+                break;
+            }
+            let row_y = y + row as f32 * memview::ROW_HEIGHT;
+            graphics::queue_text(
+                ctx,
+                &text(format!("{:04X}", address)),
+                Vec2::new(x, row_y),
+                Some(black),
+            );
+            for column in 0..memview::BYTES_PER_ROW {
+                let address = address + column;
+                if address >= memory.len() {
+                    break;
+                }
+                let background = match view.region(&self.cpu, address) {
+                    Region::Font => Some(FONT_COLOR),
+                    Region::Rom => Some(ROM_COLOR),
+                    Region::I => Some(I_COLOR),
+                    Region::Stack => Some(STACK_COLOR),
+                    Region::Free => None,
+                };
+                let hex = graphics::Rect::new(
+                    x + memview::hex_x(column) - 2.0,
+                    row_y,
+                    memview::HEX_WIDTH,
+                    memview::ROW_HEIGHT,
+                );
+                let ascii = graphics::Rect::new(
+                    x + memview::ascii_x(column),
+                    row_y,
+                    memview::ASCII_WIDTH,
+                    memview::ROW_HEIGHT,
+                );
+                if let Some(background) = background {
+                    backgrounds.rectangle(graphics::DrawMode::fill(), hex, color(background));
+                    backgrounds.rectangle(graphics::DrawMode::fill(), ascii, color(background));
+                }
+                if view.selected == Some(address) {
+                    backgrounds.rectangle(
+                        graphics::DrawMode::stroke(2.0),
+                        hex,
+                        color((0.1, 0.2, 0.9)),
+                    );
+                    backgrounds.rectangle(
+                        graphics::DrawMode::stroke(2.0),
+                        ascii,
+                        color((0.1, 0.2, 0.9)),
+                    );
+                }
+
+                // Writes show up red, fading back to black
+                let ink = match view.age(address) {
+                    Some(age) => {
+                        let fade = age as f32 / memview::RECENT_FRAMES as f32;
+                        graphics::Color::new(0.9 * (1.0 - fade), 0.0, 0.0, 1.0)
+                    }
+                    None => black,
+                };
+                let byte = memory[address];
+                graphics::queue_text(
+                    ctx,
+                    &text(format!("{:02X}", byte)),
+                    Vec2::new(x + memview::hex_x(column), row_y),
+                    Some(ink),
+                );
+                graphics::queue_text(
+                    ctx,
+                    &text(memview::ascii(byte).to_string()),
+                    Vec2::new(x + memview::ascii_x(column), row_y),
+                    Some(ink),
+                );
+            }
+        }
+
+        // And a key to the colours
+        let key_y = y + MEMORY_ROWS as f32 * memview::ROW_HEIGHT + 8.0;
+        let mut key_x = x;
+        for (name, background) in &[
+            ("font", FONT_COLOR),
+            ("rom", ROM_COLOR),
+            ("I", I_COLOR),
+            ("stack returns", STACK_COLOR),
+        ] {
+            backgrounds.rectangle(
+                graphics::DrawMode::fill(),
+                graphics::Rect::new(key_x, key_y, 12.0, 12.0),
+                color(*background),
+            );
+            graphics::queue_text(
+                ctx,
+                &text(name.to_string()),
+                Vec2::new(key_x + 16.0, key_y),
+                Some(black),
+            );
+            key_x += 30.0 + name.len() as f32 * 7.0;
+        }
+        graphics::queue_text(
+            ctx,
+            &text("written".to_string()),
+            Vec2::new(key_x, key_y),
+            Some(color((0.9, 0.0, 0.0))),
+        );
+
+        let backgrounds = backgrounds.build(ctx)?;
+        graphics::draw(ctx, &backgrounds, DrawParam::default())?;
+        graphics::draw_queued_text(
+            ctx,
+            DrawParam::default(),
+            None,
+            graphics::FilterMode::Linear,
+        )
+    }
+
+    // Just updates the informational text to display in debug mode
+    fn update_info_text(&mut self) {
+        self.texts.insert(
+            "9_cursor",
+            Text::new(format!(
+                "cursor {:#05X}{}: {}",
+                self.cursor,
+                if self.debugger.breakpoints.contains(&self.cursor) {
+                    " [break]"
+                } else {
+                    ""
+                },
+                self.disassembly.describe(&self.cpu.memory, self.cursor)
+            )),
+        );
+        let breakpoints: Vec<String> = self
+            .debugger
+            .breakpoints
+            .iter()
+            .map(|address| format!("{:03X}", address))
+            .collect();
+        let watchpoints: Vec<String> = self
+            .debugger
+            .watchpoints
+            .iter()
+            .map(|watch| watch.to_string())
+            .collect();
+        self.texts.insert(
+            "9_debug",
+            Text::new(format!(
+                "{} | break: {} | watch: {}",
+                match self.debugger.stop {
+                    Some(stop) => format!("stopped: {}", stop),
+                    None if self.cpu.pause_tick => "paused".to_string(),
+                    None => "running".to_string(),
+                },
+                breakpoints.join(" "),
+                watchpoints.join(" ")
+            )),
+        );
+    }
+}
+
+impl ggez::event::EventHandler for App {
+    fn update(&mut self, ctx: &mut Context) -> GameResult {
+        // Frame count timer
+        self.dt = timer::delta(ctx);
+        while timer::check_update_time(ctx, 60) {
+            // Run a frame's worth of instructions, and the timers once
+            // If we are not in single tick mode (pause_tick = true) then tick away
+            let result = if self.rewinding {
+                // Play backwards for as long as the rewind key is held
+                if self.rewind.rewind(&mut self.cpu) {
+                    self.clear_fault();
+                }
+                Ok(())
+            } else if self.fault.is_some() {
+                // A faulted cpu stays put until the rom is reloaded
+                Ok(())
+            } else if !self.cpu.pause_tick {
+                if self.movie_frame() {
+                    // Stopping part way through a frame would throw a movie out
+                    let debugging = self.movie.is_none();
+                    let debugger = &mut self.debugger;
+                    let result = if debugging && debugger.start(&self.cpu) {
+                        Ok(true)
+                    } else {
+                        debugger.before(&self.cpu);
+                        self.rewind.run_frame_until(&mut self.cpu, self.ipf, |cpu| {
+                            debugging && debugger.after(cpu)
+                        })
+                    };
+                    if let (Ok(true), Some(stop)) = (result, self.debugger.stop) {
+                        println!("Stopped: {}", stop);
+                        self.cpu.pause_tick = true;
+                    }
+                    result.map(|_| ())
+                } else {
+                    Ok(())
+                }
+            } else if self.tick_once {
+                // We are single ticking, wait until we have a space.
+                self.tick_once = false;
+                self.debugger.before(&self.cpu);
+                let result = self.rewind.tick(&mut self.cpu);
+                if result.is_ok() && !self.debugger.after(&self.cpu) {
+                    self.debugger.stop = Some(StopReason::Step);
+                }
+                result
+            } else {
+                Ok(())
+            };
+
+            // Pause on a fault and show it, rather than taking the whole app down
+            if let Err(err) = result {
+                println!("CPU fault: {}", err);
+                self.cpu.pause_tick = true;
+                self.fault = Some(err);
+                self.texts
+                    .insert("0_fault", Text::new(format!("FAULT: {}", err)));
+            }
+            // Update the text array of mapped objects with fresh values
+            self.update_info_text();
+        }
+
+        self.memory_view.observe(&self.cpu.memory, self.cpu.cycles);
+        self.inspector.observe(&self.cpu);
+
+        // An XO-CHIP rom has loaded a new audio pattern or pitch
+        if self.cpu.audio_updated {
+            self.cpu.audio_updated = false;
+            if let Some(pattern) = self.cpu.audio_pattern {
+                self.beeper.set_pattern(pattern, self.cpu.pitch);
+                self.buzzer = App::make_buzzer(ctx, &mut self.beeper)?;
+            }
+        }
+
+        // Sound the buzzer for as long as the sound timer is running
+        let beep = self.cpu.sound_active() && !self.muted && !self.cpu.pause_tick;
+        if let Some(buzzer) = &self.buzzer {
+            if beep && buzzer.paused() {
+                buzzer.resume();
+            } else if !beep && !buzzer.paused() {
+                buzzer.pause();
+            }
+        }
+
+        // Let our family know we are ok
+        Ok(())
+    }
+    // Clicking a line in the disassembly panel toggles a breakpoint on it.
+    // Clicking a byte in the memory panel selects it.  Both put the
+    // debugger cursor there too, for F9 and friends.
+    fn mouse_button_down_event(&mut self, _ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
+        if button != MouseButton::Left || !self.debug_area {
+            return;
+        }
+        // Clicking the registers switches them between hex and decimal
+        if register_at(x, y - INSPECTOR_Y).is_some() {
+            self.inspector.hex = !self.inspector.hex;
+            return;
+        }
+        if (CODE_PANEL_X..MEMORY_PANEL_X).contains(&x) {
+            let lines =
+                self.code_view
+                    .lines(&self.cpu, &self.disassembly, &self.debugger.breakpoints);
+            if let Some(line) = line_at(&lines, y - PANEL_ROWS_Y) {
+                self.cursor = line.address;
+                self.debugger.toggle_breakpoint(line.address);
+                self.update_info_text();
+            }
+            return;
+        }
+        let size = self.cpu.memory.len();
+        let address = self
+            .memory_view
+            .address_at(x - MEMORY_PANEL_X, y - PANEL_ROWS_Y, size);
+        self.memory_view.select(address);
+        if let Some(address) = address {
+            self.cursor = address;
+        }
+    }
+
+    fn mouse_wheel_event(&mut self, ctx: &mut Context, _x: f32, y: f32) {
+        if self.debug_area && input::mouse::position(ctx).x >= MEMORY_PANEL_X {
+            let size = self.cpu.memory.len();
+            self.memory_view.scroll((y * -3.0).round() as isize, size);
+        }
+    }
+
+    // Closing the window doesn't go through Escape
+    fn quit_event(&mut self, _ctx: &mut Context) -> bool {
+        self.stop_recording();
+        false
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult {
+        graphics::clear(ctx, graphics::WHITE);
+        let black = graphics::Color::new(0.0, 0.0, 0.0, 1.0);
+
+        // Pixels are half the size in hires mode
+        let scale = DISP_WIDTH / (self.cpu.width() as f32 * DISP_SCALE);
+        for (y, row) in self.cpu.gfx.iter().enumerate() {
+            for (x, val) in row.iter().enumerate() {
+                let x = (x as f32) * DISP_SCALE * scale;
+                let y = (y as f32) * DISP_SCALE * scale;
+
+                if *val != 0 {
+                    let (r, g, b) = PALETTE[(*val as usize - 1) % PALETTE.len()];
+                    graphics::draw(
+                        ctx,
+                        &self.cell,
+                        DrawParam::default()
+                            .dest(ggez::mint::Point2 { x, y })
+                            .scale(Vec2::new(scale, scale))
+                            .color(graphics::Color::new(r, g, b, 1.0)),
+                    )?;
+                }
+            }
+        }
+
+        self.draw_rebinding(ctx)?;
+
+        // Just the game, with the debug area hidden
+        if !self.debug_area {
+            return graphics::present(ctx);
+        }
+
+        // Draw text objects/details
+        // Create a little FPS text and display it in the info area
+        let mut height = DISP_HEIGHT; // Start at the top of the info area
+
+        // Draw a border line above info area
+        let mut line = graphics::Mesh::new_line(
+            ctx,
+            &[na::Point2::new(0.0, 0.0), na::Point2::new(DISP_WIDTH, 0.0)],
+            2.0,
+            graphics::BLACK,
+        )?;
+        graphics::draw(ctx, &line, ([0.0, height],))?;
+        line = graphics::Mesh::new_line(
+            ctx,
+            &[na::Point2::new(0.0, 0.0), na::Point2::new(0.0, height)],
+            2.0,
+            graphics::BLACK,
+        )?;
+        graphics::draw(ctx, &line, ([DISP_WIDTH, 0.0],))?;
+
+        // The inspector, then a FPS timer (not a mapped obj because it
+        // changes rapidly) below it
+        self.draw_inspector(ctx)?;
+        height += 6.0 + inspector::ROWS as f32 * inspector::ROW_HEIGHT;
+        let fps = timer::fps(ctx);
+        let fps_display = Text::new(format!("FPS: {}", fps));
+        graphics::draw(ctx, &fps_display, (Vec2::new(0.0, height), black))?;
+
+        // Draw the mapped text objects, too
+        height += 2.0 + fps_display.height(ctx) as f32; // Prep height to be used for mapped objs
+        for text in self.texts.values() {
+            graphics::queue_text(ctx, text, Vec2::new(0.0, height), Some(black));
+            height += 2.0 + text.height(ctx) as f32;
+        }
+        graphics::draw_queued_text(
+            ctx,
+            DrawParam::default(),
+            None,
+            graphics::FilterMode::Linear,
+        )?;
+
+        self.draw_code(ctx)?;
+        self.draw_memory(ctx)?;
+
+        graphics::present(ctx)?;
+
+        Ok(())
+    }
+}
+
+// The smaller text the panels use
+fn panel_text(s: String) -> Text {
+    Text::new(graphics::TextFragment::new(s).scale(graphics::Scale::uniform(PANEL_TEXT_SCALE)))
+}
+
+// The hex digit on a key, for picking a register
+fn hex_digit(key: KeyCode) -> Option<usize> {
+    match key {
+        KeyCode::Key0 => Some(0x0),
+        KeyCode::Key1 => Some(0x1),
+        KeyCode::Key2 => Some(0x2),
+        KeyCode::Key3 => Some(0x3),
+        KeyCode::Key4 => Some(0x4),
+        KeyCode::Key5 => Some(0x5),
+        KeyCode::Key6 => Some(0x6),
+        KeyCode::Key7 => Some(0x7),
+        KeyCode::Key8 => Some(0x8),
+        KeyCode::Key9 => Some(0x9),
+        KeyCode::A => Some(0xA),
+        KeyCode::B => Some(0xB),
+        KeyCode::C => Some(0xC),
+        KeyCode::D => Some(0xD),
+        KeyCode::E => Some(0xE),
+        KeyCode::F => Some(0xF),
+        _ => None,
+    }
+}
+
+// Runs the rom in a window, until it's closed
+pub fn window(args: Cli, rom: String) -> GameResult {
+    // Create a window.
+    let mut main_window = ContextBuilder::new("mygame", "myname").window_mode(
+        conf::WindowMode::default().dimensions(
+            DISP_WIDTH + DISP_WIDTH_CODE_PANEL + DISP_WIDTH_MEMORY_PANEL,
+            DISP_HEIGHT + DISP_HEIGHT_INFO_AREA,
+        ),
+    );
+    if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
+        let path = path::PathBuf::from(manifest_dir).join("resources");
+        println!("Adding 'resources' path {:?}", path);
+        main_window = main_window.add_resource_path(path);
+    }
+
+    // let main_window = ggez::ContextBuilder::new("main_window", "Thomas")
+    //     .window_setup(WindowSetup::default().title("CHIP8"))
+    //     .window_mode(
+    //         WindowMode::default()
+    //             .dimensions(
+    //                 DISP_WIDTH + DISP_WIDTH_INFO_AREA,
+    //                 DISP_HEIGHT + DISP_HEIGHT_INFO_AREA,
+    //             )
+    //             .resizable(true),
+    //     );
+
+    // Build our context
+    let (mut ctx, mut event_loop) = main_window.build().unwrap();
+
+    // Build our application
+    let hide_debug = args.hide_debug;
+    let mut app = App::new(&mut ctx, args, rom)?;
+    if hide_debug {
+        app.show_debug_area(&mut ctx, false);
+    }
+
+    // Run the application
+    run(&mut ctx, &mut event_loop, &mut app)
+}
+
+// ggez's event::run(), but passing the keys on with their scancodes, which
+// the keymap can be laid out by
+fn run(ctx: &mut Context, events_loop: &mut event::EventsLoop, app: &mut App) -> GameResult {
+    use ggez::event::winit_event::{
+        ElementState, Event, KeyboardInput, MouseScrollDelta, WindowEvent,
+    };
+    use ggez::event::EventHandler;
+
+    while ctx.continuing {
+        ctx.timer_context.tick();
+        events_loop.poll_events(|event| {
+            ctx.process_event(&event);
+            if let Event::WindowEvent { event, .. } = event {
+                match event {
+                    WindowEvent::CloseRequested if !app.quit_event(ctx) => event::quit(ctx),
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state,
+                                scancode,
+                                virtual_keycode,
+                                modifiers,
+                            },
+                        ..
+                    } => match state {
+                        ElementState::Pressed => {
+                            app.key_down(ctx, virtual_keycode, scancode, modifiers.into())
+                        }
+                        ElementState::Released => app.key_up(virtual_keycode, scancode),
+                    },
+                    WindowEvent::MouseWheel { delta, .. } => {
+                        let (x, y) = match delta {
+                            MouseScrollDelta::LineDelta(x, y) => (x, y),
+                            MouseScrollDelta::PixelDelta(position) => {
+                                (position.x as f32, position.y as f32)
+                            }
+                        };
+                        app.mouse_wheel_event(ctx, x, y);
+                    }
+                    WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button,
+                        ..
+                    } => {
+                        let position = input::mouse::position(ctx);
+                        app.mouse_button_down_event(ctx, button, position.x, position.y);
+                    }
+                    _ => (),
+                }
+            }
+        });
+        app.update(ctx)?;
+        app.draw(ctx)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,315 @@
+use crate::cpu::{Cpu, CpuError};
+use crate::image;
+use crate::movie::{Movie, MovieError};
+use crate::quirks::Platform;
+use crate::trace::open_trace;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+// Exit codes for r8-headless, so scripts can tell what happened
+pub const EXIT_OK: i32 = 0; // Reached --until-pc, or without it ran all the frames or exited
+pub const EXIT_NOT_REACHED: i32 = 1; // Ran out of frames or exited before reaching --until-pc
+pub const EXIT_FAULT: i32 = 2; // The cpu faulted
+pub const EXIT_ERROR: i32 = 3; // Bad arguments, or a file couldn't be read or written
+pub const EXIT_DESYNC: i32 = 4; // A replayed movie didn't match its recording
+
+#[derive(StructOpt)]
+#[structopt(name = "r8-headless", about = "Runs a CHIP-8 rom without a window")]
+struct HeadlessCli {
+    /// The rom to run
+    rom: PathBuf,
+
+    /// Quirks profile for ambiguous opcodes: default, vip, chip48, schip or xochip
+    #[structopt(
+        short = "q",
+        long = "quirks",
+        value_name = "quirks",
+        default_value = "default"
+    )]
+    platform: Platform,
+
+    /// Instructions to run per 60Hz frame
+    #[structopt(long, default_value = "11")]
+    ipf: usize,
+
+    /// Seed for the random number generator
+    #[structopt(long, default_value = "0")]
+    seed: u64,
+
+    /// Memory size in bytes (defaults to 64K with the xochip quirks, 4K otherwise)
+    #[structopt(long)]
+    memory: Option<usize>,
+
+    /// Frames to run for
+    #[structopt(long, default_value = "600")]
+    frames: usize,
+
+    /// Stop as soon as the PC reaches this address (hex)
+    #[structopt(long, parse(try_from_str = parse_address))]
+    until_pc: Option<usize>,
+
+    /// Input script: lines of "<frame> <held keys>", e.g. "30 5A" or "45 -"
+    #[structopt(long, conflicts_with = "replay")]
+    input: Option<PathBuf>,
+
+    /// Replay a movie file instead of running for --frames
+    #[structopt(long)]
+    replay: Option<PathBuf>,
+
+    /// Print the framebuffer as ASCII art after the registers
+    #[structopt(long)]
+    ascii: bool,
+
+    /// Write the framebuffer to a .pbm or .png file
+    #[structopt(long)]
+    screenshot: Option<PathBuf>,
+
+    /// Write a line to this file for every instruction run
+    #[structopt(long)]
+    trace: Option<PathBuf>,
+
+    /// Write the report to a file rather than stdout
+    #[structopt(short, long)]
+    output: Option<PathBuf>,
+}
+
+pub fn parse_address(s: &str) -> Result<usize, String> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    usize::from_str_radix(digits, 16).map_err(|_| format!("'{}' is not a hex address", s))
+}
+
+// Decimal, or hex with 0x in front
+pub fn parse_value(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    let value = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(digits) => usize::from_str_radix(digits, 16),
+        None => s.parse(),
+    };
+    value.map_err(|_| format!("'{}' is not a number", s))
+}
+
+// Keys held from a given frame onwards, as read from an input script:
+//   # comments and blank lines are ignored
+//   0  -     nothing held
+//   30 5     key 5 held from frame 30
+//   35 5A    keys 5 and A held from frame 35
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct InputScript {
+    pub changes: Vec<(usize, u16)>, // (frame, key mask), in frame order
+}
+
+impl InputScript {
+    pub fn parse(script: &str) -> Result<InputScript, String> {
+        let mut changes: Vec<(usize, u16)> = Vec::new();
+        for (n, line) in script.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let bad = |what: &str| format!("line {}: {} in '{}'", n + 1, what, line);
+            let mut fields = line.split_whitespace();
+            let frame = fields
+                .next()
+                .and_then(|f| f.parse::<usize>().ok())
+                .ok_or_else(|| bad("bad frame number"))?;
+            let keys = fields.next().ok_or_else(|| bad("missing keys"))?;
+            if fields.next().is_some() {
+                return Err(bad("too many fields"));
+            }
+            let mut mask = 0;
+            if keys != "-" {
+                for key in keys.chars() {
+                    let key = key.to_digit(16).ok_or_else(|| bad("bad key"))?;
+                    mask |= 1 << key;
+                }
+            }
+            if let Some((last, _)) = changes.last() {
+                if frame <= *last {
+                    return Err(bad("frames must go up"));
+                }
+            }
+            changes.push((frame, mask));
+        }
+        Ok(InputScript { changes })
+    }
+
+    // The keys held during a frame
+    pub fn keys_at(&self, frame: usize) -> u16 {
+        match self.changes.partition_point(|(from, _)| *from <= frame) {
+            0 => 0,
+            i => self.changes[i - 1].1,
+        }
+    }
+}
+
+// Why a headless run stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Frames,
+    Pc(usize),
+    Exited,
+    Fault(CpuError),
+}
+
+// Runs up to `frames` frames with scripted input, stopping early if the PC
+// reaches `until_pc` or the rom exits.  Returns why it stopped and how many
+// frames were started.
+pub fn run_headless(
+    cpu: &mut Cpu,
+    ipf: usize,
+    frames: usize,
+    until_pc: Option<usize>,
+    script: &InputScript,
+) -> (Stop, usize) {
+    // The check in the frame only sees the PC after each instruction
+    if until_pc == Some(cpu.pc) {
+        return (Stop::Pc(cpu.pc), 0);
+    }
+    for frame in 0..frames {
+        cpu.input.set_key_mask(script.keys_at(frame));
+        let stopped = cpu.run_frame_until(ipf, |cpu| cpu.exited || Some(cpu.pc) == until_pc);
+        match stopped {
+            Err(err) => return (Stop::Fault(err), frame + 1),
+            Ok(true) if cpu.exited => return (Stop::Exited, frame + 1),
+            Ok(true) => return (Stop::Pc(cpu.pc), frame + 1),
+            Ok(false) => (),
+        }
+    }
+    (Stop::Frames, frames)
+}
+
+// The registers and, optionally, the display, as plain text
+pub fn headless_report(cpu: &Cpu, stop: Stop, frames: usize, ascii: bool) -> String {
+    let mut out = format!("frames: {} cycles: {}\n", frames, cpu.cycles);
+    out += &match stop {
+        Stop::Frames => "stop: frames\n".to_string(),
+        Stop::Pc(pc) => format!("stop: pc {:#05X}\n", pc),
+        Stop::Exited => "stop: exited\n".to_string(),
+        Stop::Fault(err) => format!("stop: fault: {}\n", err),
+    };
+    out += &registers(cpu);
+    out += &format!("gfx hash: {:016X}\n", cpu.gfx_hash());
+    if ascii {
+        out += &image::to_ascii(&cpu.gfx);
+    }
+    out
+}
+
+// The registers and stack, three lines of them
+pub fn registers(cpu: &Cpu) -> String {
+    let mut out = format!(
+        "PC:{:#06X} I:{:#06X} SP:{} DT:{} ST:{}\n",
+        cpu.pc, cpu.i, cpu.sp, cpu.delay_timer, cpu.sound_timer
+    );
+    let v: Vec<String> = cpu
+        .v
+        .iter()
+        .enumerate()
+        .map(|(x, value)| format!("V{:X}:{:02X}", x, value))
+        .collect();
+    out += &v.join(" ");
+    out.push('\n');
+    let stack: Vec<String> = cpu.stack[..cpu.sp]
+        .iter()
+        .map(|address| format!("{:#05X}", address))
+        .collect();
+    out += &format!("stack: [{}]\n", stack.join(", "));
+    out
+}
+
+// The whole r8-headless program, returning the exit code
+pub fn go_headless() -> i32 {
+    let args = HeadlessCli::from_args();
+    match headless(&args) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("r8-headless: {}", err);
+            EXIT_ERROR
+        }
+    }
+}
+
+fn headless(args: &HeadlessCli) -> Result<i32, String> {
+    let mut cpu = Cpu::with_quirks(args.platform.quirks());
+    cpu.set_memory_size(args.memory.unwrap_or_else(|| args.platform.memory_size()));
+    cpu.seed_rng(args.seed);
+    let rom = fs::read(&args.rom).map_err(|e| format!("{}: {}", args.rom.display(), e))?;
+    cpu.load_rom_bytes(&rom).map_err(|e| e.to_string())?;
+
+    let mut ipf = args.ipf;
+    let mut frames = args.frames;
+    let mut script = InputScript::default();
+    let mut movie = None;
+    if let Some(path) = &args.replay {
+        let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let m = Movie::from_bytes(&bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+        m.start(&mut cpu).map_err(|e| e.to_string())?;
+        ipf = m.ipf;
+        frames = m.frames.len();
+        script.changes = m.frames.iter().cloned().enumerate().collect();
+        movie = Some(m);
+    } else if let Some(path) = &args.input {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        script = InputScript::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+
+    // Check the screenshot format now rather than after the whole run
+    let screenshot = match &args.screenshot {
+        Some(path) => {
+            let extension = path.extension().and_then(|e| e.to_str());
+            let dump: fn(&[Vec<u8>]) -> Vec<u8> = match extension {
+                Some("pbm") => image::to_pbm,
+                Some("png") => |gfx| image::to_png(gfx, 4),
+                _ => {
+                    return Err(format!(
+                        "{}: screenshots must be .pbm or .png",
+                        path.display()
+                    ))
+                }
+            };
+            Some((path, dump))
+        }
+        None => None,
+    };
+
+    if let Some(path) = &args.trace {
+        cpu.trace = Some(open_trace(path).map_err(|e| format!("{}: {}", path.display(), e))?);
+    }
+
+    let (stop, ran) = run_headless(&mut cpu, ipf, frames, args.until_pc, &script);
+    if let Some(trace) = &mut cpu.trace {
+        trace.flush().map_err(|e| e.to_string())?;
+    }
+
+    let mut out = headless_report(&cpu, stop, ran, args.ascii);
+    let mut code = match stop {
+        Stop::Fault(_) => EXIT_FAULT,
+        Stop::Frames | Stop::Exited if args.until_pc.is_some() => EXIT_NOT_REACHED,
+        _ => EXIT_OK,
+    };
+    if let Some(movie) = &movie {
+        match (stop, movie.verify(&cpu)) {
+            (Stop::Fault(_), _) => (),
+            (_, Ok(_)) => out += "replay: ok\n",
+            (_, Err(err @ MovieError::Desync { .. })) => {
+                out += &format!("replay: {}\n", err);
+                code = EXIT_DESYNC;
+            }
+            (_, Err(err)) => return Err(err.to_string()),
+        }
+    }
+
+    if let Some((path, dump)) = screenshot {
+        fs::write(path, dump(&cpu.gfx)).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+
+    match &args.output {
+        Some(path) => fs::write(path, out).map_err(|e| format!("{}: {}", path.display(), e))?,
+        None => {
+            let _ = std::io::stdout().write_all(out.as_bytes());
+        }
+    }
+    Ok(code)
+}
@@ -0,0 +1,125 @@
+// Dumps of the framebuffer for tools without a window: ASCII art for
+// terminals and logs, and PBM or PNG files.  Each pixel is the plane mask
+// from Cpu::gfx, so 0 is off and 1 - 3 are the XO-CHIP colours.
+
+// One character per pixel, a line per row
+pub fn to_ascii(gfx: &[Vec<u8>]) -> String {
+    let mut out = String::new();
+    for row in gfx {
+        for pixel in row {
+            out.push(match pixel {
+                0 => '.',
+                1 => '#',
+                2 => '+',
+                _ => '@',
+            });
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// Plain (P1) PBM: black where any plane is lit
+pub fn to_pbm(gfx: &[Vec<u8>]) -> Vec<u8> {
+    let (width, height) = dimensions(gfx);
+    let mut out = format!("P1\n{} {}\n", width, height);
+    for row in gfx {
+        let bits: Vec<&str> = row
+            .iter()
+            .map(|pixel| if *pixel == 0 { "0" } else { "1" })
+            .collect();
+        out += &bits.join(" ");
+        out.push('\n');
+    }
+    out.into_bytes()
+}
+
+// An 8 bit greyscale PNG, scaled up `scale` times.  The image data is
+// stored uncompressed, which keeps the encoder tiny and is still small
+// at these sizes.
+pub fn to_png(gfx: &[Vec<u8>], scale: usize) -> Vec<u8> {
+    let scale = scale.max(1);
+    let (width, height) = dimensions(gfx);
+    let (width, height) = (width * scale, height * scale);
+
+    // Each scanline starts with filter type 0 (none)
+    let mut raw = Vec::with_capacity((width + 1) * height);
+    for row in gfx {
+        let mut line = vec![0];
+        for pixel in row {
+            let grey = match pixel {
+                0 => 0xFF,
+                1 => 0x00,
+                2 => 0xAA,
+                _ => 0x55,
+            };
+            line.extend(std::iter::repeat_n(grey, scale));
+        }
+        for _ in 0..scale {
+            raw.extend_from_slice(&line);
+        }
+    }
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 0, 0, 0, 0]); // 8 bit greyscale, no interlacing
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    png_chunk(&mut png, b"IHDR", &header);
+    png_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    png_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn dimensions(gfx: &[Vec<u8>]) -> (usize, usize) {
+    (gfx.first().map_or(0, |row| row.len()), gfx.len())
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// A zlib stream of uncompressed deflate blocks, at most 65535 bytes each
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none() as u8;
+        let len = block.len() as u16;
+        out.push(last);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(0xFFFF_FFFF, |crc, b| {
+        (0..8).fold(crc ^ u32::from(*b), |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1_u32, 0_u32), |(a, b), byte| {
+        let a = (a + u32::from(*byte)) % 65521;
+        (a, (b + a) % 65521)
+    });
+    (b << 16) | a
+}
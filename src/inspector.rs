@@ -0,0 +1,91 @@
+use crate::cpu::Cpu;
+use crate::disassembler::Disassembly;
+use crate::trace::TraceRegisters;
+
+// The register inspector in the info area under the display: V0 - VF with
+// the ones the last step or frame changed picked out, I and the bytes it
+// points at, the call stack and the timers.  This is the model; App draws it.
+
+pub const I_BYTES: usize = 8; // Bytes shown from I onwards
+
+// Layout, relative to the top left of the inspector.  The first row has PC
+// and I, then two rows of eight registers, then the timers, the stack and
+// the keypad.
+pub const ROW_HEIGHT: f32 = 16.0;
+pub const CELL_WIDTH: f32 = 64.0;
+#[cfg(feature = "gui")]
+pub const ROWS: usize = 6;
+
+pub struct Inspector {
+    pub hex: bool,           // Registers in hex, or decimal
+    pub changed: [bool; 16], // The V registers the cpu last changed
+    pub i_changed: bool,
+    previous: TraceRegisters, // As of the last change
+    cycles: u64,
+}
+
+impl Inspector {
+    pub fn new(cpu: &Cpu) -> Inspector {
+        Inspector {
+            hex: true,
+            changed: [false; 16],
+            i_changed: false,
+            previous: TraceRegisters::of(cpu),
+            cycles: cpu.cycles,
+        }
+    }
+
+    // Called once a frame.  The highlights last until the cpu runs again,
+    // so they still show what the last step did while paused.
+    pub fn observe(&mut self, cpu: &Cpu) {
+        let now = TraceRegisters::of(cpu);
+        if now == self.previous && cpu.cycles == self.cycles {
+            return;
+        }
+        for (x, changed) in self.changed.iter_mut().enumerate() {
+            *changed = now.v[x] != self.previous.v[x];
+        }
+        self.i_changed = now.i != self.previous.i;
+        self.previous = now;
+        self.cycles = cpu.cycles;
+    }
+
+    pub fn value(&self, value: u8) -> String {
+        if self.hex {
+            format!("{:02X}", value)
+        } else {
+            format!("{:3}", value)
+        }
+    }
+
+    // The bytes from I on, as far as memory goes
+    pub fn i_bytes(&self, cpu: &Cpu) -> String {
+        let end = (cpu.i + I_BYTES).min(cpu.memory.len());
+        let bytes: Vec<String> = cpu.memory[cpu.i.min(end)..end]
+            .iter()
+            .map(|&byte| self.value(byte))
+            .collect();
+        bytes.join(" ")
+    }
+}
+
+// The register in the grid under a point
+pub fn register_at(x: f32, y: f32) -> Option<usize> {
+    if !(0.0..CELL_WIDTH * 8.0).contains(&x) || !(ROW_HEIGHT..ROW_HEIGHT * 3.0).contains(&y) {
+        return None;
+    }
+    Some(((y / ROW_HEIGHT) as usize - 1) * 8 + (x / CELL_WIDTH) as usize)
+}
+
+// The return addresses on the stack, innermost first, with the label they
+// fall under
+pub fn call_stack(cpu: &Cpu, disassembly: &Disassembly) -> Vec<String> {
+    cpu.stack[..cpu.sp.min(cpu.stack.len())]
+        .iter()
+        .rev()
+        .map(|&ret| match disassembly.labels.range(..=ret).next_back() {
+            Some(_) => format!("{:#05X} {}", ret, disassembly.locate(ret)),
+            None => format!("{:#05X}", ret),
+        })
+        .collect()
+}
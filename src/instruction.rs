@@ -0,0 +1,276 @@
+use std::fmt;
+
+// A decoded CHIP-8 instruction.  Register operands (x, y) are indexes into
+// V[], addresses are 12 bit, and bytes/nibbles are the immediate values.
+//
+// Mnemonics follow Cowgod's Chip-8 technical reference, which also covers
+// the SUPER-CHIP additions: http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
+// The XO-CHIP additions are from http://johnearnest.github.io/Octo/docs/XO-ChipSpecification.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Sys(usize),               // 0nnn - Jump to machine code at nnn (ignored)
+    ScrollDown(usize),        // 00Cn - Scroll the display down n pixels (SUPER-CHIP)
+    ScrollUp(usize),          // 00Dn - Scroll the display up n pixels (XO-CHIP)
+    Cls,                      // 00E0 - Clear the display
+    Ret,                      // 00EE - Return from a subroutine
+    ScrollRight,              // 00FB - Scroll the display right 4 pixels (SUPER-CHIP)
+    ScrollLeft,               // 00FC - Scroll the display left 4 pixels (SUPER-CHIP)
+    Exit,                     // 00FD - Exit the interpreter (SUPER-CHIP)
+    Low,                      // 00FE - Switch to 64x32 lores mode (SUPER-CHIP)
+    High,                     // 00FF - Switch to 128x64 hires mode (SUPER-CHIP)
+    Jp(usize),                // 1nnn - Jump to nnn
+    Call(usize),              // 2nnn - Call subroutine at nnn
+    SeByte(usize, u8),        // 3xkk - Skip next if Vx = kk
+    SneByte(usize, u8),       // 4xkk - Skip next if Vx != kk
+    SeReg(usize, usize),      // 5xy0 - Skip next if Vx = Vy
+    SaveRange(usize, usize),  // 5xy2 - Store Vx..Vy in memory starting at I (XO-CHIP)
+    LoadRange(usize, usize),  // 5xy3 - Read Vx..Vy from memory starting at I (XO-CHIP)
+    LdByte(usize, u8),        // 6xkk - Vx = kk
+    AddByte(usize, u8),       // 7xkk - Vx = Vx + kk
+    LdReg(usize, usize),      // 8xy0 - Vx = Vy
+    Or(usize, usize),         // 8xy1 - Vx = Vx OR Vy
+    And(usize, usize),        // 8xy2 - Vx = Vx AND Vy
+    Xor(usize, usize),        // 8xy3 - Vx = Vx XOR Vy
+    AddReg(usize, usize),     // 8xy4 - Vx = Vx + Vy, VF = carry
+    Sub(usize, usize),        // 8xy5 - Vx = Vx - Vy, VF = NOT borrow
+    Shr(usize, usize),        // 8xy6 - Vx = Vx SHR 1
+    Subn(usize, usize),       // 8xy7 - Vx = Vy - Vx, VF = NOT borrow
+    Shl(usize, usize),        // 8xyE - Vx = Vx SHL 1
+    SneReg(usize, usize),     // 9xy0 - Skip next if Vx != Vy
+    LdI(usize),               // Annn - I = nnn
+    JpV0(usize),              // Bnnn - Jump to nnn + V0
+    Rnd(usize, u8),           // Cxkk - Vx = random byte AND kk
+    Drw(usize, usize, usize), // Dxyn - Draw n-byte sprite at (Vx, Vy), or 16x16 if n is 0
+    Skp(usize),               // Ex9E - Skip next if key Vx is pressed
+    Sknp(usize),              // ExA1 - Skip next if key Vx is not pressed
+    LdILong,                  // F000 nnnn - I = the 16 bit word that follows (XO-CHIP)
+    Plane(usize),             // Fn01 - Select drawing planes n (XO-CHIP)
+    Audio,                    // F002 - Load the 16 byte audio pattern from I (XO-CHIP)
+    LdVxDt(usize),            // Fx07 - Vx = delay timer
+    LdVxK(usize),             // Fx0A - Wait for a key press, store it in Vx
+    LdDtVx(usize),            // Fx15 - delay timer = Vx
+    LdStVx(usize),            // Fx18 - sound timer = Vx
+    Pitch(usize),             // Fx3A - Audio pattern playback pitch = Vx (XO-CHIP)
+    AddIVx(usize),            // Fx1E - I = I + Vx
+    LdFVx(usize),             // Fx29 - I = location of the font sprite for Vx
+    LdHfVx(usize),            // Fx30 - I = location of the big font sprite for Vx (SUPER-CHIP)
+    LdBVx(usize),             // Fx33 - BCD of Vx into I, I+1, I+2
+    LdIVx(usize),             // Fx55 - Store V0..Vx in memory starting at I
+    LdVxI(usize),             // Fx65 - Read V0..Vx from memory starting at I
+    LdRVx(usize),             // Fx75 - Store V0..Vx in the RPL user flags (SUPER-CHIP)
+    LdVxR(usize),             // Fx85 - Read V0..Vx from the RPL user flags (SUPER-CHIP)
+}
+
+impl Instruction {
+    // Turns a raw opcode into an instruction, or None if the opcode
+    // is not one we know about.
+    pub fn decode(opcode: u16) -> Option<Instruction> {
+        let nibbles = (
+            (opcode & 0xF000) >> 12,
+            (opcode & 0x0F00) >> 8,
+            (opcode & 0x00F0) >> 4,
+            opcode & 0x000F,
+        );
+        let nnn = (opcode & 0x0FFF) as usize;
+        let kk = (opcode & 0x00FF) as u8;
+        let x = nibbles.1 as usize;
+        let y = nibbles.2 as usize;
+        let n = nibbles.3 as usize;
+
+        let instruction = match nibbles {
+            (0x00, 0x00, 0x0C, _) => Instruction::ScrollDown(n),
+            (0x00, 0x00, 0x0D, _) => Instruction::ScrollUp(n),
+            (0x00, 0x00, 0x0E, 0x00) => Instruction::Cls,
+            (0x00, 0x00, 0x0E, 0x0E) => Instruction::Ret,
+            (0x00, 0x00, 0x0F, 0x0B) => Instruction::ScrollRight,
+            (0x00, 0x00, 0x0F, 0x0C) => Instruction::ScrollLeft,
+            (0x00, 0x00, 0x0F, 0x0D) => Instruction::Exit,
+            (0x00, 0x00, 0x0F, 0x0E) => Instruction::Low,
+            (0x00, 0x00, 0x0F, 0x0F) => Instruction::High,
+            (0x00, _, _, _) => Instruction::Sys(nnn),
+            (0x01, _, _, _) => Instruction::Jp(nnn),
+            (0x02, _, _, _) => Instruction::Call(nnn),
+            (0x03, _, _, _) => Instruction::SeByte(x, kk),
+            (0x04, _, _, _) => Instruction::SneByte(x, kk),
+            (0x05, _, _, 0x00) => Instruction::SeReg(x, y),
+            (0x05, _, _, 0x02) => Instruction::SaveRange(x, y),
+            (0x05, _, _, 0x03) => Instruction::LoadRange(x, y),
+            (0x06, _, _, _) => Instruction::LdByte(x, kk),
+            (0x07, _, _, _) => Instruction::AddByte(x, kk),
+            (0x08, _, _, 0x00) => Instruction::LdReg(x, y),
+            (0x08, _, _, 0x01) => Instruction::Or(x, y),
+            (0x08, _, _, 0x02) => Instruction::And(x, y),
+            (0x08, _, _, 0x03) => Instruction::Xor(x, y),
+            (0x08, _, _, 0x04) => Instruction::AddReg(x, y),
+            (0x08, _, _, 0x05) => Instruction::Sub(x, y),
+            (0x08, _, _, 0x06) => Instruction::Shr(x, y),
+            (0x08, _, _, 0x07) => Instruction::Subn(x, y),
+            (0x08, _, _, 0x0E) => Instruction::Shl(x, y),
+            (0x09, _, _, 0x00) => Instruction::SneReg(x, y),
+            (0x0A, _, _, _) => Instruction::LdI(nnn),
+            (0x0B, _, _, _) => Instruction::JpV0(nnn),
+            (0x0C, _, _, _) => Instruction::Rnd(x, kk),
+            (0x0D, _, _, _) => Instruction::Drw(x, y, n),
+            (0x0E, _, 0x09, 0x0E) => Instruction::Skp(x),
+            (0x0E, _, 0x0A, 0x01) => Instruction::Sknp(x),
+            (0x0F, 0x00, 0x00, 0x00) => Instruction::LdILong,
+            (0x0F, _, 0x00, 0x01) => Instruction::Plane(x),
+            (0x0F, 0x00, 0x00, 0x02) => Instruction::Audio,
+            (0x0F, _, 0x00, 0x07) => Instruction::LdVxDt(x),
+            (0x0F, _, 0x00, 0x0A) => Instruction::LdVxK(x),
+            (0x0F, _, 0x01, 0x05) => Instruction::LdDtVx(x),
+            (0x0F, _, 0x01, 0x08) => Instruction::LdStVx(x),
+            (0x0F, _, 0x03, 0x0A) => Instruction::Pitch(x),
+            (0x0F, _, 0x01, 0x0E) => Instruction::AddIVx(x),
+            (0x0F, _, 0x02, 0x09) => Instruction::LdFVx(x),
+            (0x0F, _, 0x03, 0x00) => Instruction::LdHfVx(x),
+            (0x0F, _, 0x03, 0x03) => Instruction::LdBVx(x),
+            (0x0F, _, 0x05, 0x05) => Instruction::LdIVx(x),
+            (0x0F, _, 0x06, 0x05) => Instruction::LdVxI(x),
+            (0x0F, _, 0x07, 0x05) => Instruction::LdRVx(x),
+            (0x0F, _, 0x08, 0x05) => Instruction::LdVxR(x),
+            _ => return None,
+        };
+
+        Some(instruction)
+    }
+
+    // How many bytes the instruction takes up in memory; F000 is followed
+    // by its 16 bit address.
+    pub fn size(&self) -> usize {
+        match *self {
+            Instruction::LdILong => 4,
+            _ => 2,
+        }
+    }
+
+    // Turns an instruction back into its raw opcode.  For every opcode that
+    // decodes, encode(decode(opcode)) == opcode.
+    pub fn encode(&self) -> u16 {
+        fn nnn(prefix: u16, nnn: usize) -> u16 {
+            prefix | (nnn as u16 & 0x0FFF)
+        }
+        fn xkk(prefix: u16, x: usize, kk: u8) -> u16 {
+            prefix | ((x as u16 & 0x0F) << 8) | u16::from(kk)
+        }
+        fn xyn(prefix: u16, x: usize, y: usize, n: usize) -> u16 {
+            prefix | ((x as u16 & 0x0F) << 8) | ((y as u16 & 0x0F) << 4) | (n as u16 & 0x0F)
+        }
+        fn x(prefix: u16, x: usize, suffix: u16) -> u16 {
+            prefix | ((x as u16 & 0x0F) << 8) | suffix
+        }
+
+        match *self {
+            Instruction::Sys(a) => nnn(0x0000, a),
+            Instruction::ScrollDown(n) => 0x00C0 | (n as u16 & 0x0F),
+            Instruction::ScrollUp(n) => 0x00D0 | (n as u16 & 0x0F),
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::Low => 0x00FE,
+            Instruction::High => 0x00FF,
+            Instruction::Jp(a) => nnn(0x1000, a),
+            Instruction::Call(a) => nnn(0x2000, a),
+            Instruction::SeByte(vx, kk) => xkk(0x3000, vx, kk),
+            Instruction::SneByte(vx, kk) => xkk(0x4000, vx, kk),
+            Instruction::SeReg(vx, vy) => xyn(0x5000, vx, vy, 0x0),
+            Instruction::SaveRange(vx, vy) => xyn(0x5000, vx, vy, 0x2),
+            Instruction::LoadRange(vx, vy) => xyn(0x5000, vx, vy, 0x3),
+            Instruction::LdByte(vx, kk) => xkk(0x6000, vx, kk),
+            Instruction::AddByte(vx, kk) => xkk(0x7000, vx, kk),
+            Instruction::LdReg(vx, vy) => xyn(0x8000, vx, vy, 0x0),
+            Instruction::Or(vx, vy) => xyn(0x8000, vx, vy, 0x1),
+            Instruction::And(vx, vy) => xyn(0x8000, vx, vy, 0x2),
+            Instruction::Xor(vx, vy) => xyn(0x8000, vx, vy, 0x3),
+            Instruction::AddReg(vx, vy) => xyn(0x8000, vx, vy, 0x4),
+            Instruction::Sub(vx, vy) => xyn(0x8000, vx, vy, 0x5),
+            Instruction::Shr(vx, vy) => xyn(0x8000, vx, vy, 0x6),
+            Instruction::Subn(vx, vy) => xyn(0x8000, vx, vy, 0x7),
+            Instruction::Shl(vx, vy) => xyn(0x8000, vx, vy, 0xE),
+            Instruction::SneReg(vx, vy) => xyn(0x9000, vx, vy, 0x0),
+            Instruction::LdI(a) => nnn(0xA000, a),
+            Instruction::JpV0(a) => nnn(0xB000, a),
+            Instruction::Rnd(vx, kk) => xkk(0xC000, vx, kk),
+            Instruction::Drw(vx, vy, n) => xyn(0xD000, vx, vy, n),
+            Instruction::Skp(vx) => x(0xE000, vx, 0x9E),
+            Instruction::Sknp(vx) => x(0xE000, vx, 0xA1),
+            Instruction::LdILong => 0xF000,
+            Instruction::Plane(n) => x(0xF000, n, 0x01),
+            Instruction::Audio => 0xF002,
+            Instruction::LdVxDt(vx) => x(0xF000, vx, 0x07),
+            Instruction::LdVxK(vx) => x(0xF000, vx, 0x0A),
+            Instruction::LdDtVx(vx) => x(0xF000, vx, 0x15),
+            Instruction::LdStVx(vx) => x(0xF000, vx, 0x18),
+            Instruction::Pitch(vx) => x(0xF000, vx, 0x3A),
+            Instruction::AddIVx(vx) => x(0xF000, vx, 0x1E),
+            Instruction::LdFVx(vx) => x(0xF000, vx, 0x29),
+            Instruction::LdHfVx(vx) => x(0xF000, vx, 0x30),
+            Instruction::LdBVx(vx) => x(0xF000, vx, 0x33),
+            Instruction::LdIVx(vx) => x(0xF000, vx, 0x55),
+            Instruction::LdVxI(vx) => x(0xF000, vx, 0x65),
+            Instruction::LdRVx(vx) => x(0xF000, vx, 0x75),
+            Instruction::LdVxR(vx) => x(0xF000, vx, 0x85),
+        }
+    }
+}
+
+// Renders the instruction as a Cowgod style mnemonic, e.g. "LD V1, 0x0F"
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Sys(a) => write!(f, "SYS {:#05X}", a),
+            Instruction::ScrollDown(n) => write!(f, "SCD {}", n),
+            Instruction::ScrollUp(n) => write!(f, "SCU {}", n),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Low => write!(f, "LOW"),
+            Instruction::High => write!(f, "HIGH"),
+            Instruction::Jp(a) => write!(f, "JP {:#05X}", a),
+            Instruction::Call(a) => write!(f, "CALL {:#05X}", a),
+            Instruction::SeByte(x, kk) => write!(f, "SE V{:X}, {:#04X}", x, kk),
+            Instruction::SneByte(x, kk) => write!(f, "SNE V{:X}, {:#04X}", x, kk),
+            Instruction::SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange(x, y) => write!(f, "SAVE V{:X}-V{:X}", x, y),
+            Instruction::LoadRange(x, y) => write!(f, "LOAD V{:X}-V{:X}", x, y),
+            Instruction::LdByte(x, kk) => write!(f, "LD V{:X}, {:#04X}", x, kk),
+            Instruction::AddByte(x, kk) => write!(f, "ADD V{:X}, {:#04X}", x, kk),
+            Instruction::LdReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LdI(a) => write!(f, "LD I, {:#05X}", a),
+            Instruction::JpV0(a) => write!(f, "JP V0, {:#05X}", a),
+            Instruction::Rnd(x, kk) => write!(f, "RND V{:X}, {:#04X}", x, kk),
+            Instruction::Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Skp(x) => write!(f, "SKP V{:X}", x),
+            Instruction::Sknp(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LdILong => write!(f, "LD I, LONG"),
+            Instruction::Plane(n) => write!(f, "PLANE {}", n),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::Pitch(x) => write!(f, "LD PITCH, V{:X}", x),
+            Instruction::AddIVx(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LdFVx(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::LdHfVx(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::LdBVx(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::LdIVx(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LdVxI(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::LdRVx(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::LdVxR(x) => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
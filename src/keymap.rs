@@ -0,0 +1,435 @@
+use ggez::event::KeyCode;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// Which keyboard keys press which CHIP-8 keys.  The map starts from a
+// layout, then takes the bindings from the user's keymap file, then any for
+// the rom being run.  The file is plain text:
+//
+//   # CHIP-8 key (0 - F) = the keys that press it, by name or sc:scancode
+//   layout = qwerty
+//   5 = W, Up
+//   [pong.ch8]
+//   1 = Key1, sc:72
+//
+// A [section] holds the overrides for the rom with that file name.  Each
+// CHIP-8 key given replaces all of that key's bindings.
+
+// The CHIP-8 keypad, as laid out on the COSMAC VIP
+pub const KEYPAD: [usize; 16] = [
+    0x1, 0x2, 0x3, 0xC, //
+    0x4, 0x5, 0x6, 0xD, //
+    0x7, 0x8, 0x9, 0xE, //
+    0xA, 0x0, 0xB, 0xF,
+];
+
+// The 1234 / QWER / ASDF / ZXCV block it sits on, by position
+const QWERTY: [KeyCode; 16] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Q,
+    KeyCode::W,
+    KeyCode::E,
+    KeyCode::R,
+    KeyCode::A,
+    KeyCode::S,
+    KeyCode::D,
+    KeyCode::F,
+    KeyCode::Z,
+    KeyCode::X,
+    KeyCode::C,
+    KeyCode::V,
+];
+
+// The same block by scancode, which stays put whatever the layout
+#[cfg(not(target_os = "macos"))]
+const SCANCODES: [u32; 16] = [2, 3, 4, 5, 16, 17, 18, 19, 30, 31, 32, 33, 44, 45, 46, 47];
+#[cfg(target_os = "macos")]
+const SCANCODES: [u32; 16] = [
+    0x12, 0x13, 0x14, 0x15, 0x0C, 0x0D, 0x0E, 0x0F, 0x00, 0x01, 0x02, 0x03, 0x06, 0x07, 0x08, 0x09,
+];
+
+// The keys that can be bound by name; any other by its scancode
+const NAMED: [KeyCode; 76] = [
+    KeyCode::Key0,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::A,
+    KeyCode::B,
+    KeyCode::C,
+    KeyCode::D,
+    KeyCode::E,
+    KeyCode::F,
+    KeyCode::G,
+    KeyCode::H,
+    KeyCode::I,
+    KeyCode::J,
+    KeyCode::K,
+    KeyCode::L,
+    KeyCode::M,
+    KeyCode::N,
+    KeyCode::O,
+    KeyCode::P,
+    KeyCode::Q,
+    KeyCode::R,
+    KeyCode::S,
+    KeyCode::T,
+    KeyCode::U,
+    KeyCode::V,
+    KeyCode::W,
+    KeyCode::X,
+    KeyCode::Y,
+    KeyCode::Z,
+    KeyCode::Numpad0,
+    KeyCode::Numpad1,
+    KeyCode::Numpad2,
+    KeyCode::Numpad3,
+    KeyCode::Numpad4,
+    KeyCode::Numpad5,
+    KeyCode::Numpad6,
+    KeyCode::Numpad7,
+    KeyCode::Numpad8,
+    KeyCode::Numpad9,
+    KeyCode::Add,
+    KeyCode::Subtract,
+    KeyCode::Multiply,
+    KeyCode::Divide,
+    KeyCode::Decimal,
+    KeyCode::NumpadEnter,
+    KeyCode::Left,
+    KeyCode::Up,
+    KeyCode::Right,
+    KeyCode::Down,
+    KeyCode::Space,
+    KeyCode::Return,
+    KeyCode::Tab,
+    KeyCode::LShift,
+    KeyCode::RShift,
+    KeyCode::LControl,
+    KeyCode::RControl,
+    KeyCode::LAlt,
+    KeyCode::RAlt,
+    KeyCode::Apostrophe,
+    KeyCode::Backslash,
+    KeyCode::Comma,
+    KeyCode::Equals,
+    KeyCode::Grave,
+    KeyCode::LBracket,
+    KeyCode::RBracket,
+    KeyCode::Minus,
+    KeyCode::Period,
+    KeyCode::Semicolon,
+    KeyCode::Slash,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PhysicalKey {
+    Code(KeyCode),
+    Scancode(u32),
+}
+
+impl fmt::Display for PhysicalKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PhysicalKey::Code(code) => write!(f, "{:?}", code),
+            PhysicalKey::Scancode(scancode) => write!(f, "sc:{}", scancode),
+        }
+    }
+}
+
+impl FromStr for PhysicalKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PhysicalKey, String> {
+        if let Some(scancode) = s.strip_prefix("sc:") {
+            return scancode
+                .parse()
+                .map(PhysicalKey::Scancode)
+                .map_err(|_| format!("bad scancode: {}", s));
+        }
+        NAMED
+            .iter()
+            .find(|code| format!("{:?}", code).eq_ignore_ascii_case(s))
+            .map(|&code| PhysicalKey::Code(code))
+            .ok_or_else(|| format!("unknown key: {} (use sc:scancode for others)", s))
+    }
+}
+
+// Where the keypad starts out.  Qwerty goes by the letters on the keys,
+// scancode by where they are, which suits AZERTY, Dvorak and the rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Qwerty,
+    Scancode,
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Layout::Qwerty => write!(f, "qwerty"),
+            Layout::Scancode => write!(f, "scancode"),
+        }
+    }
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Layout, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "qwerty" => Ok(Layout::Qwerty),
+            "scancode" | "positional" | "azerty" | "dvorak" => Ok(Layout::Scancode),
+            _ => Err(format!(
+                "unknown layout: {} (expected qwerty or scancode)",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    pub layout: Layout,
+    pub bindings: BTreeMap<PhysicalKey, usize>, // Keyboard key -> CHIP-8 key
+}
+
+impl Keymap {
+    pub fn new(layout: Layout) -> Keymap {
+        let bindings = KEYPAD
+            .iter()
+            .enumerate()
+            .map(|(position, &key)| match layout {
+                Layout::Qwerty => (PhysicalKey::Code(QWERTY[position]), key),
+                Layout::Scancode => (PhysicalKey::Scancode(SCANCODES[position]), key),
+            })
+            .collect();
+        Keymap { layout, bindings }
+    }
+
+    // The CHIP-8 key a keyboard key presses, if any
+    pub fn key(&self, code: Option<KeyCode>, scancode: u32) -> Option<usize> {
+        let by_code = code.and_then(|code| self.bindings.get(&PhysicalKey::Code(code)));
+        by_code
+            .or_else(|| self.bindings.get(&PhysicalKey::Scancode(scancode)))
+            .copied()
+    }
+
+    pub fn keys_for(&self, key: usize) -> Vec<PhysicalKey> {
+        self.bindings
+            .iter()
+            .filter(|(_, &bound)| bound == key)
+            .map(|(&physical, _)| physical)
+            .collect()
+    }
+
+    // Replaces all the bindings for a CHIP-8 key
+    pub fn set_keys(&mut self, key: usize, keys: &[PhysicalKey]) {
+        self.bindings.retain(|_, bound| *bound != key);
+        for &physical in keys {
+            self.bindings.insert(physical, key);
+        }
+    }
+
+    // How to record a keyboard key for this layout: by scancode for the
+    // scancode layout, or for keys without a name
+    pub fn physical(&self, code: Option<KeyCode>, scancode: u32) -> PhysicalKey {
+        match code {
+            Some(code) if self.layout == Layout::Qwerty && NAMED.contains(&code) => {
+                PhysicalKey::Code(code)
+            }
+            _ => PhysicalKey::Scancode(scancode),
+        }
+    }
+
+    // Every binding, for saving
+    pub fn section(&self) -> Section {
+        Section {
+            layout: Some(self.layout),
+            bindings: (0..16).map(|key| (key, self.keys_for(key))).collect(),
+        }
+    }
+}
+
+// The settings in the file, or in one rom's part of it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Section {
+    pub layout: Option<Layout>,
+    pub bindings: BTreeMap<usize, Vec<PhysicalKey>>,
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(layout) = self.layout {
+            writeln!(f, "layout = {}", layout)?;
+        }
+        for (key, keys) in &self.bindings {
+            let keys: Vec<String> = keys.iter().map(|k| k.to_string()).collect();
+            writeln!(f, "{:X} = {}", key, keys.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeymapConfig {
+    pub global: Section,
+    pub roms: BTreeMap<String, Section>, // Rom file name -> its overrides
+}
+
+impl KeymapConfig {
+    pub fn parse(text: &str) -> Result<KeymapConfig, String> {
+        let mut config = KeymapConfig::default();
+        let mut rom: Option<String> = None;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                let name = line[1..line.len() - 1].trim().to_string();
+                config.roms.entry(name.clone()).or_default();
+                rom = Some(name);
+                continue;
+            }
+            let section = match &rom {
+                Some(name) => config.roms.get_mut(name).unwrap(),
+                None => &mut config.global,
+            };
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| error(format!("expected key = value: {}", line)))?;
+            let (name, value) = (name.trim(), value.trim());
+            if name.eq_ignore_ascii_case("layout") {
+                section.layout = Some(value.parse().map_err(error)?);
+                continue;
+            }
+            let key = match usize::from_str_radix(name, 16) {
+                Ok(key) if key < 16 => key,
+                _ => return Err(error(format!("not a CHIP-8 key (0 - F): {}", name))),
+            };
+            let keys = value
+                .split(',')
+                .map(|k| k.trim())
+                .filter(|k| !k.is_empty())
+                .map(|k| k.parse())
+                .collect::<Result<Vec<PhysicalKey>, String>>()
+                .map_err(error)?;
+            section.bindings.insert(key, keys);
+        }
+        Ok(config)
+    }
+
+    // A missing file is just no settings
+    pub fn load(path: &Path) -> Result<KeymapConfig, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => KeymapConfig::parse(&text),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(KeymapConfig::default()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_string())
+    }
+
+    // The keymap for a rom, by its file name
+    pub fn keymap(&self, rom: &str) -> Keymap {
+        let overrides = self.roms.get(rom);
+        let layout = overrides
+            .and_then(|section| section.layout)
+            .or(self.global.layout)
+            .unwrap_or(Layout::Qwerty);
+        let mut keymap = Keymap::new(layout);
+        for section in std::iter::once(&self.global).chain(overrides) {
+            for (&key, keys) in &section.bindings {
+                keymap.set_keys(key, keys);
+            }
+        }
+        keymap
+    }
+}
+
+impl fmt::Display for KeymapConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "# r8 keymap: CHIP-8 key (0 - F) = the keys that press it, by name or sc:scancode"
+        )?;
+        write!(f, "{}", self.global)?;
+        for (rom, section) in &self.roms {
+            writeln!(f, "\n[{}]", rom)?;
+            write!(f, "{}", section)?;
+        }
+        Ok(())
+    }
+}
+
+// Where the keymap lives unless --keymap says otherwise
+pub fn default_keymap_path() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("r8").join("keymap.conf"))
+}
+
+// The rebinding screen walks the keypad.  The first key pressed for a
+// CHIP-8 key replaces its bindings and any more are added to them.
+pub struct Rebinder {
+    pub keymap: Keymap,
+    pub position: usize, // Into KEYPAD
+    fresh: bool,         // Nothing pressed yet for this CHIP-8 key
+}
+
+impl Rebinder {
+    pub fn new(keymap: Keymap) -> Rebinder {
+        Rebinder {
+            keymap,
+            position: 0,
+            fresh: true,
+        }
+    }
+
+    // The CHIP-8 key being bound
+    pub fn current(&self) -> usize {
+        KEYPAD[self.position]
+    }
+
+    pub fn press(&mut self, physical: PhysicalKey) {
+        let key = self.current();
+        if self.fresh {
+            self.keymap.set_keys(key, &[]);
+            self.fresh = false;
+        }
+        self.keymap.bindings.insert(physical, key);
+    }
+
+    pub fn clear(&mut self) {
+        self.keymap.set_keys(self.current(), &[]);
+        self.fresh = true;
+    }
+
+    // On to another key, wrapping round the keypad
+    pub fn advance(&mut self, by: isize) {
+        self.position = (self.position as isize + by).rem_euclid(KEYPAD.len() as isize) as usize;
+        self.fresh = true;
+    }
+}
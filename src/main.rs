@@ -1,6 +1,4 @@
-use ggez::*;
-
-fn main() -> GameResult {
-    lib::go()?;
-    Ok(())
+// The emulator window, or one of the tools.  See `r8 --help`.
+fn main() {
+    std::process::exit(lib::go());
 }
@@ -0,0 +1,204 @@
+use crate::cpu::Cpu;
+use crate::fonts::{BIG_FONT_ADDR, BIG_FONT_SET};
+use std::ops::Range;
+
+// The memory panel beside the display: a scrolling hex and ASCII dump with
+// the fonts, the rom, I and the stack picked out, the bytes written lately
+// highlighted, and a selected byte that can be typed over while paused.
+// This is the model; App draws it and feeds it the mouse and keys.
+
+pub const BYTES_PER_ROW: usize = 16;
+pub const RECENT_FRAMES: u32 = 60; // How long a write stays highlighted
+
+// Layout, relative to the top left of the rows
+pub const ROW_HEIGHT: f32 = 15.0;
+pub const ADDRESS_WIDTH: f32 = 44.0;
+pub const HEX_WIDTH: f32 = 20.0; // Per byte
+pub const ASCII_GAP: f32 = 10.0;
+pub const ASCII_WIDTH: f32 = 8.0; // Per byte
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Font,
+    Rom,
+    I,     // The byte I points at
+    Stack, // Where a call on the stack will return to
+    Free,
+}
+
+pub struct MemoryView {
+    pub top: usize,  // Address of the first row shown
+    pub rows: usize, // Rows shown
+    pub rom: Range<usize>,
+    pub selected: Option<usize>,
+    pub nibble: Option<u8>, // The first digit typed of the selected byte's new value
+    previous: Vec<u8>,      // Memory as of the last observe()
+    ages: Vec<u32>,         // Frames since each byte was last written
+    cycles: u64,            // Cpu::cycles as of the last observe()
+}
+
+impl MemoryView {
+    pub fn new(rom: Range<usize>, rows: usize) -> MemoryView {
+        MemoryView {
+            top: rom.start - rom.start % BYTES_PER_ROW,
+            rows,
+            rom,
+            selected: None,
+            nibble: None,
+            previous: Vec::new(),
+            ages: Vec::new(),
+            cycles: 0,
+        }
+    }
+
+    pub fn region(&self, cpu: &Cpu, address: usize) -> Region {
+        if address == cpu.i {
+            Region::I
+        } else if cpu.stack[..cpu.sp.min(cpu.stack.len())]
+            .iter()
+            .any(|&ret| address == ret || address == ret + 1)
+        {
+            Region::Stack
+        } else if address < BIG_FONT_ADDR + BIG_FONT_SET.len() {
+            Region::Font
+        } else if self.rom.contains(&address) {
+            Region::Rom
+        } else {
+            Region::Free
+        }
+    }
+
+    // Spots the bytes written since last time, once a frame.  The old writes
+    // only age while the cpu runs, so what the last step wrote stays lit
+    // while paused.
+    pub fn observe(&mut self, memory: &[u8], cycles: u64) {
+        if self.previous.len() != memory.len() {
+            self.previous = memory.to_vec();
+            self.ages = vec![RECENT_FRAMES; memory.len()];
+            self.cycles = cycles;
+            return;
+        }
+        let ran = cycles != self.cycles;
+        self.cycles = cycles;
+        for (address, &byte) in memory.iter().enumerate() {
+            if self.previous[address] != byte {
+                self.previous[address] = byte;
+                self.ages[address] = 0;
+            } else if ran && self.ages[address] < RECENT_FRAMES {
+                self.ages[address] += 1;
+            }
+        }
+    }
+
+    // Frames since the byte was written, if that was recently
+    pub fn age(&self, address: usize) -> Option<u32> {
+        self.ages
+            .get(address)
+            .copied()
+            .filter(|&age| age < RECENT_FRAMES)
+    }
+
+    pub fn scroll(&mut self, rows: isize, size: usize) {
+        let last = self.last_top(size) as isize;
+        let top = self.top as isize + rows * BYTES_PER_ROW as isize;
+        self.top = top.max(0).min(last) as usize;
+    }
+
+    // Scrolls just far enough for the address to be on screen
+    pub fn show(&mut self, address: usize, size: usize) {
+        let row = address - address % BYTES_PER_ROW;
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + self.rows * BYTES_PER_ROW {
+            self.top = (row + BYTES_PER_ROW).saturating_sub(self.rows * BYTES_PER_ROW);
+        }
+        self.top = self.top.min(self.last_top(size));
+    }
+
+    fn last_top(&self, size: usize) -> usize {
+        let rows = size.div_ceil(BYTES_PER_ROW);
+        rows.saturating_sub(self.rows) * BYTES_PER_ROW
+    }
+
+    pub fn select(&mut self, address: Option<usize>) {
+        self.selected = address;
+        self.nibble = None;
+    }
+
+    // Types a hex digit over the selected byte.  The second digit writes it
+    // and moves on to the next byte; returns the address written.
+    pub fn type_digit(&mut self, memory: &mut [u8], digit: u8) -> Option<usize> {
+        let address = self.selected.filter(|&address| address < memory.len())?;
+        match self.nibble.take() {
+            None => {
+                self.nibble = Some(digit & 0xF);
+                None
+            }
+            Some(high) => {
+                memory[address] = high << 4 | (digit & 0xF);
+                // An edit counts as a write, without aging everything else
+                if let (Some(previous), Some(age)) =
+                    (self.previous.get_mut(address), self.ages.get_mut(address))
+                {
+                    *previous = memory[address];
+                    *age = 0;
+                }
+                if address + 1 < memory.len() {
+                    self.selected = Some(address + 1);
+                    self.show(address + 1, memory.len());
+                }
+                Some(address)
+            }
+        }
+    }
+
+    // The byte under a point, in the hex or the ASCII columns
+    pub fn address_at(&self, x: f32, y: f32, size: usize) -> Option<usize> {
+        if y < 0.0 || y >= self.rows as f32 * ROW_HEIGHT {
+            return None;
+        }
+        let row = (y / ROW_HEIGHT) as usize;
+        let hex = x - ADDRESS_WIDTH;
+        let ascii = x - ascii_x(0);
+        let column = if hex >= 0.0 && hex < BYTES_PER_ROW as f32 * HEX_WIDTH {
+            (hex / HEX_WIDTH) as usize
+        } else if ascii >= 0.0 && ascii < BYTES_PER_ROW as f32 * ASCII_WIDTH {
+            (ascii / ASCII_WIDTH) as usize
+        } else {
+            return None;
+        };
+        Some(self.top + row * BYTES_PER_ROW + column).filter(|&address| address < size)
+    }
+}
+
+pub fn hex_x(column: usize) -> f32 {
+    ADDRESS_WIDTH + column as f32 * HEX_WIDTH
+}
+
+pub fn ascii_x(column: usize) -> f32 {
+    hex_x(BYTES_PER_ROW) + ASCII_GAP + column as f32 * ASCII_WIDTH
+}
+
+// Printable ASCII, or a dot
+pub fn ascii(byte: u8) -> char {
+    if byte.is_ascii_graphic() || byte == b' ' {
+        byte as char
+    } else {
+        '.'
+    }
+}
+
+// A row as text, "0200: 00 E0 .. |..|", as Cpu::dump_ram prints it
+pub fn format_row(memory: &[u8], address: usize) -> String {
+    let end = (address + BYTES_PER_ROW).min(memory.len());
+    let bytes = &memory[address.min(end)..end];
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    let text: String = bytes.iter().map(|&b| ascii(b)).collect();
+    format!(
+        "{:04X}: {:<width$} |{}|",
+        address,
+        hex.join(" "),
+        text,
+        width = BYTES_PER_ROW * 3 - 1
+    )
+}
@@ -0,0 +1,177 @@
+use crate::cpu::{Cpu, CpuError};
+use crate::state::{Reader, StateError, Writer};
+use std::fmt;
+
+// Movies record a run as the state it started from plus the keypad for
+// every frame after that, which is all it takes to replay it exactly:
+//   "R8MV" magic, u16 version, u64 RNG seed, u32 instructions per frame,
+//   u32 length + the starting save state, u32 count + a u16 key mask per
+//   frame, then a flag and u64 hash of the final framebuffer.
+pub const MOVIE_MAGIC: &[u8; 4] = b"R8MV";
+pub const MOVIE_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovieError {
+    BadMagic,
+    UnsupportedVersion { version: u16 },
+    Truncated,
+    Invalid,
+    State(StateError),
+    Cpu(CpuError),
+    Desync { expected: u64, found: u64 },
+}
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MovieError::BadMagic => write!(f, "not a movie file"),
+            MovieError::UnsupportedVersion { version } => write!(
+                f,
+                "unsupported movie version {} (expected {})",
+                version, MOVIE_VERSION
+            ),
+            MovieError::Truncated => write!(f, "movie file is truncated"),
+            MovieError::Invalid => write!(f, "movie file is corrupt"),
+            MovieError::State(err) => write!(f, "bad starting state: {}", err),
+            MovieError::Cpu(err) => write!(f, "CPU fault during replay: {}", err),
+            MovieError::Desync { expected, found } => write!(
+                f,
+                "replay desynced: framebuffer hash {:016X}, expected {:016X}",
+                found, expected
+            ),
+        }
+    }
+}
+impl std::error::Error for MovieError {}
+
+pub struct Movie {
+    pub seed: u64,
+    pub ipf: usize,
+    pub start: Vec<u8>, // Save state the movie starts from
+    pub frames: Vec<u16>,
+    pub gfx_hash: Option<u64>, // Set once the recording is finished
+}
+
+impl Movie {
+    // Starts recording from the cpu as it is now
+    pub fn new(cpu: &Cpu, seed: u64, ipf: usize) -> Movie {
+        Movie {
+            seed,
+            ipf,
+            start: cpu.save_state(),
+            frames: Vec::new(),
+            gfx_hash: None,
+        }
+    }
+
+    // Records the keys held for the frame about to run
+    pub fn record_frame(&mut self, cpu: &Cpu) {
+        self.frames.push(cpu.input.key_mask());
+    }
+
+    // Notes how the display ended up, for replays to be checked against
+    pub fn finish(&mut self, cpu: &Cpu) {
+        self.gfx_hash = Some(cpu.gfx_hash());
+    }
+
+    // Puts the cpu back where the movie started
+    pub fn start(&self, cpu: &mut Cpu) -> Result<(), MovieError> {
+        cpu.seed_rng(self.seed);
+        cpu.load_state(&self.start).map_err(MovieError::State)
+    }
+
+    // Feeds in the keys for a frame, false once the movie has run out
+    pub fn apply_frame(&self, frame: usize, cpu: &mut Cpu) -> bool {
+        match self.frames.get(frame) {
+            Some(mask) => {
+                cpu.input.set_key_mask(*mask);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Checks that a replay drew the same thing as the recording
+    pub fn verify(&self, cpu: &Cpu) -> Result<(), MovieError> {
+        match self.gfx_hash {
+            Some(expected) if expected != cpu.gfx_hash() => Err(MovieError::Desync {
+                expected,
+                found: cpu.gfx_hash(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    // Replays the whole movie on a cpu with the ROM loaded, and verifies it
+    pub fn play(&self, cpu: &mut Cpu) -> Result<(), MovieError> {
+        self.start(cpu)?;
+        for frame in 0..self.frames.len() {
+            self.apply_frame(frame, cpu);
+            cpu.run_frame(self.ipf).map_err(MovieError::Cpu)?;
+        }
+        self.verify(cpu)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer { bytes: Vec::new() };
+        w.bytes(MOVIE_MAGIC);
+        w.u16(MOVIE_VERSION);
+        w.u64(self.seed);
+        w.u32(self.ipf);
+        w.u32(self.start.len());
+        w.bytes(&self.start);
+        w.u32(self.frames.len());
+        for mask in &self.frames {
+            w.u16(*mask);
+        }
+        match self.gfx_hash {
+            Some(hash) => {
+                w.bool(true);
+                w.u64(hash);
+            }
+            None => w.bool(false),
+        }
+        w.bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Movie, MovieError> {
+        if !bytes.starts_with(MOVIE_MAGIC) {
+            return Err(MovieError::BadMagic);
+        }
+        let mut r = Reader {
+            bytes,
+            pos: MOVIE_MAGIC.len(),
+        };
+        let version = r.u16().map_err(|_| MovieError::Truncated)?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion { version });
+        }
+        let movie = Movie::read(&mut r).map_err(|err| match err {
+            StateError::Truncated => MovieError::Truncated,
+            _ => MovieError::Invalid,
+        })?;
+        if r.pos != bytes.len() {
+            return Err(MovieError::Invalid);
+        }
+        Ok(movie)
+    }
+
+    fn read(r: &mut Reader) -> Result<Movie, StateError> {
+        let seed = r.u64()?;
+        let ipf = r.u32()?;
+        let start_len = r.u32()?;
+        let start = r.bytes(start_len)?.to_vec();
+        let frame_count = r.u32()?;
+        let mut frames = Vec::with_capacity(frame_count.min(r.bytes.len()));
+        for _ in 0..frame_count {
+            frames.push(r.u16()?);
+        }
+        let gfx_hash = if r.bool()? { Some(r.u64()?) } else { None };
+        Ok(Movie {
+            seed,
+            ipf,
+            start,
+            frames,
+            gfx_hash,
+        })
+    }
+}
@@ -0,0 +1,193 @@
+use crate::disassembler::Disassembly;
+use crate::instruction::Instruction;
+use std::collections::BTreeMap;
+
+// Where the instructions go.  Cpu::tick() hands each one it runs to
+// record() while Cpu::profile is set; time is counted in instructions,
+// since that is the budget a rom has each frame.
+
+// Time spent in a subroutine, counted from its first instruction up to and
+// including its return.  Inclusive time takes in the subroutines it calls.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Subroutine {
+    pub calls: u64,
+    pub inclusive: u64,
+    pub exclusive: u64,
+}
+
+// A call that hasn't returned yet
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    entry: usize,
+    start: u64,    // Instructions run when it was called
+    children: u64, // Inclusive time of the calls it has made
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    pub instructions: u64,
+    pub hits: BTreeMap<usize, u64>,                // PC -> times run
+    pub classes: BTreeMap<String, u64>,            // Instruction kind -> times run
+    pub back_edges: BTreeMap<(usize, usize), u64>, // (jump, target) -> times taken
+    subroutines: BTreeMap<usize, Subroutine>,      // Entry -> time in the finished calls
+    frames: Vec<Frame>,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    // Counts the instruction that was at `pc`, now that it has run and left
+    // the PC at `next`
+    pub fn record(&mut self, pc: usize, instruction: Option<Instruction>, next: usize) {
+        self.instructions += 1;
+        *self.hits.entry(pc).or_insert(0) += 1;
+        *self.classes.entry(class(instruction)).or_insert(0) += 1;
+        match instruction {
+            Some(Instruction::Call(entry)) => {
+                self.subroutines.entry(entry).or_default().calls += 1;
+                self.frames.push(Frame {
+                    entry,
+                    start: self.instructions,
+                    children: 0,
+                });
+            }
+            Some(Instruction::Ret) => {
+                // Returning without a call we saw, as after loading a state
+                let frame = match self.frames.pop() {
+                    Some(frame) => frame,
+                    None => return,
+                };
+                let inclusive = self.instructions - frame.start;
+                let subroutine = self.subroutines.entry(frame.entry).or_default();
+                subroutine.inclusive += inclusive;
+                subroutine.exclusive += inclusive - frame.children;
+                if let Some(caller) = self.frames.last_mut() {
+                    caller.children += inclusive;
+                }
+            }
+            // Loops go round by jumping back
+            Some(Instruction::Jp(_) | Instruction::JpV0(_)) if next <= pc => {
+                *self.back_edges.entry((pc, next)).or_insert(0) += 1;
+            }
+            _ => (),
+        }
+    }
+
+    // Time in each subroutine, counting the calls still running as if they
+    // returned now
+    pub fn subroutines(&self) -> BTreeMap<usize, Subroutine> {
+        let mut subroutines = self.subroutines.clone();
+        let mut running_child = 0;
+        for frame in self.frames.iter().rev() {
+            let inclusive = self.instructions - frame.start;
+            let subroutine = subroutines.entry(frame.entry).or_default();
+            subroutine.inclusive += inclusive;
+            subroutine.exclusive += inclusive - frame.children - running_child;
+            running_child = inclusive;
+        }
+        subroutines
+    }
+
+    // Loops by the instructions run inside them: (first, last, times round,
+    // instructions), biggest first
+    pub fn hot_loops(&self) -> Vec<(usize, usize, u64, u64)> {
+        let mut loops: Vec<(usize, usize, u64, u64)> = self
+            .back_edges
+            .iter()
+            .map(|(&(jump, target), &count)| {
+                let inside = self.hits.range(target..=jump).map(|(_, hits)| hits).sum();
+                (target, jump, count, inside)
+            })
+            .collect();
+        loops.sort_by(|a, b| b.3.cmp(&a.3).then(a.0.cmp(&b.0)));
+        loops
+    }
+
+    // The report for `r8 profile`, with the `top` few of each table
+    pub fn report(
+        &self,
+        frames: usize,
+        disassembly: &Disassembly,
+        memory: &[u8],
+        top: usize,
+    ) -> String {
+        let total = self.instructions.max(1) as f64;
+        let percent = |n: u64| format!("{:5.1}%", n as f64 * 100.0 / total);
+        let name = |address: usize| match disassembly.labels.get(&address) {
+            Some(label) => label.clone(),
+            None => format!("{:#05X}", address),
+        };
+
+        let mut out = format!(
+            "{} instructions in {} frames, {:.1} a frame\n",
+            self.instructions,
+            frames,
+            self.instructions as f64 / frames.max(1) as f64
+        );
+
+        out += "\nTop addresses\n";
+        let mut hits: Vec<(&usize, &u64)> = self.hits.iter().collect();
+        hits.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (address, count) in hits.iter().take(top) {
+            out += &format!(
+                "  {:#05X}  {:>10} {}  {}\n",
+                address,
+                count,
+                percent(**count),
+                disassembly.describe(memory, **address)
+            );
+        }
+
+        out += "\nInstructions\n";
+        let mut classes: Vec<(&String, &u64)> = self.classes.iter().collect();
+        classes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (class, count) in classes.iter().take(top) {
+            out += &format!("  {:<10} {:>10} {}\n", class, count, percent(**count));
+        }
+
+        out += "\nHot loops\n";
+        for (first, last, rounds, inside) in self.hot_loops().into_iter().take(top) {
+            out += &format!(
+                "  {:#05X}-{:#05X}  {:>10} {}  {} times round, at {}\n",
+                first,
+                last,
+                inside,
+                percent(inside),
+                rounds,
+                name(first)
+            );
+        }
+
+        out += "\nSubroutines                    inclusive            exclusive    calls\n";
+        let mut subroutines: Vec<(usize, Subroutine)> = self.subroutines().into_iter().collect();
+        subroutines.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(&b.0)));
+        for (entry, time) in subroutines.iter().take(top) {
+            out += &format!(
+                "  {:<20} {:>10} {}  {:>10} {}  {:>7}\n",
+                name(*entry),
+                time.inclusive,
+                percent(time.inclusive),
+                time.exclusive,
+                percent(time.exclusive),
+                time.calls
+            );
+        }
+        out
+    }
+}
+
+// The kind of instruction, as named in Instruction
+fn class(instruction: Option<Instruction>) -> String {
+    match instruction {
+        Some(instruction) => {
+            let name = format!("{:?}", instruction);
+            match name.find('(') {
+                Some(end) => name[..end].to_string(),
+                None => name,
+            }
+        }
+        None => "Unknown".to_string(),
+    }
+}
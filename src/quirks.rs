@@ -0,0 +1,157 @@
+use crate::{MEMORY_SIZE, XO_MEMORY_SIZE};
+use std::str::FromStr;
+
+// Toggles for the instructions that different CHIP-8 interpreters disagree
+// about.  A good write up of each of these is at:
+// https://github.com/Timendus/chip8-test-suite#quirks-test
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    pub shift: bool,           // 8xy6/8xyE shift Vx in place, ignoring Vy
+    pub load_store: LoadStore, // What Fx55/Fx65 do to I
+    pub jump_with_vx: bool,    // Bxnn jumps to xnn + Vx rather than nnn + V0
+    pub clip: bool,            // Sprites are clipped at the screen edge, not wrapped
+    pub vf_reset: bool,        // 8xy1/8xy2/8xy3 reset VF to 0
+    pub display_wait: bool,    // Dxyn waits for the next vertical blank before drawing
+}
+
+// How far Fx55/Fx65 move I on.  The VIP leaves it past the last register,
+// CHIP-48 one short of that, and SUPER-CHIP where it was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStore {
+    Unchanged,
+    AddX,
+    AddXPlusOne,
+}
+
+impl LoadStore {
+    // What I goes up by for Fx55/Fx65
+    pub fn increment(self, x: usize) -> usize {
+        match self {
+            LoadStore::Unchanged => 0,
+            LoadStore::AddX => x,
+            LoadStore::AddXPlusOne => x + 1,
+        }
+    }
+}
+
+impl Default for Quirks {
+    // The behaviour this interpreter has always had
+    fn default() -> Self {
+        Quirks {
+            shift: true,
+            load_store: LoadStore::Unchanged,
+            jump_with_vx: false,
+            clip: false,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+}
+
+impl Quirks {
+    // The original COSMAC VIP interpreter
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift: false,
+            load_store: LoadStore::AddXPlusOne,
+            jump_with_vx: false,
+            clip: true,
+            vf_reset: true,
+            display_wait: true,
+        }
+    }
+
+    // CHIP-48 on the HP-48 calculators
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift: true,
+            load_store: LoadStore::AddX,
+            jump_with_vx: true,
+            clip: true,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+
+    // SUPER-CHIP 1.1
+    pub fn superchip() -> Quirks {
+        Quirks {
+            shift: true,
+            load_store: LoadStore::Unchanged,
+            jump_with_vx: true,
+            clip: true,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+
+    // XO-CHIP, as implemented by Octo
+    pub fn xochip() -> Quirks {
+        Quirks {
+            shift: false,
+            load_store: LoadStore::AddXPlusOne,
+            jump_with_vx: false,
+            clip: false,
+            vf_reset: false,
+            display_wait: false,
+        }
+    }
+}
+
+// The machines the presets are named after.  Besides its quirks, each one
+// decides how much memory there is, so that's never guessed from the flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Default,
+    CosmacVip,
+    Chip48,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::Default => Quirks::default(),
+            Platform::CosmacVip => Quirks::cosmac_vip(),
+            Platform::Chip48 => Quirks::chip48(),
+            Platform::SuperChip => Quirks::superchip(),
+            Platform::XoChip => Quirks::xochip(),
+        }
+    }
+
+    // XO-CHIP roms get the full 64K, everything else the classic 4K
+    pub fn memory_size(self) -> usize {
+        match self {
+            Platform::XoChip => XO_MEMORY_SIZE,
+            _ => MEMORY_SIZE,
+        }
+    }
+}
+
+// Lets a platform be picked by name, e.g. from the command line
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "default" => Ok(Platform::Default),
+            "vip" | "cosmac-vip" | "chip8" | "chip-8" => Ok(Platform::CosmacVip),
+            "chip48" | "chip-48" => Ok(Platform::Chip48),
+            "schip" | "superchip" | "super-chip" => Ok(Platform::SuperChip),
+            "xochip" | "xo-chip" => Ok(Platform::XoChip),
+            _ => Err(format!(
+                "unknown quirks profile '{}' (expected one of: default, vip, chip48, schip, xochip)",
+                s
+            )),
+        }
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<Platform>().map(Platform::quirks)
+    }
+}
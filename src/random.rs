@@ -0,0 +1,46 @@
+// Where Cxkk gets its random bytes from.  The cpu owns one of these so a
+// known seed (or a fake source in tests) gives repeatable runs.
+pub trait RandomSource {
+    fn next_byte(&mut self) -> u8;
+
+    // The generator's internal state, for save states.  Sources without
+    // any state worth saving can leave these be.
+    fn state(&self) -> Option<u64> {
+        None
+    }
+    fn set_state(&mut self, _state: u64) {}
+}
+
+// The default source: SplitMix64, which is tiny, fast and happy with any
+// seed, including zero.  http://xoshiro.di.unimi.it/splitmix64.c
+pub struct SeededRandom {
+    state: u64,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> SeededRandom {
+        SeededRandom { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_byte(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+
+    fn state(&self) -> Option<u64> {
+        Some(self.state)
+    }
+
+    fn set_state(&mut self, state: u64) {
+        self.state = state;
+    }
+}
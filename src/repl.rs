@@ -0,0 +1,397 @@
+use crate::analyzer::analyze;
+use crate::cpu::Cpu;
+use crate::debugger::{Debugger, StopReason, Watchpoint};
+use crate::disassembler::{disassemble_analyzed, Disassembly};
+use crate::headless::{parse_address, parse_value, registers};
+use crate::image;
+use crate::instruction::Instruction;
+use crate::quirks::Quirks;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Write};
+
+// A gdb style prompt for debugging a rom without a window, behind `r8
+// debug`.  Each command gives back the text to print, so the whole thing
+// can be driven from a test as well as from a terminal.
+
+const HELP: &str = "\
+break [ADDR]        stop before running ADDR, or list what it stops on
+watch SPEC          stop on r:ADDR, w:ADDR-ADDR, v0 - vF or i, as with --watch
+delete [ADDR|SPEC]  take away a breakpoint or watchpoint, or all of them
+step [N]            run N instructions (1)
+next                step, running the whole of a call
+finish              run until the current subroutine returns
+until ADDR          run until the PC reaches ADDR
+continue            run until something stops it
+regs                show the registers and stack
+x/N ADDR            show N bytes (16) of memory from ADDR, I or PC
+set REG=VALUE       set v0 - vF, i, pc, dt or st, in decimal or 0x hex
+disasm [ADDR] [N]   disassemble N instructions (8) from ADDR or the PC
+display             draw the screen as text
+keys KEYS           hold hex keys down, e.g. keys 5A, or keys - for none
+reset               start the rom again, keeping breakpoints and watchpoints
+quit                leave
+An empty line runs the last command again.  Addresses are hex.
+";
+
+pub struct Repl {
+    pub cpu: Cpu,
+    pub debugger: Debugger,
+    pub ipf: usize,
+    pub limit: usize, // Frames `continue` runs before giving up
+    pub quit: bool,
+    disassembly: Disassembly, // Of the rom as loaded, for naming addresses
+    rom: Vec<u8>,
+    quirks: Quirks,
+    memory: usize,
+    seed: u64,
+    last: String,
+}
+
+impl Repl {
+    pub fn new(
+        rom: &[u8],
+        quirks: Quirks,
+        memory: usize,
+        seed: u64,
+        ipf: usize,
+    ) -> Result<Repl, String> {
+        Ok(Repl {
+            cpu: boot(rom, quirks, memory, seed)?,
+            debugger: Debugger::new(),
+            ipf,
+            limit: 3600,
+            quit: false,
+            disassembly: disassemble_analyzed(rom, 0x200, &analyze(rom, 0x200)),
+            rom: rom.to_vec(),
+            quirks,
+            memory,
+            seed,
+            last: String::new(),
+        })
+    }
+
+    // Reads commands until quit or the end of the input, prompting for each
+    // one if there's a person typing them
+    pub fn run<R: BufRead, W: Write>(
+        &mut self,
+        input: R,
+        mut output: W,
+        prompt: bool,
+    ) -> io::Result<()> {
+        writeln!(output, "{}", self.location())?;
+        let mut lines = input.lines();
+        while !self.quit {
+            if prompt {
+                write!(output, "(r8) ")?;
+                output.flush()?;
+            }
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => break,
+            };
+            match self.execute(&line) {
+                Ok(text) => write!(output, "{}", text)?,
+                Err(err) => writeln!(output, "error: {}", err)?,
+            }
+        }
+        Ok(())
+    }
+
+    // Runs one command line, returning what to print
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let line = match line.trim() {
+            "" => self.last.clone(),
+            line => line.to_string(),
+        };
+        self.last = line.clone();
+        let (command, rest) = match line.find(|c: char| c.is_whitespace() || c == '/') {
+            Some(at) => line.split_at(at),
+            None => (line.as_str(), ""),
+        };
+        let args: Vec<&str> = rest.split_whitespace().collect();
+        match (command, args.as_slice()) {
+            ("", _) => Ok(String::new()),
+            ("help" | "h", _) => Ok(HELP.to_string()),
+            ("break" | "b", []) => Ok(self.points()),
+            ("break" | "b", [address]) => {
+                let address = self.address(address)?;
+                self.debugger.breakpoints.insert(address);
+                Ok(format!("breakpoint at {}\n", self.name(address)))
+            }
+            ("watch", [spec]) => {
+                let watch: Watchpoint = spec.parse()?;
+                if !self.debugger.watchpoints.contains(&watch) {
+                    self.debugger.watchpoints.push(watch);
+                }
+                Ok(format!("watching {}\n", watch))
+            }
+            ("delete" | "d", []) => {
+                self.debugger.breakpoints.clear();
+                self.debugger.watchpoints.clear();
+                Ok("deleted all breakpoints and watchpoints\n".to_string())
+            }
+            ("delete" | "d", [what]) => self.delete(what),
+            ("step" | "s", []) => self.step(1),
+            ("step" | "s", [count]) => self.step(parse_count(count)?),
+            ("next" | "n", []) => {
+                if self.debugger.step_over(&self.cpu) {
+                    self.run_on()
+                } else {
+                    self.step(1)
+                }
+            }
+            ("finish", []) => {
+                if !self.debugger.step_out(&self.cpu) {
+                    return Err("not in a subroutine".to_string());
+                }
+                self.run_on()
+            }
+            ("until" | "u", [address]) => {
+                let address = self.address(address)?;
+                self.debugger.run_to(address);
+                self.run_on()
+            }
+            ("continue" | "c", []) => self.run_on(),
+            ("regs" | "r", []) => Ok(registers(&self.cpu)),
+            ("x", _) => self.examine(rest),
+            ("set", [_, ..]) => self.set(&args.concat()),
+            ("disasm", []) => Ok(self.listing(self.cpu.pc, 8)),
+            ("disasm", [address]) => Ok(self.listing(self.address(address)?, 8)),
+            ("disasm", [address, count]) => {
+                Ok(self.listing(self.address(address)?, parse_count(count)?))
+            }
+            ("display", []) => Ok(image::to_ascii(&self.cpu.gfx)),
+            ("keys", [keys]) => {
+                let mut mask = 0;
+                if *keys != "-" {
+                    for key in keys.chars() {
+                        let key = key
+                            .to_digit(16)
+                            .ok_or_else(|| format!("'{}' is not a key", key))?;
+                        mask |= 1 << key;
+                    }
+                }
+                self.cpu.input.set_key_mask(mask);
+                Ok(format!("holding {:#06X}\n", mask))
+            }
+            ("reset", []) => {
+                self.cpu = boot(&self.rom, self.quirks, self.memory, self.seed)?;
+                self.debugger.cancel();
+                self.debugger.resume();
+                Ok(format!("{}\n", self.location()))
+            }
+            ("quit" | "q", []) => {
+                self.quit = true;
+                Ok(String::new())
+            }
+            _ => Err(format!("don't know '{}', try help", line)),
+        }
+    }
+
+    // An address, or I or the PC
+    fn address(&self, text: &str) -> Result<usize, String> {
+        match text.to_ascii_lowercase().as_str() {
+            "i" => Ok(self.cpu.i),
+            "pc" => Ok(self.cpu.pc),
+            _ => parse_address(text),
+        }
+    }
+
+    // An address along with its label, if it has one
+    fn name(&self, address: usize) -> String {
+        match self.disassembly.labels.get(&address) {
+            Some(label) => format!("{:#05X} ({})", address, label),
+            None => format!("{:#05X}", address),
+        }
+    }
+
+    fn points(&self) -> String {
+        let mut out = String::new();
+        for address in &self.debugger.breakpoints {
+            out += &format!("break {}\n", self.name(*address));
+        }
+        for watch in &self.debugger.watchpoints {
+            out += &format!("watch {}\n", watch);
+        }
+        if out.is_empty() {
+            out += "no breakpoints or watchpoints\n";
+        }
+        out
+    }
+
+    fn delete(&mut self, what: &str) -> Result<String, String> {
+        if let Ok(watch) = what.parse::<Watchpoint>() {
+            return match self.debugger.watchpoints.iter().position(|w| *w == watch) {
+                Some(index) => {
+                    self.debugger.watchpoints.remove(index);
+                    Ok(format!("deleted watch {}\n", watch))
+                }
+                None => Err(format!("not watching {}", watch)),
+            };
+        }
+        let address = self.address(what)?;
+        if self.debugger.breakpoints.remove(&address) {
+            Ok(format!("deleted breakpoint at {}\n", self.name(address)))
+        } else {
+            Err(format!("no breakpoint at {:#05X}", address))
+        }
+    }
+
+    fn step(&mut self, count: usize) -> Result<String, String> {
+        self.check_running()?;
+        let mut stop = StopReason::Step;
+        for _ in 0..count {
+            stop = self
+                .debugger
+                .step(&mut self.cpu)
+                .unwrap_or_else(StopReason::Fault);
+            if stop != StopReason::Step {
+                break;
+            }
+        }
+        Ok(self.stopped(stop))
+    }
+
+    // Runs frames until the debugger stops, or `limit` of them have gone by
+    fn run_on(&mut self) -> Result<String, String> {
+        self.check_running()?;
+        for _ in 0..self.limit {
+            match self.debugger.run_frame(&mut self.cpu, self.ipf) {
+                Ok(Some(stop)) => return Ok(self.stopped(stop)),
+                Ok(None) => (),
+                Err(err) => return Ok(self.stopped(StopReason::Fault(err))),
+            }
+        }
+        self.debugger.cancel();
+        Ok(format!(
+            "still running after {} frames\n{}\n",
+            self.limit,
+            self.location()
+        ))
+    }
+
+    fn check_running(&mut self) -> Result<(), String> {
+        if self.cpu.exited {
+            self.debugger.cancel();
+            return Err("the rom has exited, reset to start again".to_string());
+        }
+        Ok(())
+    }
+
+    fn stopped(&self, stop: StopReason) -> String {
+        match stop {
+            StopReason::Step => format!("{}\n", self.location()),
+            stop => format!("{}\n{}\n", stop, self.location()),
+        }
+    }
+
+    fn location(&self) -> String {
+        self.line(self.cpu.pc)
+    }
+
+    // One line of disassembly, marked if it's the PC or a breakpoint
+    fn line(&self, address: usize) -> String {
+        let marker = if address == self.cpu.pc {
+            "=>"
+        } else if self.debugger.breakpoints.contains(&address) {
+            " *"
+        } else {
+            "  "
+        };
+        format!(
+            "{} {:#05X}  {}",
+            marker,
+            address,
+            self.disassembly.describe(&self.cpu.memory, address)
+        )
+    }
+
+    fn listing(&self, mut address: usize, count: usize) -> String {
+        let mut out = String::new();
+        for _ in 0..count {
+            if address >= self.cpu.memory.len() {
+                break;
+            }
+            out += &self.line(address);
+            out.push('\n');
+            address += match Instruction::decode(self.cpu.word_at(address)) {
+                Some(instruction) => instruction.size(),
+                None => 2,
+            };
+        }
+        out
+    }
+
+    // x/N ADDR, eight bytes to a row
+    fn examine(&self, rest: &str) -> Result<String, String> {
+        let rest = rest.trim();
+        let (count, rest) = match rest.strip_prefix('/') {
+            Some(rest) => {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                (parse_count(&rest[..end])?, rest[end..].trim())
+            }
+            None => (16, rest),
+        };
+        if rest.is_empty() {
+            return Err("x needs an address".to_string());
+        }
+        let start = self.address(rest)?;
+        let memory = &self.cpu.memory;
+        if start >= memory.len() {
+            return Err(format!("{:#05X} is past the end of memory", start));
+        }
+        let end = (start + count).min(memory.len());
+        let mut out = String::new();
+        for row in (start..end).step_by(8) {
+            let bytes: Vec<String> = memory[row..end.min(row + 8)]
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            out += &format!("{:#05X}: {}\n", row, bytes.join(" "));
+        }
+        Ok(out)
+    }
+
+    fn set(&mut self, assignment: &str) -> Result<String, String> {
+        let (target, value) = assignment
+            .split_once('=')
+            .ok_or_else(|| "set needs REG=VALUE".to_string())?;
+        let target = target.to_ascii_lowercase();
+        let value = parse_value(value)?;
+        set_register(&mut self.cpu, &target, value)?;
+        Ok(format!("{} = {:#X}\n", target, value))
+    }
+}
+
+// Sets v0 - vF, i, pc, dt or st by name
+pub fn set_register(cpu: &mut Cpu, name: &str, value: usize) -> Result<(), String> {
+    let byte = || u8::try_from(value).map_err(|_| format!("{:#X} won't fit in a byte", value));
+    match name.to_ascii_lowercase().as_str() {
+        "i" => cpu.i = value,
+        "pc" => cpu.pc = value,
+        "dt" => cpu.delay_timer = byte()?,
+        "st" => cpu.sound_timer = byte()?,
+        target => {
+            let x = target
+                .strip_prefix('v')
+                .filter(|x| x.len() == 1)
+                .and_then(|x| usize::from_str_radix(x, 16).ok())
+                .ok_or_else(|| format!("can't set '{}'", name))?;
+            cpu.v[x] = byte()?;
+        }
+    }
+    Ok(())
+}
+
+fn boot(rom: &[u8], quirks: Quirks, memory: usize, seed: u64) -> Result<Cpu, String> {
+    let mut cpu = Cpu::with_quirks(quirks);
+    cpu.set_memory_size(memory);
+    cpu.seed_rng(seed);
+    cpu.load_rom_bytes(rom).map_err(|e| e.to_string())?;
+    Ok(cpu)
+}
+
+fn parse_count(text: &str) -> Result<usize, String> {
+    text.parse()
+        .map_err(|_| format!("'{}' is not a count", text))
+}
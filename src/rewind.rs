@@ -0,0 +1,263 @@
+use crate::cpu::{Cpu, CpuError};
+use std::collections::VecDeque;
+
+// One call into the cpu since the last snapshot: the keys that were held,
+// whether it started a new frame, how many instructions ran and whether the
+// timers ticked at the end of it.  Replaying these from a snapshot brings
+// the cpu back to exactly where it was.
+struct Step {
+    keys: [bool; 16],
+    vblank: bool,
+    cycles: usize,
+    timers: bool,
+}
+
+struct Snapshot {
+    cycles: u64,
+    state: Vec<u8>, // A run length encoded save state
+    steps: Vec<Step>,
+}
+
+// A ring buffer of snapshots taken every `interval` frames, for playing a
+// game backwards or stepping back a single instruction.  Frames and single
+// ticks go through here rather than straight to the cpu so that they can be
+// journalled and replayed.
+pub struct Rewind {
+    snapshots: VecDeque<Snapshot>,
+    pub capacity: usize, // Most snapshots kept, the oldest are dropped first
+    pub interval: usize, // Frames between snapshots
+    frames: usize,
+}
+
+impl Rewind {
+    pub fn new(capacity: usize, interval: usize) -> Rewind {
+        Rewind {
+            snapshots: VecDeque::new(),
+            capacity: capacity.max(1),
+            interval: interval.max(1),
+            frames: 0,
+        }
+    }
+
+    // How many snapshots there are to go back through
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    // Forgets all history, e.g. once a save state has been loaded
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.frames = 0;
+    }
+
+    // Snapshots the cpu as it is now, after something other than the cpu
+    // has changed it, such as memory typed over while paused.  Otherwise
+    // replaying from the last snapshot would quietly undo the change.
+    pub fn edited(&mut self, cpu: &Cpu) {
+        self.snapshot(cpu);
+    }
+
+    fn snapshot(&mut self, cpu: &Cpu) {
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(Snapshot {
+            cycles: cpu.cycles,
+            state: compress(&cpu.save_state()),
+            steps: Vec::new(),
+        });
+    }
+
+    fn journal(&mut self, step: Step) {
+        if let Some(snapshot) = self.snapshots.back_mut() {
+            snapshot.steps.push(step);
+        }
+    }
+
+    // Cpu::run_frame(), snapshotting every `interval` frames
+    pub fn run_frame(&mut self, cpu: &mut Cpu, cycles: usize) -> Result<(), CpuError> {
+        self.run_frame_until(cpu, cycles, |_| false).map(|_| ())
+    }
+
+    // Cpu::run_frame_until(), for frames the debugger may cut short
+    pub fn run_frame_until<F>(
+        &mut self,
+        cpu: &mut Cpu,
+        cycles: usize,
+        stop: F,
+    ) -> Result<bool, CpuError>
+    where
+        F: FnMut(&Cpu) -> bool,
+    {
+        if self.frames.is_multiple_of(self.interval) || self.snapshots.is_empty() {
+            self.snapshot(cpu);
+        }
+        self.frames += 1;
+
+        let (keys, start) = (cpu.input.keys, cpu.cycles);
+        let result = cpu.run_frame_until(cycles, stop);
+        self.journal(Step {
+            keys,
+            vblank: true,
+            cycles: (cpu.cycles - start) as usize,
+            timers: result == Ok(false),
+        });
+        result
+    }
+
+    // Cpu::tick(), for single stepping
+    pub fn tick(&mut self, cpu: &mut Cpu) -> Result<(), CpuError> {
+        if self.snapshots.is_empty() {
+            self.snapshot(cpu);
+        }
+
+        let (keys, start) = (cpu.input.keys, cpu.cycles);
+        let result = cpu.tick(false);
+        self.journal(Step {
+            keys,
+            vblank: false,
+            cycles: (cpu.cycles - start) as usize,
+            timers: false,
+        });
+        result
+    }
+
+    // Goes back to the most recent snapshot, and further back on each
+    // call.  The oldest snapshot is kept so there is always one to return
+    // to; false once there's nowhere further back to go.
+    pub fn rewind(&mut self, cpu: &mut Cpu) -> bool {
+        let snapshot = match self.snapshots.back() {
+            Some(snapshot) => snapshot,
+            None => return false,
+        };
+        if self.snapshots.len() == 1 && snapshot.steps.is_empty() {
+            return false;
+        }
+        if cpu.load_state(&decompress(&snapshot.state)).is_err() {
+            return false;
+        }
+        if self.snapshots.len() > 1 {
+            self.snapshots.pop_back();
+        } else if let Some(snapshot) = self.snapshots.back_mut() {
+            snapshot.steps.clear();
+        }
+        // Start a fresh snapshot from here on the next frame
+        self.frames = 0;
+        true
+    }
+
+    // Goes back exactly one instruction by restoring the snapshot before
+    // it and replaying forward to the instruction before the current one.
+    // False, with the cpu left as it was, at the start or if the replay
+    // faults or comes up short, as it can when it doesn't go the way it
+    // went the first time (with a random source that can't be saved, say).
+    pub fn step_back(&mut self, cpu: &mut Cpu) -> bool {
+        if cpu.cycles == 0 {
+            return false;
+        }
+        let target = cpu.cycles - 1;
+        let index = match self.snapshots.iter().rposition(|s| s.cycles <= target) {
+            Some(index) => index,
+            None => return false,
+        };
+        let now = cpu.save_state();
+        if cpu
+            .load_state(&decompress(&self.snapshots[index].state))
+            .is_err()
+        {
+            return false;
+        }
+
+        // The trace and the profile have seen these instructions already,
+        // so they sit the replay out
+        let (trace, profile) = (cpu.trace.take(), cpu.profile.take());
+        let replayed = replay(cpu, &self.snapshots[index].steps, target);
+        cpu.trace = trace;
+        cpu.profile = profile;
+
+        match replayed {
+            Ok((kept, cut)) if cpu.cycles == target => {
+                // Cut the journal short where we stopped so that it still
+                // describes how we got here
+                self.snapshots.truncate(index + 1);
+                let steps = &mut self.snapshots[index].steps;
+                steps.truncate(kept);
+                if let (Some(cycles), Some(step)) = (cut, steps.last_mut()) {
+                    step.cycles = cycles;
+                    step.timers = false;
+                }
+                true
+            }
+            _ => {
+                let _ = cpu.load_state(&now);
+                false
+            }
+        }
+    }
+}
+
+// Replays journalled steps until the cpu reaches `target` cycles.  Returns
+// how many steps that took, and how many cycles of the last one ran if it
+// was cut short.
+fn replay(cpu: &mut Cpu, steps: &[Step], target: u64) -> Result<(usize, Option<usize>), CpuError> {
+    for (n, step) in steps.iter().enumerate() {
+        cpu.input.keys = step.keys;
+        if step.vblank {
+            cpu.signal_vblank();
+        }
+        let last = cpu.cycles + step.cycles as u64 > target;
+        let cycles = if last {
+            (target - cpu.cycles) as usize
+        } else {
+            step.cycles
+        };
+        for _ in 0..cycles {
+            cpu.tick(false)?;
+        }
+        if last {
+            return Ok((n + 1, Some(cycles)));
+        }
+        if step.timers {
+            cpu.tick_timers();
+        }
+    }
+    Ok((steps.len(), None))
+}
+
+// Save states are mostly empty memory and display, so runs of zeros are
+// stored as a zero followed by the run length.
+fn compress(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == 0 {
+            let run = bytes[i..].iter().take(255).take_while(|b| **b == 0).count();
+            out.push(0);
+            out.push(run as u8);
+            i += run;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+fn decompress(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == 0 {
+            out.resize(out.len() + bytes[i + 1] as usize, 0);
+            i += 2;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    out
+}
@@ -0,0 +1,102 @@
+// A square wave generator for the CHIP-8 buzzer.  The core only produces
+// samples; it is up to the frontend to get them to a speaker, so this can
+// be exercised without any audio hardware.
+//
+// Given an XO-CHIP audio pattern it plays the 128 bits of that instead,
+// one bit per sample at the rate set by the pitch register.
+pub struct Beeper {
+    pub frequency: f32, // Tone, in Hz.  With a pattern, how often the whole pattern plays
+    pub volume: f32,    // Amplitude, 0.0 - 1.0
+    pub sample_rate: u32,
+    pub pattern: Option<[u8; 16]>,
+    phase: f32, // How far through the current wave period we are, 0.0 - 1.0
+}
+
+impl Default for Beeper {
+    fn default() -> Self {
+        Self::new(440.0, 0.25, 44_100)
+    }
+}
+
+impl Beeper {
+    pub fn new(frequency: f32, volume: f32, sample_rate: u32) -> Beeper {
+        Beeper {
+            frequency,
+            volume: volume.clamp(0.0, 1.0),
+            sample_rate,
+            pattern: None,
+            phase: 0.0,
+        }
+    }
+
+    // The XO-CHIP pattern playback rate in bits per second for a pitch
+    // register value.  64 is the default, 4000Hz.
+    pub fn playback_rate(pitch: u8) -> f32 {
+        4000.0 * 2.0_f32.powf((f32::from(pitch) - 64.0) / 48.0)
+    }
+
+    // Switches to playing an XO-CHIP audio pattern at the given pitch
+    pub fn set_pattern(&mut self, pattern: [u8; 16], pitch: u8) {
+        self.pattern = Some(pattern);
+        self.frequency = Beeper::playback_rate(pitch) / 128.0;
+        self.phase = 0.0;
+    }
+
+    // The next sample of the square wave: high for the first half of
+    // each period, low for the second.  Or, with a pattern, high while
+    // the current bit of the pattern is set.
+    pub fn next_sample(&mut self) -> f32 {
+        let high = match self.pattern {
+            Some(pattern) => {
+                let bit = (self.phase * 128.0) as usize % 128;
+                (pattern[bit / 8] >> (7 - bit % 8)) & 1 == 1
+            }
+            None => self.phase < 0.5,
+        };
+        let sample = if high { self.volume } else { -self.volume };
+        self.phase = (self.phase + self.frequency / self.sample_rate as f32) % 1.0;
+        sample
+    }
+
+    // Fills a buffer with the next samples of the wave
+    pub fn fill(&mut self, buffer: &mut [f32]) {
+        for sample in buffer.iter_mut() {
+            *sample = self.next_sample();
+        }
+    }
+
+    // How many samples are in `periods` whole periods of the wave, so that a
+    // clip of that length loops without clicking.
+    pub fn period_samples(&self, periods: usize) -> usize {
+        ((self.sample_rate as f32 * periods as f32) / self.frequency).round() as usize
+    }
+
+    // Renders `samples` samples as a 16 bit mono PCM wav file, for
+    // frontends that want an encoded clip rather than raw samples.
+    pub fn to_wav(&mut self, samples: usize) -> Vec<u8> {
+        let data_len = (samples * 2) as u32;
+        let mut wav = Vec::with_capacity(44 + samples * 2);
+
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+
+        wav.extend_from_slice(b"fmt ");
+        wav.extend_from_slice(&16_u32.to_le_bytes()); // Size of this chunk
+        wav.extend_from_slice(&1_u16.to_le_bytes()); // PCM
+        wav.extend_from_slice(&1_u16.to_le_bytes()); // Mono
+        wav.extend_from_slice(&self.sample_rate.to_le_bytes());
+        wav.extend_from_slice(&(self.sample_rate * 2).to_le_bytes()); // Bytes per second
+        wav.extend_from_slice(&2_u16.to_le_bytes()); // Bytes per frame
+        wav.extend_from_slice(&16_u16.to_le_bytes()); // Bits per sample
+
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for _ in 0..samples {
+            let sample = (self.next_sample() * f32::from(i16::MAX)) as i16;
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        wav
+    }
+}
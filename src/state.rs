@@ -0,0 +1,305 @@
+use crate::cpu::Cpu;
+use crate::quirks::{LoadStore, Quirks};
+use std::fmt;
+
+// Save states are a small versioned binary format:
+//   "R8SS" magic, u16 version, u64 hash of the ROM, then the machine.
+// All numbers are little endian.  Bump STATE_VERSION whenever the layout
+// of the machine changes; old states are refused rather than misread.
+pub const STATE_MAGIC: &[u8; 4] = b"R8SS";
+pub const STATE_VERSION: u16 = 2;
+
+// Why a save state could not be loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion { version: u16 },
+    RomMismatch { expected: u64, found: u64 },
+    Truncated,
+    Invalid,
+}
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion { version } => write!(
+                f,
+                "unsupported save state version {} (expected {})",
+                version, STATE_VERSION
+            ),
+            StateError::RomMismatch { expected, found } => write!(
+                f,
+                "save state is for a different ROM (hash {:016X}, loaded ROM is {:016X})",
+                found, expected
+            ),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid => write!(f, "save state is corrupt"),
+        }
+    }
+}
+impl std::error::Error for StateError {}
+
+// FNV-1a, used to tie save states to the ROM they were made with and to
+// compare framebuffers
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    fnv1a_extend(0xCBF2_9CE4_8422_2325, bytes)
+}
+
+pub fn fnv1a_extend(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+// Little endian encoding shared by save states and movies
+pub(crate) struct Writer {
+    pub(crate) bytes: Vec<u8>,
+}
+impl Writer {
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+    pub(crate) fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+    pub(crate) fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    pub(crate) fn u32(&mut self, value: usize) {
+        self.bytes.extend_from_slice(&(value as u32).to_le_bytes());
+    }
+    pub(crate) fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    pub(crate) fn bytes(&mut self, value: &[u8]) {
+        self.bytes.extend_from_slice(value);
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+    pub(crate) pos: usize,
+}
+impl<'a> Reader<'a> {
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.pos + len > self.bytes.len() {
+            return Err(StateError::Truncated);
+        }
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+    pub(crate) fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+    pub(crate) fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid),
+        }
+    }
+    pub(crate) fn u16(&mut self) -> Result<u16, StateError> {
+        let mut b = [0; 2];
+        b.copy_from_slice(self.bytes(2)?);
+        Ok(u16::from_le_bytes(b))
+    }
+    pub(crate) fn u32(&mut self) -> Result<usize, StateError> {
+        let mut b = [0; 4];
+        b.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(b) as usize)
+    }
+    pub(crate) fn u64(&mut self) -> Result<u64, StateError> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+    pub(crate) fn array16(&mut self) -> Result<[u8; 16], StateError> {
+        let mut a = [0; 16];
+        a.copy_from_slice(self.bytes(16)?);
+        Ok(a)
+    }
+    pub(crate) fn bools16(&mut self) -> Result<[bool; 16], StateError> {
+        let mut a = [false; 16];
+        for b in a.iter_mut() {
+            *b = self.bool()?;
+        }
+        Ok(a)
+    }
+}
+
+impl Cpu {
+    // Serializes the whole machine, so it can be restored with load_state()
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = Writer { bytes: Vec::new() };
+        w.bytes(STATE_MAGIC);
+        w.u16(STATE_VERSION);
+        w.u64(self.rom_hash);
+
+        let q = &self.quirks;
+        w.bool(q.shift);
+        // Once a bool, so the first two keep their old values
+        w.u8(match q.load_store {
+            LoadStore::Unchanged => 0,
+            LoadStore::AddXPlusOne => 1,
+            LoadStore::AddX => 2,
+        });
+        for quirk in &[q.jump_with_vx, q.clip, q.vf_reset, q.display_wait] {
+            w.bool(*quirk);
+        }
+
+        w.u32(self.memory.len());
+        w.bytes(&self.memory);
+        w.u16(self.opcode);
+        w.bytes(&self.v);
+        w.u32(self.i);
+        w.u32(self.pc);
+
+        w.bool(self.hires);
+        for row in &self.gfx {
+            w.bytes(row);
+        }
+
+        w.u8(self.delay_timer);
+        w.u8(self.sound_timer);
+        for address in &self.stack {
+            w.u32(*address);
+        }
+        w.u32(self.sp);
+
+        for key in &self.input.keys {
+            w.bool(*key);
+        }
+        w.bool(self.input.read_keys);
+        w.u32(self.input.key_target);
+        w.u8(self.input.key_pressed.map_or(0xFF, |k| k as u8));
+        for key in &self.input.last_keys {
+            w.bool(*key);
+        }
+
+        w.bool(self.vblank);
+        match self.rng.state() {
+            Some(state) => {
+                w.bool(true);
+                w.u64(state);
+            }
+            None => w.bool(false),
+        }
+        w.bool(self.exited);
+        w.bytes(&self.rpl);
+        w.u8(self.planes);
+        match self.audio_pattern {
+            Some(pattern) => {
+                w.bool(true);
+                w.bytes(&pattern);
+            }
+            None => w.bool(false),
+        }
+        w.u8(self.pitch);
+        w.u64(self.cycles);
+
+        w.bytes
+    }
+
+    // Restores a state made by save_state().  The state must come from the
+    // same format version and the same ROM, and on any error the cpu is
+    // left exactly as it was.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut r = Reader {
+            bytes: state,
+            pos: 0,
+        };
+        if state.len() < STATE_MAGIC.len() || r.bytes(STATE_MAGIC.len())? != STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = r.u16()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion { version });
+        }
+        let found = r.u64()?;
+        if found != self.rom_hash {
+            return Err(StateError::RomMismatch {
+                expected: self.rom_hash,
+                found,
+            });
+        }
+
+        // Check the whole state reads cleanly before touching anything
+        let body = r.pos;
+        Cpu::new().read_state(&mut r)?;
+        r.pos = body;
+        self.read_state(&mut r)?;
+        self.rom_hash = found;
+        Ok(())
+    }
+
+    fn read_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.quirks = Quirks {
+            shift: r.bool()?,
+            load_store: match r.u8()? {
+                0 => LoadStore::Unchanged,
+                1 => LoadStore::AddXPlusOne,
+                2 => LoadStore::AddX,
+                _ => return Err(StateError::Invalid),
+            },
+            jump_with_vx: r.bool()?,
+            clip: r.bool()?,
+            vf_reset: r.bool()?,
+            display_wait: r.bool()?,
+        };
+
+        let memory_size = r.u32()?;
+        self.memory = r.bytes(memory_size)?.to_vec();
+        self.opcode = r.u16()?;
+        self.v = r.array16()?;
+        self.i = r.u32()?;
+        self.pc = r.u32()?;
+
+        self.set_hires(r.bool()?);
+        let width = self.width();
+        for row in self.gfx.iter_mut() {
+            row.copy_from_slice(r.bytes(width)?);
+        }
+
+        self.delay_timer = r.u8()?;
+        self.sound_timer = r.u8()?;
+        for address in self.stack.iter_mut() {
+            *address = r.u32()?;
+        }
+        self.sp = r.u32()?;
+        if self.sp > self.stack.len() {
+            return Err(StateError::Invalid);
+        }
+
+        self.input.keys = r.bools16()?;
+        self.input.read_keys = r.bool()?;
+        self.input.key_target = r.u32()?;
+        self.input.key_pressed = match r.u8()? {
+            0xFF => None,
+            k if k < 16 => Some(k as usize),
+            _ => return Err(StateError::Invalid),
+        };
+        self.input.last_keys = r.bools16()?;
+        if self.input.key_target >= self.v.len() {
+            return Err(StateError::Invalid);
+        }
+
+        self.vblank = r.bool()?;
+        if r.bool()? {
+            self.rng.set_state(r.u64()?);
+        }
+        self.exited = r.bool()?;
+        self.rpl = r.array16()?;
+        self.planes = r.u8()?;
+        self.audio_pattern = if r.bool()? { Some(r.array16()?) } else { None };
+        self.pitch = r.u8()?;
+        self.cycles = r.u64()?;
+        if r.pos != r.bytes.len() {
+            return Err(StateError::Invalid);
+        }
+
+        self.gfx_updated = true;
+        self.audio_updated = true;
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+use crate::cpu::Cpu;
+use crate::instruction::Instruction;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Per-instruction execution traces, and comparing them with another
+// emulator's to find where the two part ways.  A trace is a text file with
+// one line per instruction:
+//
+//   cycle=3 pc=0206 op=220C v=05000000000000000000000000000000 i=0216 sp=0 dt=00 st=00
+//     v'=05000000000000000000000000000000 i'=0216 sp'=1 dt'=00 st'=00 ; CALL 0x20C
+//
+// (all on one line).  Fields are key=value, with a ' on the key for the
+// state after the instruction; the v fields are V0 - VF as hex bytes.
+// Anything after a ; and lines starting with # are for people, so a
+// reference trace only needs the fields it has, in any order.
+
+pub const TRACE_HEADER: &str = "# r8 trace: cycle pc op, then v i sp dt st before and after\n";
+
+// A buffered trace file, header written, ready for Cpu::trace
+pub fn open_trace(path: &Path) -> io::Result<Box<dyn Write>> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(TRACE_HEADER.as_bytes())?;
+    Ok(Box::new(file))
+}
+
+// The registers a trace line records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRegisters {
+    pub v: [u8; 16],
+    pub i: usize,
+    pub sp: usize,
+    pub dt: u8,
+    pub st: u8,
+}
+
+impl TraceRegisters {
+    pub fn of(cpu: &Cpu) -> TraceRegisters {
+        TraceRegisters {
+            v: cpu.v,
+            i: cpu.i,
+            sp: cpu.sp,
+            dt: cpu.delay_timer,
+            st: cpu.sound_timer,
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter, mark: &str) -> fmt::Result {
+        write!(f, "v{}=", mark)?;
+        for v in &self.v {
+            write!(f, "{:02X}", v)?;
+        }
+        write!(
+            f,
+            " i{}={:04X} sp{}={} dt{}={:02X} st{}={:02X}",
+            mark, self.i, mark, self.sp, mark, self.dt, mark, self.st
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceLine {
+    pub cycle: u64,
+    pub pc: usize,
+    pub opcode: u32, // Eight hex digits for the four byte F000 NNNN
+    pub instruction: Option<Instruction>,
+    pub before: TraceRegisters,
+    pub after: TraceRegisters,
+}
+
+impl fmt::Display for TraceLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cycle={} pc={:04X} ", self.cycle, self.pc)?;
+        if self.opcode > 0xFFFF {
+            write!(f, "op={:08X} ", self.opcode)?;
+        } else {
+            write!(f, "op={:04X} ", self.opcode)?;
+        }
+        self.before.write(f, "")?;
+        write!(f, " ")?;
+        self.after.write(f, "'")?;
+        match self.instruction {
+            Some(instruction) => write!(f, " ; {}", instruction),
+            None => write!(f, " ; ???"),
+        }
+    }
+}
+
+// Where two traces first differ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub index: usize, // Instructions in, from 0
+    pub ours: String,
+    pub reference: String,
+    pub fields: Vec<(String, String, String)>, // (field, ours, reference)
+    pub previous: Option<String>,              // Our last line that matched
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "traces diverge at instruction {}", self.index)?;
+        if let Some(previous) = &self.previous {
+            writeln!(f, "  after:     {}", previous)?;
+        }
+        writeln!(f, "  ours:      {}", self.ours)?;
+        writeln!(f, "  reference: {}", self.reference)?;
+        for (field, ours, reference) in &self.fields {
+            writeln!(f, "  {}: {} vs {}", field, ours, reference)?;
+        }
+        Ok(())
+    }
+}
+
+// The fields of a trace line, or None for a comment or blank line
+pub fn trace_fields(line: &str) -> Option<BTreeMap<String, String>> {
+    let line = line.split(';').next().unwrap_or("").trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    Some(
+        line.split_whitespace()
+            .filter_map(|field| field.split_once('='))
+            .map(|(key, value)| (key.to_ascii_lowercase(), value.to_ascii_uppercase()))
+            .collect(),
+    )
+}
+
+// Compares two traces instruction by instruction, on the fields both have
+// and aren't told to ignore ("dt" ignores dt' too).  If they agree for as
+// long as both run, returns how many instructions that was.
+pub fn diff_traces(ours: &str, reference: &str, ignore: &[String]) -> Result<usize, Divergence> {
+    let lines = |text: &str| -> Vec<(String, BTreeMap<String, String>)> {
+        text.lines()
+            .filter_map(|line| trace_fields(line).map(|fields| (line.to_string(), fields)))
+            .collect()
+    };
+    let ours = lines(ours);
+    let reference = lines(reference);
+    for (index, (a, b)) in ours.iter().zip(&reference).enumerate() {
+        let mut fields = Vec::new();
+        for (key, value) in &a.1 {
+            if ignore
+                .iter()
+                .any(|field| field == key || field == key.trim_end_matches('\''))
+            {
+                continue;
+            }
+            match b.1.get(key) {
+                Some(other) if numbers_differ(value, other) => {
+                    fields.extend(field_differences(key, value, other))
+                }
+                _ => (),
+            }
+        }
+        if !fields.is_empty() {
+            return Err(Divergence {
+                index,
+                ours: a.0.clone(),
+                reference: b.0.clone(),
+                fields,
+                previous: index.checked_sub(1).map(|i| ours[i].0.clone()),
+            });
+        }
+    }
+    Ok(ours.len().min(reference.len()))
+}
+
+// Hex values are compared as numbers, so 0206 and 206 agree
+fn numbers_differ(a: &str, b: &str) -> bool {
+    let number = |s: &str| u128::from_str_radix(s.trim_start_matches("0X"), 16).ok();
+    match (number(a), number(b)) {
+        (Some(a), Some(b)) => a != b,
+        _ => a != b,
+    }
+}
+
+// The v fields are split up to say which registers differ
+fn field_differences(key: &str, ours: &str, reference: &str) -> Vec<(String, String, String)> {
+    let after = if key.ends_with('\'') { " after" } else { "" };
+    if (key == "v" || key == "v'") && ours.len() == 32 && reference.len() == 32 {
+        (0..16)
+            .filter(|x| ours[x * 2..x * 2 + 2] != reference[x * 2..x * 2 + 2])
+            .map(|x| {
+                (
+                    format!("V{:X}{}", x, after),
+                    ours[x * 2..x * 2 + 2].to_string(),
+                    reference[x * 2..x * 2 + 2].to_string(),
+                )
+            })
+            .collect()
+    } else {
+        vec![(key.to_string(), ours.to_string(), reference.to_string())]
+    }
+}
@@ -0,0 +1,162 @@
+extern crate lib;
+use lib::{analyze, assemble, disassemble_analyzed, ByteClass, Item};
+
+// Draws a box, then picks one of two branches through a jump table.  The
+// left one patches the code the right one falls into.
+const PROGRAM: &str = "
+: main
+  i := box
+  sprite v0 v1 4
+  v0 := random 1
+  v0 += v0
+  jump0 table
+: table
+  jump left
+  jump right
+: left
+  i := patch
+  save v1
+  draw
+  exit
+: right
+  draw
+: patch
+  clear
+  exit
+: draw
+  sprite v0 v1 4
+  return
+: box
+  0xF0 0x90 0x90 0xF0
+: unused
+  0x12 0x34
+";
+
+fn program() -> Vec<u8> {
+    assemble(PROGRAM).unwrap().rom
+}
+
+#[test]
+fn test_blocks() {
+    let analysis = analyze(&program(), 0x200);
+    let blocks: Vec<(usize, usize, Vec<usize>, Option<usize>)> = analysis
+        .blocks
+        .values()
+        .map(|b| (b.start, b.end, b.successors.clone(), b.call))
+        .collect();
+    assert_eq!(
+        blocks,
+        vec![
+            (0x200, 0x20A, vec![0x20A, 0x20C], None),
+            (0x20A, 0x20C, vec![0x20E], None),
+            (0x20C, 0x20E, vec![0x216], None),
+            (0x20E, 0x214, vec![0x214], Some(0x21C)),
+            (0x214, 0x216, vec![], None),
+            (0x216, 0x218, vec![0x218], Some(0x21C)),
+            (0x218, 0x21C, vec![], None),
+            (0x21C, 0x220, vec![], None),
+        ]
+    );
+    assert_eq!(analysis.calls.len(), 2);
+    assert_eq!(
+        analysis.calls[&0x200].iter().copied().collect::<Vec<_>>(),
+        vec![0x21C]
+    );
+    assert!(analysis.calls[&0x21C].is_empty());
+}
+
+#[test]
+fn test_classes() {
+    let analysis = analyze(&program(), 0x200);
+    assert_eq!(
+        analysis.map(),
+        vec![
+            (0x200, 0x220, ByteClass::Code),
+            (0x220, 0x224, ByteClass::Sprite),
+            (0x224, 0x226, ByteClass::Unknown),
+        ]
+    );
+    assert_eq!(analysis.class_at(0x1FF), ByteClass::Unknown);
+    assert_eq!(analysis.class_at(0x300), ByteClass::Unknown);
+}
+
+#[test]
+fn test_flags() {
+    let analysis = analyze(&program(), 0x200);
+    assert_eq!(
+        analysis.computed_jumps.iter().copied().collect::<Vec<_>>(),
+        vec![0x208]
+    );
+    assert_eq!(
+        analysis.self_modifying.iter().collect::<Vec<_>>(),
+        vec![(&0x210, &0x218)]
+    );
+    assert!(analysis.invalid.is_empty());
+
+    let notes = analysis.annotations();
+    assert_eq!(notes[&0x208], "computed jump");
+    assert_eq!(notes[&0x210], "writes to code at 0x218");
+}
+
+#[test]
+fn test_tracking_i() {
+    // I is known going into the subroutine, but not once it returns, and
+    // the skip steps over the whole of the F000 long load
+    let rom = [
+        0xA2, 0x12, // i := table
+        0x22, 0x0E, // show
+        0xF3, 0x65, // load v3
+        0x40, 0x01, // if v0 == 1 then
+        0xF0, 0x00, 0x02, 0x12, // i := long table
+        0x12, 0x0C, // loop again
+        0xD0, 0x12, // : show sprite v0 v1 2
+        0x00, 0xEE, // return
+        0x01, 0x02, 0x03, 0x04, // : table
+    ];
+    let analysis = analyze(&rom, 0x200);
+    assert_eq!(analysis.class_at(0x212), ByteClass::Sprite);
+    assert_eq!(analysis.class_at(0x213), ByteClass::Sprite);
+    assert_eq!(analysis.class_at(0x214), ByteClass::Unknown);
+    assert_eq!(analysis.class_at(0x20B), ByteClass::Code);
+    assert_eq!(analysis.blocks[&0x204].successors, vec![0x208, 0x20C]);
+
+    // Running into something that isn't an instruction
+    let analysis = analyze(&[0x00, 0xE0, 0xFF, 0xFF], 0x200);
+    assert_eq!(
+        analysis.invalid.iter().copied().collect::<Vec<_>>(),
+        vec![0x202]
+    );
+    assert_eq!(analysis.class_at(0x202), ByteClass::Unknown);
+    assert_eq!(
+        analysis.annotations()[&0x202],
+        "reached, but not an instruction"
+    );
+}
+
+#[test]
+fn test_json_and_listing() {
+    let rom = program();
+    let analysis = analyze(&rom, 0x200);
+    let json = analysis.to_json();
+    assert!(json.starts_with("{\n  \"origin\": 512,\n  \"size\": 38,\n"));
+    assert!(json.contains("{\"start\": 526, \"end\": 532, \"successors\": [532], \"call\": 540}"));
+    assert!(json.contains("\"computed_jumps\": [520],"));
+    assert!(json.contains("{\"at\": 528, \"writes\": 536}"));
+    assert!(json.contains("{\"start\": 544, \"end\": 548, \"class\": \"sprite\"}"));
+
+    let disassembly = disassemble_analyzed(&rom, 0x200, &analysis);
+    let text = disassembly.to_text();
+    assert!(text.contains("JP V0, table_20A     ; computed jump\n"));
+    assert!(text.contains("label_218:\n  0x218  00E0      CLS\n"));
+    let octo = disassembly.to_octo();
+    assert!(octo.contains("  save v1  # writes to code at 0x218\n"));
+    assert_eq!(assemble(&octo).unwrap().rom, rom);
+
+    // Bytes known to be data aren't decoded
+    let lines: Vec<(usize, Item)> = disassembly
+        .lines
+        .iter()
+        .map(|line| (line.address, line.item))
+        .collect();
+    assert!(lines.contains(&(0x220, Item::Sprite)));
+}
@@ -0,0 +1,117 @@
+extern crate lib;
+use lib::{assemble, Cpu};
+
+fn rom(source: &str) -> Vec<u8> {
+    assemble(source).unwrap().rom
+}
+
+fn error_line(source: &str) -> usize {
+    assemble(source).unwrap_err().line
+}
+
+#[test]
+fn test_statements() {
+    assert_eq!(
+        rom("clear return v3 := 0x12 v3 += 1 v3 := v4 v3 -= v4 v3 =- v4"),
+        vec![0x00, 0xE0, 0x00, 0xEE, 0x63, 0x12, 0x73, 0x01, 0x83, 0x40, 0x83, 0x45, 0x83, 0x47]
+    );
+    assert_eq!(
+        rom("v1 |= v2 v1 &= v2 v1 ^= v2 v1 >>= v2 v1 <<= v2"),
+        vec![0x81, 0x21, 0x81, 0x22, 0x81, 0x23, 0x81, 0x26, 0x81, 0x2E]
+    );
+    assert_eq!(
+        rom("v5 := random 0xF v5 := delay v5 := key delay := v5 buzzer := v5"),
+        vec![0xC5, 0x0F, 0xF5, 0x07, 0xF5, 0x0A, 0xF5, 0x15, 0xF5, 0x18]
+    );
+    assert_eq!(
+        rom("i := 0x300 i += v2 i := hex v2 bcd v2 save v4 load v4 sprite v1 v2 5"),
+        vec![0xA3, 0x00, 0xF2, 0x1E, 0xF2, 0x29, 0xF2, 0x33, 0xF4, 0x55, 0xF4, 0x65, 0xD1, 0x25]
+    );
+    assert_eq!(
+        rom("hires lores scroll-down 4 exit"),
+        vec![0x00, 0xFF, 0x00, 0xFE, 0x00, 0xC4, 0x00, 0xFD]
+    );
+    assert_eq!(rom("v0 += -1"), vec![0x70, 0xFF]);
+}
+
+#[test]
+fn test_labels() {
+    let asm =
+        assemble(": main\n  jump done\n  sub\n: sub\n  return\n: done\n  jump0 main\n").unwrap();
+    assert_eq!(
+        asm.rom,
+        vec![0x12, 0x06, 0x22, 0x04, 0x00, 0xEE, 0xB2, 0x00]
+    );
+    assert_eq!(asm.symbols["main"], 0x200);
+    assert_eq!(asm.symbols["sub"], 0x204);
+    assert_eq!(asm.symbols["done"], 0x206);
+    assert_eq!(asm.symbol_table(), "0x200 main\n0x204 sub\n0x206 done\n");
+    assert_eq!(asm.lines[&0x200], 2);
+    assert_eq!(asm.lines[&0x206], 7);
+}
+
+#[test]
+fn test_data() {
+    let asm = assemble(":const SPEED 3\nv0 += SPEED\n:byte 1 0x2 0b11\n4\n:org 0x208\n: here 0xFF")
+        .unwrap();
+    assert_eq!(asm.constants["SPEED"], 3);
+    assert_eq!(
+        asm.rom,
+        vec![0x70, 0x03, 0x01, 0x02, 0x03, 0x04, 0x00, 0x00, 0xFF]
+    );
+    assert_eq!(asm.symbols["here"], 0x208);
+}
+
+#[test]
+fn test_control_flow() {
+    // if ... then skips the one instruction when the condition fails, and
+    // if ... begin jumps over the block
+    assert_eq!(
+        rom("if v0 == 60 then v0 := 0 if v1 != v2 then clear if v3 key then clear"),
+        vec![0x40, 0x3C, 0x60, 0x00, 0x51, 0x20, 0x00, 0xE0, 0xE3, 0xA1, 0x00, 0xE0]
+    );
+    assert_eq!(
+        rom("if v0 == 1 begin clear else return end"),
+        vec![0x30, 0x01, 0x12, 0x08, 0x00, 0xE0, 0x12, 0x0A, 0x00, 0xEE]
+    );
+    assert_eq!(
+        rom("loop v0 += 1 while v0 != 10 again"),
+        vec![0x70, 0x01, 0x40, 0x0A, 0x12, 0x08, 0x12, 0x00]
+    );
+}
+
+#[test]
+fn test_long_addresses() {
+    let asm = assemble("i := long data\n:org 0x1234\n: data 1").unwrap();
+    assert_eq!(&asm.rom[..4], &[0xF0, 0x00, 0x12, 0x34]);
+    assert_eq!(asm.rom.len(), 0x1234 - 0x200 + 1);
+}
+
+#[test]
+fn test_runs() {
+    // Counts v0 up to 5 and exits
+    let asm = assemble("v0 := 0 loop v0 += 1 while v0 != 5 again exit").unwrap();
+    let mut cpu = Cpu::new();
+    cpu.load_rom_bytes(&asm.rom).unwrap();
+    for _ in 0..100 {
+        if cpu.exited {
+            break;
+        }
+        cpu.tick(false).unwrap();
+    }
+    assert!(cpu.exited);
+    assert_eq!(cpu.v[0], 5);
+}
+
+#[test]
+fn test_errors() {
+    assert_eq!(error_line("clear\njump nowhere"), 2);
+    assert_eq!(error_line("clear\n\nfrobnicate"), 3);
+    assert_eq!(error_line("v0 := 300"), 1);
+    assert_eq!(error_line(": a\n: a"), 2);
+    assert_eq!(error_line("clear\nloop\nclear"), 2);
+    assert_eq!(error_line("if v0 == 1 then"), 1);
+    assert_eq!(error_line("v0 := SPEED\n:const SPEED 1"), 1);
+    let err = assemble("clear\nsprite v0 v1 16").unwrap_err();
+    assert_eq!(err.to_string(), format!("line 2: {}", err.message));
+}
@@ -0,0 +1,88 @@
+extern crate lib;
+use lib::{assemble, disassemble, line_at, CodeView, Cpu};
+use std::collections::BTreeSet;
+
+const SOURCE: &str = ": main
+  i := long buffer
+  v0 := 1
+  loop
+    count
+    if v0 == 3 then v1 := 1
+    v0 += 1
+  again
+: count
+  v2 += 1
+  return
+: buffer
+";
+
+fn boot() -> (Cpu, lib::Disassembly) {
+    let rom = assemble(SOURCE).unwrap().rom;
+    let mut cpu = Cpu::new();
+    cpu.load_rom_bytes(&rom).unwrap();
+    (cpu, disassemble(&rom, 0x200))
+}
+
+#[test]
+fn test_follows_pc() {
+    let (mut cpu, disassembly) = boot();
+    let mut view = CodeView::new(9);
+    let breakpoints: BTreeSet<usize> = vec![0x206].into_iter().collect();
+
+    // Nothing before the rom worth scrolling back over, but the view goes
+    // back anyway to keep the PC a third of the way down
+    let lines = view.lines(&cpu, &disassembly, &breakpoints);
+    let addresses: Vec<usize> = lines.iter().map(|l| l.address).collect();
+    assert_eq!(
+        addresses,
+        vec![0x1FA, 0x1FC, 0x1FE, 0x200, 0x204, 0x206, 0x208, 0x20A, 0x20C]
+    );
+    assert!(lines[3].current);
+    assert_eq!(lines[3].text, "LD I, LONG 0x214");
+    assert!(lines[5].breakpoint);
+    assert_eq!(lines[5].text, "label_206: CALL sub_210");
+    assert_eq!(lines[5].note.as_deref(), Some("down to 210"));
+
+    // Stepping inside the view doesn't scroll it
+    cpu.run_frame(2).unwrap();
+    let lines = view.lines(&cpu, &disassembly, &breakpoints);
+    assert_eq!(lines[0].address, 0x1FA);
+    assert!(lines[5].current);
+    assert_eq!(line_at(&lines, 5.0 * 15.0 + 1.0).unwrap().address, 0x206);
+    assert_eq!(line_at(&lines, -1.0), None);
+
+    // Going out of view scrolls it
+    cpu.run_frame(1).unwrap();
+    let lines = view.lines(&cpu, &disassembly, &breakpoints);
+    assert_eq!(lines[0].address, 0x20A);
+    assert_eq!(lines[3].address, 0x210);
+    assert!(lines[3].current);
+    assert!(lines.iter().all(|l| !l.target));
+}
+
+#[test]
+fn test_branches() {
+    let (mut cpu, disassembly) = boot();
+    let mut view = CodeView::new(21);
+    let none = BTreeSet::new();
+
+    // At the return, it goes back after the call
+    cpu.run_frame(4).unwrap();
+    assert_eq!(cpu.pc, 0x212);
+    let lines = view.lines(&cpu, &disassembly, &none);
+    let at = |address: usize| lines.iter().find(|l| l.address == address).unwrap();
+    assert_eq!(at(0x212).note.as_deref(), Some("back to 208"));
+    assert!(at(0x208).target);
+    assert_eq!(at(0x208).note.as_deref(), Some("may skip to 20C"));
+    assert_eq!(at(0x20E).note.as_deref(), Some("up to 206"));
+
+    // At the skip, where it lands if taken
+    cpu.run_frame(1).unwrap();
+    let lines = view.lines(&cpu, &disassembly, &none);
+    let targets: Vec<usize> = lines
+        .iter()
+        .filter(|l| l.target)
+        .map(|l| l.address)
+        .collect();
+    assert_eq!(targets, vec![0x20C]);
+}
@@ -1,5 +1,22 @@
+// Some of the oldest tests here predate these lints
+#![allow(
+    unused_assignments,
+    clippy::unnecessary_cast,
+    clippy::legacy_numeric_constants
+)]
 extern crate lib;
-use lib::{Cpu, OPCODE_SIZE};
+use lib::{
+    Cpu, CpuError, LoadStore, Platform, Quirks, RandomSource, MEMORY_SIZE, OPCODE_SIZE,
+    XO_MEMORY_SIZE,
+};
+
+// Always "randomly" returns the same byte
+struct FixedRandom(u8);
+impl RandomSource for FixedRandom {
+    fn next_byte(&mut self) -> u8 {
+        self.0
+    }
+}
 
 #[test]
 fn test_get_digit() {
@@ -27,7 +44,7 @@ fn test_op_00e0() {
     cpu.gfx[0][0] = 1;
     assert_eq!(1, cpu.gfx[0][0]);
 
-    cpu.run_opcode(0x00E0, Some(false));
+    cpu.run_opcode(0x00E0, Some(false)).unwrap();
 
     assert_eq!(0, cpu.gfx[0][0]);
 }
@@ -40,7 +57,7 @@ fn test_op_00ee() {
     cpu.stack[cpu.sp] = 0x201;
     let target = cpu.stack[cpu.sp - 1]; // where pc should end up
 
-    cpu.run_opcode(0x00EE, Some(false));
+    cpu.run_opcode(0x00EE, Some(false)).unwrap();
 
     assert_eq!(target, cpu.pc);
 }
@@ -49,7 +66,7 @@ fn test_op_00ee() {
 fn test_op_1nnn() {
     let mut cpu = Cpu::new();
 
-    cpu.run_opcode(0x1201, Some(false)); // PC should jump to 0x201
+    cpu.run_opcode(0x1201, Some(false)).unwrap(); // PC should jump to 0x201
 
     assert_eq!(cpu.pc, 0x201);
 }
@@ -58,7 +75,7 @@ fn test_op_1nnn() {
 fn test_op_2nnn() {
     let mut cpu = Cpu::new();
 
-    cpu.run_opcode(0x2201, Some(false));
+    cpu.run_opcode(0x2201, Some(false)).unwrap();
 
     // sp incremented after opcode has run
     assert_eq!(cpu.sp, 1);
@@ -79,12 +96,12 @@ fn test_op_3xkk() {
     cpu.v[x] = 3 as u8;
 
     // After skip, cpu.pc should have moved up two opcode size
-    cpu.run_opcode(0x3103, Some(false));
+    cpu.run_opcode(0x3103, Some(false)).unwrap();
     assert_eq!(cpu.pc, p + (OPCODE_SIZE * 2));
 
     // Should not skip, cpu.pc should be up one opcode size
     p = cpu.pc;
-    cpu.run_opcode(0x3104, Some(false)); // 3 != 4
+    cpu.run_opcode(0x3104, Some(false)).unwrap(); // 3 != 4
     assert_eq!(cpu.pc, p + OPCODE_SIZE);
 }
 
@@ -98,12 +115,12 @@ fn test_op_4xkk() {
     cpu.v[x] = 3 as u8;
 
     // Should skip
-    cpu.run_opcode(0x4101, Some(false)); // 3 != 1
+    cpu.run_opcode(0x4101, Some(false)).unwrap(); // 3 != 1
     assert_eq!(cpu.pc, p + (OPCODE_SIZE * 2));
 
     // Should not skip
     p = cpu.pc;
-    cpu.run_opcode(0x4103, Some(false)); // 3 = 1
+    cpu.run_opcode(0x4103, Some(false)).unwrap(); // 3 = 1
     assert_eq!(cpu.pc, p + OPCODE_SIZE);
 }
 
@@ -116,11 +133,11 @@ fn test_op_5xy0() {
     cpu.v[1] = 1;
 
     let mut pc = cpu.pc;
-    cpu.run_opcode(0x5010, Some(false)); // v[0] == v[1] ( should skip )
+    cpu.run_opcode(0x5010, Some(false)).unwrap(); // v[0] == v[1] ( should skip )
     assert_eq!(cpu.pc, pc + (OPCODE_SIZE * 2));
 
     pc = cpu.pc;
-    cpu.run_opcode(0x5020, Some(false)); // v[0] != v[2] ( should not skip )
+    cpu.run_opcode(0x5020, Some(false)).unwrap(); // v[0] != v[2] ( should not skip )
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
 }
 
@@ -130,7 +147,7 @@ fn test_op_6xkk() {
     let mut cpu = Cpu::new();
 
     let pc = cpu.pc;
-    cpu.run_opcode(0x61F0, Some(false));
+    cpu.run_opcode(0x61F0, Some(false)).unwrap();
 
     // Vx should = F0
     assert_eq!(cpu.v[1], 0xF0);
@@ -146,19 +163,19 @@ fn test_op_7xkk() {
     assert_eq!(cpu.v[x], 0x00);
 
     // Test add without overflow
-    cpu.run_opcode(0x7001, Some(false));
+    cpu.run_opcode(0x7001, Some(false)).unwrap();
     assert_eq!(cpu.v[x], 0x01);
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
 
     // Test add with overflow on a different register
     x = 1;
     pc = cpu.pc;
-    cpu.run_opcode(0x71ff, Some(false));
+    cpu.run_opcode(0x71ff, Some(false)).unwrap();
     assert_eq!(cpu.v[x], u8::max_value());
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
 
     pc = cpu.pc;
-    cpu.run_opcode(0x7102, Some(false));
+    cpu.run_opcode(0x7102, Some(false)).unwrap();
     assert_eq!(cpu.v[x], 0x01);
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
 }
@@ -169,7 +186,7 @@ fn test_op_8xy0() {
     let mut cpu = Cpu::new();
     let p = cpu.pc;
     cpu.v[1] = 0x05;
-    cpu.run_opcode(0x8010, Some(false));
+    cpu.run_opcode(0x8010, Some(false)).unwrap();
 
     assert_eq!(0x05, cpu.v[0]);
     assert_eq!(cpu.v[0], cpu.v[1]);
@@ -186,7 +203,7 @@ fn test_op_8xy1() {
 
     let pc = cpu.pc;
     // Should bitwise OR v[0] and v[1]
-    cpu.run_opcode(0x8011, Some(false));
+    cpu.run_opcode(0x8011, Some(false)).unwrap();
 
     assert_eq!(cpu.v[0], 0b1001);
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
@@ -202,7 +219,7 @@ fn test_op_8xy2() {
     cpu.v[1] = 0b1011;
 
     // Should bitwise OR v[0] and v[1]
-    cpu.run_opcode(0x8012, Some(false));
+    cpu.run_opcode(0x8012, Some(false)).unwrap();
 
     assert_eq!(cpu.v[0], 0b1001);
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
@@ -219,7 +236,7 @@ fn test_op_8xy3() {
     cpu.v[1] = 0b1011;
 
     // Should bitwise OR v[0] and v[1]
-    cpu.run_opcode(0x8013, Some(false));
+    cpu.run_opcode(0x8013, Some(false)).unwrap();
     assert_eq!(cpu.v[0], 0b0110);
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
 }
@@ -233,7 +250,7 @@ fn test_op_8xy4() {
     // Test with overflow
     cpu.v[0] = 0xF0;
     cpu.v[1] = 0xF0;
-    cpu.run_opcode(0x8014, Some(false));
+    cpu.run_opcode(0x8014, Some(false)).unwrap();
     assert_eq!(cpu.v[0], 0xE0);
     assert_eq!(cpu.v[0xF], 1);
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
@@ -242,7 +259,7 @@ fn test_op_8xy4() {
     pc = cpu.pc;
     cpu.v[2] = 0x05;
     cpu.v[3] = 0x02;
-    cpu.run_opcode(0x8234, Some(false));
+    cpu.run_opcode(0x8234, Some(false)).unwrap();
     assert_eq!(cpu.v[2], 0x07);
     assert_eq!(cpu.v[0xF], 0);
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
@@ -257,7 +274,7 @@ fn test_op_8xy5() {
     // Test with overflow
     cpu.v[0] = 0x08;
     cpu.v[1] = 0x0A;
-    cpu.run_opcode(0x8015, Some(false));
+    cpu.run_opcode(0x8015, Some(false)).unwrap();
     assert_eq!(cpu.v[0], 0xFE);
     assert_eq!(cpu.v[0xF], 0);
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
@@ -266,7 +283,7 @@ fn test_op_8xy5() {
     pc = cpu.pc;
     cpu.v[2] = 0x05;
     cpu.v[3] = 0x02;
-    cpu.run_opcode(0x8235, Some(false));
+    cpu.run_opcode(0x8235, Some(false)).unwrap();
     assert_eq!(cpu.v[2], 0x03);
     assert_eq!(cpu.v[0xF], 1);
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
@@ -278,14 +295,14 @@ fn test_op_8x06() {
 
     let mut pc = cpu.pc;
     cpu.v[0] = 4;
-    cpu.run_opcode(0x8006, Some(false)); // cpu.v[0] should = 2; with v[f] = 0;
+    cpu.run_opcode(0x8006, Some(false)).unwrap(); // cpu.v[0] should = 2; with v[f] = 0;
     assert_eq!(cpu.v[0], 2);
     assert_eq!(cpu.v[0xF], 0);
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
 
     pc = cpu.pc;
     cpu.v[4] = 5;
-    cpu.run_opcode(0x8406, Some(false)); // cpu.v[4] should = 2; with v[f] = 1;
+    cpu.run_opcode(0x8406, Some(false)).unwrap(); // cpu.v[4] should = 2; with v[f] = 1;
     assert_eq!(cpu.v[4], 2);
     assert_eq!(cpu.v[0xF], 1);
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
@@ -299,7 +316,7 @@ fn test_op_8xy7() {
     let mut pc = cpu.pc;
     cpu.v[0] = 0x05;
     cpu.v[1] = 0x06;
-    cpu.run_opcode(0x8017, Some(false));
+    cpu.run_opcode(0x8017, Some(false)).unwrap();
 
     assert_eq!(cpu.v[0x0F], 1);
     assert_eq!(cpu.v[0], 0x01);
@@ -309,7 +326,7 @@ fn test_op_8xy7() {
     pc = cpu.pc;
     cpu.v[0] = 0x08;
     cpu.v[1] = 0x03;
-    cpu.run_opcode(0x8017, Some(false));
+    cpu.run_opcode(0x8017, Some(false)).unwrap();
 
     assert_eq!(cpu.v[0x0F], 0);
     assert_eq!(cpu.v[0], 251);
@@ -322,7 +339,7 @@ fn test_op_8x0e() {
 
     let mut pc = cpu.pc;
     cpu.v[0] = 0x04;
-    cpu.run_opcode(0x800E, Some(false));
+    cpu.run_opcode(0x800E, Some(false)).unwrap();
 
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
     assert_eq!(cpu.v[0x0F], 0);
@@ -330,7 +347,7 @@ fn test_op_8x0e() {
 
     pc = cpu.pc;
     cpu.v[1] = 0x82; // 0b10000010
-    cpu.run_opcode(0x810E, Some(false));
+    cpu.run_opcode(0x810E, Some(false)).unwrap();
 
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
     assert_eq!(cpu.v[0x0F], 1);
@@ -344,14 +361,14 @@ fn test_op_9xy0() {
     let mut pc = cpu.pc;
     cpu.v[0] = 0x04;
     cpu.v[1] = 0x04;
-    cpu.run_opcode(0x9010, Some(false));
+    cpu.run_opcode(0x9010, Some(false)).unwrap();
 
     assert_eq!(cpu.pc, pc + OPCODE_SIZE); // Should not skip
 
     pc = cpu.pc;
     cpu.v[0] = 0x04;
     cpu.v[1] = 0x01;
-    cpu.run_opcode(0x9010, Some(false));
+    cpu.run_opcode(0x9010, Some(false)).unwrap();
 
     assert_eq!(cpu.pc, pc + (OPCODE_SIZE * 2)); // Should skip
 }
@@ -361,7 +378,7 @@ fn test_op_annn() {
     let mut cpu = Cpu::new();
 
     let pc = cpu.pc;
-    cpu.run_opcode(0xA0FF, Some(false)); // Should load 123 into register i
+    cpu.run_opcode(0xA0FF, Some(false)).unwrap(); // Should load 123 into register i
 
     assert_eq!(cpu.i, 255 as usize);
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
@@ -372,24 +389,60 @@ fn test_op_bnnn() {
     let mut cpu = Cpu::new();
 
     cpu.v[0] = 1;
-    cpu.run_opcode(0xB0CA, Some(false)); // Should jump to 0x0CA + v[0]
+    cpu.run_opcode(0xB0CA, Some(false)).unwrap(); // Should jump to 0x0CA + v[0]
     assert_eq!(cpu.pc, 0x0CB);
 }
 
 #[test]
 fn test_op_cxkk() {
     let mut cpu = Cpu::new();
+    cpu.set_rng(Box::new(FixedRandom(0b1010_1010)));
     let pc = cpu.pc;
-    cpu.run_opcode(0xC001, Some(false)); // set v[0] to random + 01
+    cpu.run_opcode(0xC00F, Some(false)).unwrap(); // set v[0] to random AND 0x0F
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
+    assert_eq!(cpu.v[0], 0b0000_1010);
 
-    // TODO: Figure out a way to test the RNG
+    cpu.run_opcode(0xC100, Some(false)).unwrap(); // AND with 0 is always 0
+    assert_eq!(cpu.v[1], 0);
+}
+
+#[test]
+fn test_op_cxkk_seeded() {
+    // The same seed gives the same sequence
+    let mut a = Cpu::new();
+    let mut b = Cpu::new();
+    a.seed_rng(1234);
+    b.seed_rng(1234);
+    for _ in 0..32 {
+        a.run_opcode(0xC0FF, Some(false)).unwrap();
+        b.run_opcode(0xC0FF, Some(false)).unwrap();
+        assert_eq!(a.v[0], b.v[0]);
+    }
 }
 
 #[test]
 fn test_op_dxyn() {
-    // TODO: Write test for display and overlap check v[F]
-    assert_eq!(1, 1);
+    let mut cpu = Cpu::new();
+    cpu.i = 0x300;
+    cpu.memory[0x300] = 0b1100_0000;
+    cpu.memory[0x301] = 0b0100_0000;
+    cpu.v[0] = 2;
+    cpu.v[1] = 3;
+
+    // Draw a 2 row sprite at (2, 3)
+    cpu.run_opcode(0xD012, Some(false)).unwrap();
+    assert_eq!(cpu.gfx[3][2], 1);
+    assert_eq!(cpu.gfx[3][3], 1);
+    assert_eq!(cpu.gfx[4][2], 0);
+    assert_eq!(cpu.gfx[4][3], 1);
+    assert_eq!(cpu.v[0xF], 0);
+    assert!(cpu.gfx_updated);
+
+    // Drawing it again erases it and reports the collision
+    cpu.run_opcode(0xD012, Some(false)).unwrap();
+    assert_eq!(cpu.gfx[3][2], 0);
+    assert_eq!(cpu.gfx[4][3], 0);
+    assert_eq!(cpu.v[0xF], 1);
 }
 
 #[test]
@@ -400,14 +453,14 @@ fn test_op_ex9e() {
     let mut pc = cpu.pc;
     cpu.input.keys[0x00] = true;
     cpu.v[0] = 0x00;
-    cpu.run_opcode(0xE09E, Some(false)); // Check for press at key v[0] (0)
+    cpu.run_opcode(0xE09E, Some(false)).unwrap(); // Check for press at key v[0] (0)
     assert_eq!(cpu.pc, pc + (OPCODE_SIZE * 2));
 
     // Should not skip
     pc = cpu.pc;
     cpu.v[0] = 0x01;
     cpu.input.keys[0x01] = false;
-    cpu.run_opcode(0xE09E, Some(false)); // Look for press at key v[0] (1)
+    cpu.run_opcode(0xE09E, Some(false)).unwrap(); // Look for press at key v[0] (1)
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
 }
 
@@ -419,14 +472,14 @@ fn test_op_exa1() {
     let mut pc = cpu.pc;
     cpu.input.keys[0x00] = false;
     cpu.v[0] = 0x00; // Check for key 0
-    cpu.run_opcode(0xE0A1, Some(false));
+    cpu.run_opcode(0xE0A1, Some(false)).unwrap();
     assert_eq!(cpu.pc, pc + (OPCODE_SIZE * 2));
 
     // Lets press a key and test it does not skip
     pc = cpu.pc;
     cpu.input.keys[0x01] = true;
     cpu.v[0] = 0x01; // Check for key 1
-    cpu.run_opcode(0xE0A1, Some(false));
+    cpu.run_opcode(0xE0A1, Some(false)).unwrap();
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
 }
 
@@ -435,7 +488,7 @@ fn test_op_fx07() {
     let mut cpu = Cpu::new();
     let pc = cpu.pc;
     cpu.delay_timer = 123;
-    cpu.run_opcode(0xFA07, Some(false));
+    cpu.run_opcode(0xFA07, Some(false)).unwrap();
     assert_eq!(cpu.pc, pc + OPCODE_SIZE);
     assert_eq!(cpu.v[0xA], 123);
 }
@@ -444,15 +497,487 @@ fn test_op_fx07() {
 fn test_op_fx0a() {
     let mut cpu = Cpu::new();
     let pc = cpu.pc;
+    cpu.memory[pc] = 0xF1;
+    cpu.memory[pc + 1] = 0x0A;
+    cpu.delay_timer = 10;
 
-    // Setup our fake keypress as key 2
-    cpu.input.keys[2] = true;
+    // Starts waiting, and the pc stays put while the timers keep counting
+    cpu.run_frame(10).unwrap();
+    assert_eq!(cpu.waiting_for_key(), Some(0x01));
+    assert_eq!(cpu.pc, pc);
+    assert_eq!(cpu.delay_timer, 9);
 
-    // Run this opcode and check v[x] for the key 1 after a tick()
-    cpu.run_opcode(0xF10A, Some(false));
-    cpu.tick(false);
-    //assert_eq!(cpu.input.read_keys, true);
-    assert_eq!(cpu.input.key_target, 0x01);
-    assert_eq!(cpu.pc, pc + (OPCODE_SIZE * 2)); // Because tick() will run an opcode again
+    // Press key 2, still waiting on its release
+    cpu.input.keys[2] = true;
+    cpu.tick(false).unwrap();
+    cpu.tick(false).unwrap();
+    assert_eq!(cpu.pc, pc);
+    assert_eq!(cpu.waiting_for_key(), Some(0x01));
+
+    // Release it and we move on
+    cpu.input.keys[2] = false;
+    cpu.tick(false).unwrap();
+    assert_eq!(cpu.pc, pc + OPCODE_SIZE);
     assert_eq!(cpu.v[1], 2); // Key 2 (the pressed one) was stored in v[1]
+    assert_eq!(cpu.waiting_for_key(), None);
+}
+
+#[test]
+fn test_op_fx0a_held_key() {
+    // A key already held when the wait starts has to be released and pressed again
+    let mut cpu = Cpu::new();
+    let pc = cpu.pc;
+    cpu.input.keys[5] = true;
+    cpu.run_opcode(0xF30A, Some(false)).unwrap();
+    cpu.run_opcode(0xF30A, Some(false)).unwrap();
+    cpu.input.keys[5] = false;
+    cpu.run_opcode(0xF30A, Some(false)).unwrap();
+    assert_eq!(cpu.pc, pc);
+
+    cpu.input.keys[5] = true;
+    cpu.run_opcode(0xF30A, Some(false)).unwrap();
+    cpu.input.keys[5] = false;
+    cpu.run_opcode(0xF30A, Some(false)).unwrap();
+    assert_eq!(cpu.pc, pc + OPCODE_SIZE);
+    assert_eq!(cpu.v[3], 5);
+}
+
+#[test]
+fn test_stack_underflow() {
+    let mut cpu = Cpu::new();
+    let result = cpu.run_opcode(0x00EE, Some(false));
+    assert_eq!(
+        result,
+        Err(CpuError::StackUnderflow {
+            pc: 0x200,
+            opcode: 0x00EE
+        })
+    );
+    assert_eq!(cpu.pc, 0x200); // Nothing moved
+}
+
+#[test]
+fn test_stack_overflow() {
+    let mut cpu = Cpu::new();
+    for _ in 0..16 {
+        cpu.run_opcode(0x2200, Some(false)).unwrap();
+    }
+    let result = cpu.run_opcode(0x2200, Some(false));
+    assert_eq!(
+        result,
+        Err(CpuError::StackOverflow {
+            pc: 0x200,
+            opcode: 0x2200
+        })
+    );
+}
+
+#[test]
+fn test_memory_out_of_bounds() {
+    let mut cpu = Cpu::new();
+    cpu.i = 0xFFE;
+    let result = cpu.run_opcode(0xF255, Some(false)); // Writes 0xFFE..=0x1000
+    assert_eq!(
+        result,
+        Err(CpuError::MemoryOutOfBounds {
+            pc: 0x200,
+            opcode: 0xF255,
+            address: 0x1000
+        })
+    );
+
+    cpu.i = 0xFFF;
+    assert!(cpu.run_opcode(0xF033, Some(false)).is_err());
+    assert!(cpu.run_opcode(0xD012, Some(false)).is_err());
+    assert!(cpu.run_opcode(0xD011, Some(false)).is_ok());
+}
+
+#[test]
+fn test_invalid_key() {
+    let mut cpu = Cpu::new();
+    cpu.v[3] = 0x10;
+    let result = cpu.run_opcode(0xE39E, Some(false));
+    assert_eq!(
+        result,
+        Err(CpuError::InvalidKey {
+            pc: 0x200,
+            opcode: 0xE39E,
+            key: 0x10
+        })
+    );
+    assert!(cpu.run_opcode(0xE3A1, Some(false)).is_err());
+}
+
+#[test]
+fn test_pc_out_of_range() {
+    let mut cpu = Cpu::new();
+    cpu.run_opcode(0x1FFF, Some(false)).unwrap();
+    let result = cpu.tick(false);
+    assert_eq!(
+        result,
+        Err(CpuError::PcOutOfRange {
+            pc: 0xFFF,
+            opcode: 0x1FFF
+        })
+    );
+}
+
+#[test]
+fn test_quirk_shift() {
+    // Without the shift quirk Vy is shifted into Vx
+    let mut cpu = Cpu::with_quirks(Quirks::cosmac_vip());
+    cpu.v[1] = 0x05;
+    cpu.run_opcode(0x8016, Some(false)).unwrap();
+    assert_eq!(cpu.v[0], 0x02);
+    assert_eq!(cpu.v[0xF], 1);
+
+    cpu.v[1] = 0x81;
+    cpu.run_opcode(0x801E, Some(false)).unwrap();
+    assert_eq!(cpu.v[0], 0x02);
+    assert_eq!(cpu.v[0xF], 1);
+}
+
+#[test]
+fn test_quirk_load_store_increment() {
+    let mut cpu = Cpu::with_quirks(Quirks::cosmac_vip());
+    cpu.i = 0x300;
+    cpu.run_opcode(0xF255, Some(false)).unwrap();
+    assert_eq!(cpu.i, 0x303);
+    cpu.run_opcode(0xF165, Some(false)).unwrap();
+    assert_eq!(cpu.i, 0x305);
+
+    // CHIP-48 stops one short
+    let mut cpu = Cpu::with_quirks(Quirks::chip48());
+    cpu.i = 0x300;
+    cpu.run_opcode(0xF255, Some(false)).unwrap();
+    assert_eq!(cpu.i, 0x302);
+    cpu.run_opcode(0xF165, Some(false)).unwrap();
+    assert_eq!(cpu.i, 0x303);
+
+    // And SUPER-CHIP leaves it alone, as does the default
+    for quirks in &[Quirks::superchip(), Quirks::default()] {
+        let mut cpu = Cpu::with_quirks(*quirks);
+        cpu.i = 0x300;
+        cpu.run_opcode(0xF255, Some(false)).unwrap();
+        assert_eq!(cpu.i, 0x300);
+    }
+}
+
+#[test]
+fn test_quirk_jump_with_vx() {
+    let mut cpu = Cpu::with_quirks(Quirks::superchip());
+    cpu.v[0] = 0x01;
+    cpu.v[2] = 0x04;
+    cpu.run_opcode(0xB220, Some(false)).unwrap(); // Jump to 0x220 + v[2]
+    assert_eq!(cpu.pc, 0x224);
+}
+
+#[test]
+fn test_quirk_vf_reset() {
+    let mut cpu = Cpu::with_quirks(Quirks::cosmac_vip());
+    cpu.v[0xF] = 1;
+    cpu.run_opcode(0x8011, Some(false)).unwrap();
+    assert_eq!(cpu.v[0xF], 0);
+
+    let mut cpu = Cpu::new();
+    cpu.v[0xF] = 1;
+    cpu.run_opcode(0x8011, Some(false)).unwrap();
+    assert_eq!(cpu.v[0xF], 1);
+}
+
+#[test]
+fn test_quirk_clip() {
+    // A single pixel sprite row drawn at the right edge
+    let mut cpu = Cpu::with_quirks(Quirks::superchip());
+    cpu.i = 0x300;
+    cpu.memory[0x300] = 0xFF;
+    cpu.v[0] = 60;
+    cpu.run_opcode(0xD011, Some(false)).unwrap();
+    assert_eq!(cpu.gfx[0][63], 1);
+    assert_eq!(cpu.gfx[0][0], 0); // Clipped
+
+    let mut cpu = Cpu::new();
+    cpu.i = 0x300;
+    cpu.memory[0x300] = 0xFF;
+    cpu.v[0] = 60;
+    cpu.run_opcode(0xD011, Some(false)).unwrap();
+    assert_eq!(cpu.gfx[0][63], 1);
+    assert_eq!(cpu.gfx[0][0], 1); // Wrapped
+}
+
+#[test]
+fn test_quirk_display_wait() {
+    let mut cpu = Cpu::with_quirks(Quirks::cosmac_vip());
+    let pc = cpu.pc;
+
+    // Waits for the vblank, the pc stays put
+    cpu.run_opcode(0xD001, Some(false)).unwrap();
+    assert_eq!(cpu.pc, pc);
+
+    cpu.signal_vblank();
+    cpu.run_opcode(0xD001, Some(false)).unwrap();
+    assert_eq!(cpu.pc, pc + OPCODE_SIZE);
+}
+
+#[test]
+fn test_quirks_from_str() {
+    assert_eq!("vip".parse::<Quirks>(), Ok(Quirks::cosmac_vip()));
+    assert_eq!("SCHIP".parse::<Quirks>(), Ok(Quirks::superchip()));
+    assert_eq!("xo-chip".parse::<Quirks>(), Ok(Quirks::xochip()));
+    assert!("nope".parse::<Quirks>().is_err());
+}
+
+#[test]
+fn test_platforms() {
+    let platform: Platform = "xo-chip".parse().unwrap();
+    assert_eq!(platform.quirks(), Quirks::xochip());
+    assert_eq!(platform.memory_size(), XO_MEMORY_SIZE);
+    assert_eq!(Platform::Default.memory_size(), MEMORY_SIZE);
+    assert_eq!(Platform::SuperChip.memory_size(), MEMORY_SIZE);
+    assert!("nope".parse::<Platform>().is_err());
+}
+
+#[test]
+fn test_quirk_presets_differ() {
+    assert_ne!(Quirks::chip48(), Quirks::superchip());
+    assert_eq!(Quirks::cosmac_vip().load_store, LoadStore::AddXPlusOne);
+    assert_eq!(Quirks::chip48().load_store, LoadStore::AddX);
+    assert_eq!(Quirks::superchip().load_store, LoadStore::Unchanged);
+    assert_eq!(Quirks::xochip().load_store, LoadStore::AddXPlusOne);
+}
+
+#[test]
+fn test_timers_tick_per_frame() {
+    let mut cpu = Cpu::new();
+    // A tight loop: 0x200 jumps to itself
+    cpu.memory[0x200] = 0x12;
+    cpu.memory[0x201] = 0x00;
+    cpu.delay_timer = 5;
+    cpu.sound_timer = 1;
+
+    // Instructions alone do not touch the timers
+    for _ in 0..100 {
+        cpu.tick(false).unwrap();
+    }
+    assert_eq!(cpu.delay_timer, 5);
+    assert_eq!(cpu.sound_timer, 1);
+
+    // Each frame counts them down once, no matter how many cycles it runs
+    cpu.run_frame(500).unwrap();
+    assert_eq!(cpu.delay_timer, 4);
+    assert_eq!(cpu.sound_timer, 0);
+
+    cpu.run_frame(1).unwrap();
+    cpu.run_frame(1).unwrap();
+    assert_eq!(cpu.delay_timer, 2);
+    assert_eq!(cpu.sound_timer, 0); // And stop at zero
+}
+
+#[test]
+fn test_op_00fe_00ff() {
+    let mut cpu = Cpu::new();
+    assert_eq!((cpu.width(), cpu.height()), (64, 32));
+    cpu.gfx[0][0] = 1;
+
+    cpu.run_opcode(0x00FF, Some(false)).unwrap();
+    assert!(cpu.hires);
+    assert_eq!((cpu.width(), cpu.height()), (128, 64));
+    assert_eq!(cpu.gfx.len(), 64);
+    assert_eq!(cpu.gfx[0].len(), 128);
+    assert_eq!(cpu.gfx[0][0], 0); // Switching clears the display
+
+    cpu.run_opcode(0x00FE, Some(false)).unwrap();
+    assert!(!cpu.hires);
+    assert_eq!(cpu.gfx.len(), 32);
+    assert_eq!(cpu.gfx[0].len(), 64);
+}
+
+#[test]
+fn test_op_00cn() {
+    let mut cpu = Cpu::new();
+    cpu.gfx[0][5] = 1;
+    cpu.gfx[31][5] = 1;
+    cpu.run_opcode(0x00C3, Some(false)).unwrap();
+    assert_eq!(cpu.gfx[0][5], 0);
+    assert_eq!(cpu.gfx[3][5], 1);
+    assert_eq!(cpu.gfx[31][5], 0); // Scrolled off the bottom
+}
+
+#[test]
+fn test_op_00fb_00fc() {
+    let mut cpu = Cpu::new();
+    cpu.run_opcode(0x00FF, Some(false)).unwrap();
+    cpu.gfx[1][0] = 1;
+    cpu.gfx[1][127] = 1;
+
+    cpu.run_opcode(0x00FB, Some(false)).unwrap(); // Right
+    assert_eq!(cpu.gfx[1][0], 0);
+    assert_eq!(cpu.gfx[1][4], 1);
+    assert_eq!(cpu.gfx[1][127], 0);
+
+    cpu.run_opcode(0x00FC, Some(false)).unwrap(); // Left
+    assert_eq!(cpu.gfx[1][0], 1);
+    assert_eq!(cpu.gfx[1][4], 0);
+}
+
+#[test]
+fn test_op_00fd() {
+    let mut cpu = Cpu::new();
+    cpu.memory[0x200] = 0x00;
+    cpu.memory[0x201] = 0xFD;
+    cpu.tick(false).unwrap();
+    assert!(cpu.exited);
+    assert_eq!(cpu.pc, 0x200);
+
+    // Nothing runs after an exit
+    cpu.memory[0x201] = 0xE0;
+    cpu.gfx[0][0] = 1;
+    cpu.tick(false).unwrap();
+    assert_eq!(cpu.gfx[0][0], 1);
+}
+
+#[test]
+fn test_op_dxy0() {
+    let mut cpu = Cpu::new();
+    cpu.run_opcode(0x00FF, Some(false)).unwrap();
+    cpu.i = 0x300;
+    for b in 0..32 {
+        cpu.memory[0x300 + b] = 0xFF;
+    }
+    cpu.v[0] = 100;
+    cpu.v[1] = 10;
+
+    // A solid 16x16 block
+    cpu.run_opcode(0xD010, Some(false)).unwrap();
+    assert_eq!(cpu.gfx[10][100], 1);
+    assert_eq!(cpu.gfx[25][115], 1);
+    assert_eq!(cpu.gfx[26][115], 0);
+    assert_eq!(cpu.gfx[25][116], 0);
+    assert_eq!(cpu.v[0xF], 0);
+
+    // In hires VF counts the rows that collided
+    cpu.v[1] = 20;
+    cpu.run_opcode(0xD010, Some(false)).unwrap();
+    assert_eq!(cpu.v[0xF], 6);
+}
+
+#[test]
+fn test_op_dxy0_clipped_rows() {
+    // Rows clipped off the bottom count as collisions too
+    let mut cpu = Cpu::with_quirks(Quirks::superchip());
+    cpu.run_opcode(0x00FF, Some(false)).unwrap();
+    cpu.i = 0x300;
+    cpu.v[1] = 60;
+    cpu.run_opcode(0xD010, Some(false)).unwrap();
+    assert_eq!(cpu.v[0xF], 12);
+}
+
+#[test]
+fn test_op_fx30() {
+    let mut cpu = Cpu::new();
+    cpu.v[2] = 3;
+    cpu.run_opcode(0xF230, Some(false)).unwrap();
+    assert_eq!(cpu.i, 0x50 + 30);
+    assert_eq!(cpu.memory[cpu.i..cpu.i + 2], [0xFF, 0xFF]);
+}
+
+#[test]
+fn test_op_fx75_fx85() {
+    let mut cpu = Cpu::new();
+    cpu.v[0] = 1;
+    cpu.v[1] = 2;
+    cpu.v[2] = 3;
+    cpu.run_opcode(0xF175, Some(false)).unwrap(); // Save V0, V1
+    assert_eq!(cpu.rpl[..3], [1, 2, 0]);
+
+    cpu.v = [0; 16];
+    cpu.run_opcode(0xF285, Some(false)).unwrap(); // Load V0 - V2
+    assert_eq!(cpu.v[..3], [1, 2, 0]);
+}
+
+#[test]
+fn test_op_f000() {
+    let mut cpu = Cpu::new();
+    cpu.set_memory_size(XO_MEMORY_SIZE);
+    cpu.memory[0x200..0x204].copy_from_slice(&[0xF0, 0x00, 0xBE, 0xEF]);
+    cpu.tick(false).unwrap();
+    assert_eq!(cpu.i, 0xBEEF);
+    assert_eq!(cpu.pc, 0x204);
+}
+
+#[test]
+fn test_skip_over_f000() {
+    // A skip jumps the whole four byte instruction
+    let mut cpu = Cpu::new();
+    cpu.memory[0x202..0x206].copy_from_slice(&[0xF0, 0x00, 0x12, 0x34]);
+    cpu.run_opcode(0x3000, Some(false)).unwrap(); // V0 == 0, skip
+    assert_eq!(cpu.pc, 0x206);
+}
+
+#[test]
+fn test_op_5xy2_5xy3() {
+    let mut cpu = Cpu::new();
+    cpu.i = 0x300;
+    cpu.v[2] = 2;
+    cpu.v[3] = 3;
+    cpu.v[4] = 4;
+    cpu.run_opcode(0x5242, Some(false)).unwrap();
+    assert_eq!(cpu.memory[0x300..0x303], [2, 3, 4]);
+    assert_eq!(cpu.i, 0x300);
+
+    // Reversed ranges load backwards
+    cpu.run_opcode(0x5A83, Some(false)).unwrap();
+    assert_eq!(cpu.v[0x8..0xB], [4, 3, 2]);
+
+    cpu.i = 0xFFF;
+    assert!(cpu.run_opcode(0x5012, Some(false)).is_err());
+}
+
+#[test]
+fn test_op_fn01_planes() {
+    let mut cpu = Cpu::with_quirks(Quirks::xochip());
+    cpu.i = 0x300;
+    cpu.memory[0x300] = 0x80; // Plane 1 sprite
+    cpu.memory[0x301] = 0xC0; // Plane 2 sprite
+    cpu.run_opcode(0xF301, Some(false)).unwrap(); // Both planes
+    cpu.run_opcode(0xD001, Some(false)).unwrap();
+    assert_eq!(cpu.gfx[0][..2], [3, 2]);
+    assert_eq!(cpu.v[0xF], 0);
+
+    // Only plane 2 collides and is cleared
+    cpu.run_opcode(0xF201, Some(false)).unwrap();
+    cpu.i = 0x301;
+    cpu.run_opcode(0xD001, Some(false)).unwrap();
+    assert_eq!(cpu.gfx[0][..2], [1, 0]);
+    assert_eq!(cpu.v[0xF], 1);
+
+    cpu.gfx[0][1] = 2;
+    cpu.run_opcode(0xF101, Some(false)).unwrap();
+    cpu.run_opcode(0x00E0, Some(false)).unwrap();
+    assert_eq!(cpu.gfx[0][..2], [0, 2]);
+}
+
+#[test]
+fn test_op_00dn_planes() {
+    let mut cpu = Cpu::with_quirks(Quirks::xochip());
+    cpu.gfx[5][0] = 3;
+    cpu.run_opcode(0xF101, Some(false)).unwrap();
+    cpu.run_opcode(0x00D2, Some(false)).unwrap(); // Scroll plane 1 up 2
+    assert_eq!(cpu.gfx[3][0], 1);
+    assert_eq!(cpu.gfx[5][0], 2);
+}
+
+#[test]
+fn test_op_f002_fx3a() {
+    let mut cpu = Cpu::new();
+    cpu.i = 0x300;
+    for b in 0..16 {
+        cpu.memory[0x300 + b] = b as u8;
+    }
+    cpu.run_opcode(0xF002, Some(false)).unwrap();
+    assert_eq!(cpu.audio_pattern.unwrap()[15], 15);
+    cpu.v[1] = 100;
+    cpu.run_opcode(0xF13A, Some(false)).unwrap();
+    assert_eq!(cpu.pitch, 100);
+    assert!(cpu.audio_updated);
 }
@@ -0,0 +1,368 @@
+extern crate lib;
+use lib::{read_message, serve_dap, write_message, Json};
+use std::collections::VecDeque;
+use std::io::{self, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// Line numbers matter here
+const SOURCE: &str = ": main
+  v0 := 5
+  i := buffer
+  save v0
+  count
+  sprite v1 v2 1
+  exit
+: count
+  v1 += 1
+  bump
+  return
+: bump
+  v2 += 1
+  return
+: buffer
+  0x80
+";
+
+// One end of an in-memory pipe
+struct PipeReader {
+    receiver: Receiver<Vec<u8>>,
+    buffer: VecDeque<u8>,
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffer.is_empty() {
+            match self.receiver.recv_timeout(Duration::from_secs(5)) {
+                Ok(bytes) => self.buffer.extend(bytes),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+                Err(RecvTimeoutError::Timeout) => return Err(io::ErrorKind::TimedOut.into()),
+            }
+        }
+        self.buffer.read(buf)
+    }
+}
+
+struct PipeWriter(Sender<Vec<u8>>);
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn pipe() -> (PipeWriter, PipeReader) {
+    let (sender, receiver) = mpsc::channel();
+    let reader = PipeReader {
+        receiver,
+        buffer: VecDeque::new(),
+    };
+    (PipeWriter(sender), reader)
+}
+
+// A scripted client, talking to the server on another thread
+struct Client {
+    to_server: PipeWriter,
+    from_server: BufReader<PipeReader>,
+    server: JoinHandle<Result<(), String>>,
+    events: Vec<Json>, // Events that came in while waiting for a response
+    seq: usize,
+}
+
+impl Client {
+    fn new() -> Client {
+        let (to_server, server_input) = pipe();
+        let (server_output, from_server) = pipe();
+        Client {
+            to_server,
+            from_server: BufReader::new(from_server),
+            server: thread::spawn(move || serve_dap(server_input, server_output)),
+            events: Vec::new(),
+            seq: 0,
+        }
+    }
+
+    fn receive(&mut self) -> Json {
+        read_message(&mut self.from_server).unwrap().unwrap()
+    }
+
+    // Sends a request and waits for its response
+    fn request(&mut self, command: &str, arguments: &str) -> Json {
+        self.seq += 1;
+        let request = format!(
+            "{{\"seq\": {}, \"type\": \"request\", \"command\": \"{}\", \"arguments\": {}}}",
+            self.seq, command, arguments
+        );
+        write_message(&mut self.to_server, &Json::parse(&request).unwrap()).unwrap();
+        loop {
+            let message = self.receive();
+            if message.get("type").as_str() == Some("event") {
+                self.events.push(message);
+                continue;
+            }
+            assert_eq!(message.get("request_seq").as_usize(), Some(self.seq));
+            assert_eq!(message.get("command").as_str(), Some(command));
+            return message;
+        }
+    }
+
+    fn succeed(&mut self, command: &str, arguments: &str) -> Json {
+        let response = self.request(command, arguments);
+        assert_eq!(
+            response.get("success"),
+            &Json::Bool(true),
+            "{}",
+            response.get("message")
+        );
+        response.get("body").clone()
+    }
+
+    // Waits for an event
+    fn event(&mut self, name: &str) -> Json {
+        loop {
+            if let Some(at) = self
+                .events
+                .iter()
+                .position(|e| e.get("event").as_str() == Some(name))
+            {
+                return self.events.remove(at).get("body").clone();
+            }
+            let message = self.receive();
+            self.events.push(message);
+        }
+    }
+
+    fn stopped(&mut self) -> String {
+        let body = self.event("stopped");
+        body.get("reason").as_str().unwrap().to_string()
+    }
+
+    // (name, line, instructionPointerReference) of each stack frame
+    fn frames(&mut self) -> Vec<(String, usize, String)> {
+        let body = self.succeed("stackTrace", "{\"threadId\": 1}");
+        body.get("stackFrames")
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|frame| {
+                (
+                    frame.get("name").as_str().unwrap().to_string(),
+                    frame.get("line").as_usize().unwrap(),
+                    frame
+                        .get("instructionPointerReference")
+                        .as_str()
+                        .unwrap()
+                        .to_string(),
+                )
+            })
+            .collect()
+    }
+
+    fn register(&mut self, name: &str) -> String {
+        let body = self.succeed("variables", "{\"variablesReference\": 1}");
+        let variables = body.get("variables").as_array().unwrap();
+        let variable = variables
+            .iter()
+            .find(|v| v.get("name").as_str() == Some(name))
+            .unwrap();
+        variable.get("value").as_str().unwrap().to_string()
+    }
+}
+
+fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("r8-{}-{}", std::process::id(), name));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+fn frame(name: &str, line: usize, pc: &str) -> (String, usize, String) {
+    (name.to_string(), line, pc.to_string())
+}
+
+#[test]
+fn test_session() {
+    let source = temp_file("session.8o", SOURCE.as_bytes());
+    let path = Json::from(source.to_string_lossy().to_string());
+    let mut client = Client::new();
+
+    let capabilities = client.succeed("initialize", "{\"adapterID\": \"r8\"}");
+    assert_eq!(
+        capabilities.get("supportsInstructionBreakpoints"),
+        &Json::Bool(true)
+    );
+    client.event("initialized");
+    client.succeed(
+        "launch",
+        &format!("{{\"program\": {}, \"stopOnEntry\": true}}", path),
+    );
+
+    // The label line has no code, so the breakpoint moves down to the next
+    let body = client.succeed(
+        "setBreakpoints",
+        &format!(
+            "{{\"source\": {{\"path\": {}}}, \"breakpoints\": [{{\"line\": 12}}, {{\"line\": 40}}]}}",
+            path
+        ),
+    );
+    let breakpoints = body.get("breakpoints").as_array().unwrap();
+    assert_eq!(breakpoints[0].get("verified"), &Json::Bool(true));
+    assert_eq!(breakpoints[0].get("line").as_usize(), Some(13));
+    assert_eq!(
+        breakpoints[0].get("instructionReference").as_str(),
+        Some("0x212")
+    );
+    assert_eq!(breakpoints[1].get("verified"), &Json::Bool(false));
+
+    client.succeed("configurationDone", "{}");
+    assert_eq!(client.stopped(), "entry");
+    assert_eq!(client.frames(), vec![frame("main", 2, "0x200")]);
+
+    client.succeed("continue", "{\"threadId\": 1}");
+    assert_eq!(client.stopped(), "breakpoint");
+    assert_eq!(
+        client.frames(),
+        vec![
+            frame("bump", 13, "0x212"),
+            frame("count+2", 10, "0x20E"),
+            frame("main+6", 5, "0x206"),
+        ]
+    );
+    assert_eq!(client.register("V1"), "0x01");
+    assert_eq!(client.register("I"), "0x216");
+    client.succeed(
+        "setVariable",
+        "{\"variablesReference\": 1, \"name\": \"V2\", \"value\": \"0x10\"}",
+    );
+    assert_eq!(client.register("V2"), "0x10");
+
+    // The 5 that save v0 wrote over the sprite
+    let body = client.succeed(
+        "readMemory",
+        "{\"memoryReference\": \"0x216\", \"count\": 2}",
+    );
+    assert_eq!(body.get("data").as_str(), Some("BQA="));
+    assert_eq!(body.get("unreadableBytes").as_usize(), Some(0));
+
+    client.succeed("next", "{\"threadId\": 1}");
+    assert_eq!(client.stopped(), "step");
+    assert_eq!(client.register("V2"), "0x11");
+    client.succeed("stepOut", "{\"threadId\": 1}");
+    assert_eq!(client.stopped(), "step");
+    assert_eq!(client.frames()[0], frame("count+4", 11, "0x210"));
+
+    let body = client.succeed(
+        "disassemble",
+        "{\"memoryReference\": \"0x20C\", \"instructionCount\": 2}",
+    );
+    let instructions = body.get("instructions").as_array().unwrap();
+    assert_eq!(
+        instructions[1].get("instruction").as_str(),
+        Some("CALL bump")
+    );
+    assert_eq!(instructions[1].get("line").as_usize(), Some(10));
+
+    client.succeed(
+        "setInstructionBreakpoints",
+        "{\"breakpoints\": [{\"instructionReference\": \"0x208\"}]}",
+    );
+    client.succeed("continue", "{\"threadId\": 1}");
+    assert_eq!(client.stopped(), "instruction breakpoint");
+    client.succeed("continue", "{\"threadId\": 1}");
+    assert_eq!(client.event("exited").get("exitCode").as_usize(), Some(0));
+    client.event("terminated");
+
+    client.succeed("disconnect", "{}");
+    assert_eq!(client.server.join().unwrap(), Ok(()));
+    std::fs::remove_file(source).unwrap();
+}
+
+#[test]
+fn test_breakpoint_on_first_line() {
+    let source = temp_file("first-line.8o", SOURCE.as_bytes());
+    let path = Json::from(source.to_string_lossy().to_string());
+    let mut client = Client::new();
+    client.succeed("initialize", "{}");
+    client.event("initialized");
+    client.succeed(
+        "launch",
+        &format!("{{\"program\": {}, \"stopOnEntry\": false}}", path),
+    );
+    client.succeed(
+        "setBreakpoints",
+        &format!(
+            "{{\"source\": {{\"path\": {}}}, \"breakpoints\": [{{\"line\": 2}}]}}",
+            path
+        ),
+    );
+
+    // Stops before the first instruction runs, then carries on past it
+    client.succeed("configurationDone", "{}");
+    assert_eq!(client.stopped(), "breakpoint");
+    assert_eq!(client.frames(), vec![frame("main", 2, "0x200")]);
+    assert_eq!(client.register("V0"), "0x00");
+    client.succeed("continue", "{\"threadId\": 1}");
+    assert_eq!(client.event("exited").get("exitCode").as_usize(), Some(0));
+    client.event("terminated");
+
+    client.succeed("disconnect", "{}");
+    assert_eq!(client.server.join().unwrap(), Ok(()));
+    std::fs::remove_file(source).unwrap();
+}
+
+#[test]
+fn test_errors() {
+    let rom = temp_file("errors.ch8", &[0x00, 0xFD]);
+    let source = temp_file("errors.8o", SOURCE.as_bytes());
+    let mut client = Client::new();
+    client.succeed("initialize", "{}");
+
+    let response = client.request("stackTrace", "{\"threadId\": 1}");
+    assert_eq!(response.get("success"), &Json::Bool(false));
+    let response = client.request(
+        "launch",
+        &format!(
+            "{{\"program\": {}, \"source\": {}}}",
+            Json::from(rom.to_string_lossy().to_string()),
+            Json::from(source.to_string_lossy().to_string())
+        ),
+    );
+    assert!(response
+        .get("message")
+        .as_str()
+        .unwrap()
+        .contains("doesn't assemble to"));
+    let response = client.request("evaluate", "{\"expression\": \"v0\"}");
+    assert_eq!(response.get("success"), &Json::Bool(false));
+
+    // Running out of input ends the session
+    drop(client.to_server);
+    assert_eq!(client.server.join().unwrap(), Ok(()));
+    std::fs::remove_file(rom).unwrap();
+    std::fs::remove_file(source).unwrap();
+}
+
+#[test]
+fn test_json() {
+    let text = r#"{"a": [1, -2.5, true, null], "b": "q\"\\\n\u00e9\ud83d\ude00", "c": {}}"#;
+    let json = Json::parse(text).unwrap();
+    assert_eq!(json.get("a").as_array().unwrap()[1], Json::Number(-2.5));
+    assert_eq!(json.get("b").as_str(), Some("q\"\\\n\u{e9}\u{1F600}"));
+    assert_eq!(json.get("missing"), &Json::Null);
+    assert_eq!(
+        json.to_string(),
+        "{\"a\":[1,-2.5,true,null],\"b\":\"q\\\"\\\\\\n\u{e9}\u{1F600}\",\"c\":{}}"
+    );
+    assert_eq!(Json::parse(&json.to_string()), Ok(json));
+    for bad in ["", "{", "[1,]", "{\"a\" 1}", "\"\\ud800\"", "tru", "1 2"] {
+        assert!(Json::parse(bad).is_err(), "{}", bad);
+    }
+}
@@ -0,0 +1,198 @@
+extern crate lib;
+use lib::{assemble, Cpu, Debugger, Rewind, StopReason, Watchpoint};
+
+const PROGRAM: &str = "
+: main
+  v0 := 5
+  i := buffer
+  save v0
+  count
+  sprite v1 v2 1
+  exit
+: count
+  v1 += 1
+  bump
+  return
+: bump
+  v2 += 1
+  return
+: buffer
+  0x80
+";
+
+fn cpu() -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.load_rom_bytes(&assemble(PROGRAM).unwrap().rom).unwrap();
+    cpu
+}
+
+#[test]
+fn test_breakpoints() {
+    let mut cpu = cpu();
+    let mut debugger = Debugger::new();
+    assert!(debugger.toggle_breakpoint(0x20C));
+    assert_eq!(
+        debugger.run_frame(&mut cpu, 100),
+        Ok(Some(StopReason::Breakpoint(0x20C)))
+    );
+    assert_eq!(cpu.pc, 0x20C);
+    assert_eq!(cpu.v[1], 0);
+
+    // Carrying on runs the instruction at the breakpoint
+    assert_eq!(
+        debugger.run_frame(&mut cpu, 100),
+        Ok(Some(StopReason::Exited))
+    );
+    assert_eq!(debugger.stop, Some(StopReason::Exited));
+
+    assert!(!debugger.toggle_breakpoint(0x20C));
+    assert!(debugger.breakpoints.is_empty());
+}
+
+#[test]
+fn test_breakpoint_at_entry() {
+    let mut cpu = cpu();
+    let mut debugger = Debugger::new();
+    debugger.toggle_breakpoint(0x200);
+    // Stops before anything runs
+    assert_eq!(
+        debugger.run_frame(&mut cpu, 100),
+        Ok(Some(StopReason::Breakpoint(0x200)))
+    );
+    assert_eq!((cpu.pc, cpu.cycles, cpu.v[0]), (0x200, 0, 0));
+
+    // And carries on from there
+    assert_eq!(
+        debugger.run_frame(&mut cpu, 100),
+        Ok(Some(StopReason::Exited))
+    );
+    assert_eq!(cpu.v[0], 5);
+}
+
+#[test]
+fn test_memory_watchpoints() {
+    let mut cpu = cpu();
+    let mut debugger = Debugger::new();
+    debugger.toggle_watchpoint(Watchpoint::Write(0x216, 0x216));
+    debugger.toggle_watchpoint(Watchpoint::Read(0x214, 0x216));
+    assert_eq!(
+        debugger.run_frame(&mut cpu, 100),
+        Ok(Some(StopReason::Write {
+            pc: 0x204,
+            address: 0x216,
+            old: 0x80,
+            new: 5,
+        }))
+    );
+    assert_eq!(cpu.pc, 0x206);
+    assert_eq!(
+        debugger.run_frame(&mut cpu, 100),
+        Ok(Some(StopReason::Read {
+            pc: 0x208,
+            address: 0x216,
+        }))
+    );
+    assert_eq!(cpu.pc, 0x20A);
+}
+
+#[test]
+fn test_register_watchpoints() {
+    let mut cpu = cpu();
+    let mut debugger = Debugger::new();
+    debugger.toggle_watchpoint(Watchpoint::I);
+    debugger.toggle_watchpoint(Watchpoint::V(2));
+    assert_eq!(
+        debugger.run_frame(&mut cpu, 100),
+        Ok(Some(StopReason::Register {
+            pc: 0x202,
+            watch: Watchpoint::I,
+            old: 0,
+            new: 0x216,
+        }))
+    );
+    let stop = debugger.run_frame(&mut cpu, 100).unwrap().unwrap();
+    assert_eq!(
+        stop,
+        StopReason::Register {
+            pc: 0x212,
+            watch: Watchpoint::V(2),
+            old: 0,
+            new: 1,
+        }
+    );
+    assert_eq!(stop.to_string(), "V2 changed by 0x212: 0x00 -> 0x01");
+}
+
+#[test]
+fn test_stepping() {
+    let mut cpu = cpu();
+    let mut debugger = Debugger::new();
+    for _ in 0..3 {
+        assert_eq!(debugger.step(&mut cpu), Ok(StopReason::Step));
+    }
+
+    // Over the call to count, which calls bump in turn
+    assert_eq!(cpu.pc, 0x206);
+    assert!(debugger.step_over(&cpu));
+    assert_eq!(
+        debugger.run_frame(&mut cpu, 100),
+        Ok(Some(StopReason::StepOver(0x208)))
+    );
+    assert_eq!((cpu.v[1], cpu.v[2]), (1, 1));
+    assert!(!debugger.step_over(&cpu));
+
+    // Into count and bump, then out of both
+    let mut cpu = self::cpu();
+    debugger.toggle_breakpoint(0x212);
+    debugger.run_frame(&mut cpu, 100).unwrap();
+    assert_eq!(cpu.sp, 2);
+    assert!(debugger.step_out(&cpu));
+    assert_eq!(
+        debugger.run_frame(&mut cpu, 100),
+        Ok(Some(StopReason::StepOut(0x210)))
+    );
+    assert!(debugger.step_out(&cpu));
+    assert_eq!(
+        debugger.run_frame(&mut cpu, 100),
+        Ok(Some(StopReason::StepOut(0x208)))
+    );
+    assert!(!debugger.step_out(&cpu));
+
+    debugger.run_to(0x20A);
+    assert_eq!(
+        debugger.run_frame(&mut cpu, 100),
+        Ok(Some(StopReason::Cursor(0x20A)))
+    );
+}
+
+#[test]
+fn test_partial_frames_rewind() {
+    // A frame cut short by a breakpoint still steps back exactly
+    let mut cpu = cpu();
+    let mut rewind = Rewind::new(10, 1);
+    let mut debugger = Debugger::new();
+    debugger.toggle_breakpoint(0x20C);
+    debugger.before(&cpu);
+    let stopped = rewind.run_frame_until(&mut cpu, 100, |cpu| debugger.after(cpu));
+    assert_eq!(stopped, Ok(true));
+    assert_eq!((cpu.cycles, cpu.pc), (4, 0x20C));
+    assert!(rewind.step_back(&mut cpu));
+    assert_eq!((cpu.cycles, cpu.pc), (3, 0x206));
+}
+
+#[test]
+fn test_watchpoint_syntax() {
+    for (text, watch) in [
+        ("r:300", Watchpoint::Read(0x300, 0x300)),
+        ("w:0x300-30f", Watchpoint::Write(0x300, 0x30F)),
+        ("vA", Watchpoint::V(0xA)),
+        ("I", Watchpoint::I),
+    ] {
+        assert_eq!(text.parse::<Watchpoint>(), Ok(watch));
+        assert_eq!(watch.to_string().parse::<Watchpoint>(), Ok(watch));
+    }
+    assert_eq!(Watchpoint::Write(0x300, 0x30F).to_string(), "w:300-30F");
+    for bad in ["x:300", "r:", "w:310-300", "v10", "vg", "300"] {
+        assert!(bad.parse::<Watchpoint>().is_err(), "{}", bad);
+    }
+}
@@ -0,0 +1,130 @@
+extern crate lib;
+use lib::{analyze, assemble, disassemble, disassemble_analyzed, Instruction, Item};
+
+const PROGRAM: &str = "
+: main
+  clear
+  i := box
+  loop
+    sprite v0 v1 4
+    wait
+    if v0 == 60 then v0 := 0
+    v2 := random 3
+    jump0 table
+  again
+: wait
+  v3 := delay
+  if v3 != 0 then return
+  jump wait
+: table
+  v0 += 1
+  v0 += 2
+: box
+  0xF0 0x90 0x90 0xF0
+";
+
+#[test]
+fn test_labels() {
+    let rom = assemble(PROGRAM).unwrap().rom;
+    let disassembly = disassemble(&rom, 0x200);
+    let labels: Vec<(usize, &str)> = disassembly
+        .labels
+        .iter()
+        .map(|(address, label)| (*address, label.as_str()))
+        .collect();
+    assert_eq!(
+        labels,
+        vec![
+            (0x204, "label_204"),
+            (0x212, "sub_212"),
+            (0x21A, "table_21A"),
+            (0x21E, "sprite_21E"),
+        ]
+    );
+}
+
+#[test]
+fn test_sprites() {
+    let rom = assemble(PROGRAM).unwrap().rom;
+    let disassembly = disassemble(&rom, 0x200);
+    let sprites: Vec<usize> = disassembly
+        .lines
+        .iter()
+        .filter(|line| line.item == Item::Sprite)
+        .map(|line| line.address)
+        .collect();
+    assert_eq!(sprites, vec![0x21E, 0x21F, 0x220, 0x221]);
+
+    let text = disassembly.to_text();
+    assert!(text.contains("  0x202  A21E      LD I, sprite_21E\n"));
+    assert!(text.contains("sub_212:\n  0x212  F307      LD V3, DT\n"));
+    assert!(text.contains("  0x21F  90        #..#....\n"));
+}
+
+#[test]
+fn test_octo_round_trip() {
+    let rom = assemble(PROGRAM).unwrap().rom;
+    let octo = disassemble(&rom, 0x200).to_octo();
+    assert!(octo.contains("  if v0 == 0x3C then v0 := 0x00\n"));
+    assert!(octo.contains(": sprite_21E\n  0xF0 0x90 0x90 0xF0\n"));
+    assert_eq!(assemble(&octo).unwrap().rom, rom);
+}
+
+#[test]
+fn test_awkward_bytes() {
+    // A skip in front of bytes, bytes that don't decode, a jump into the
+    // middle of an instruction and a dangling odd byte
+    let rom = [
+        0x12, 0x07, 0x30, 0x01, 0xFF, 0xFF, 0x60, 0x00, 0xE0, 0x10, 0x00, 0xAB,
+    ];
+    let disassembly = disassemble(&rom, 0x200);
+    let items: Vec<(usize, Item)> = disassembly
+        .lines
+        .iter()
+        .map(|line| (line.address, line.item))
+        .collect();
+    assert_eq!(
+        items,
+        vec![
+            (0x200, Item::Code(Instruction::Jp(0x207))),
+            (0x202, Item::Code(Instruction::SeByte(0, 1))),
+            (0x204, Item::Data),
+            (0x206, Item::Data),
+            (0x207, Item::Code(Instruction::Cls)),
+            (0x209, Item::Code(Instruction::Jp(0))),
+            (0x20B, Item::Data),
+        ]
+    );
+    assert_eq!(assemble(&disassembly.to_octo()).unwrap().rom, rom.to_vec());
+}
+
+#[test]
+fn test_unknown_plane() {
+    // Octo has planes 0 - 3, so anything past that stays as bytes
+    let rom = [0xF2, 0x01, 0xF8, 0x01];
+    for disassembly in &[
+        disassemble(&rom, 0x200),
+        disassemble_analyzed(&rom, 0x200, &analyze(&rom, 0x200)),
+    ] {
+        let octo = disassembly.to_octo();
+        assert!(octo.contains("  plane 2\n"));
+        assert!(!octo.contains("plane 8"));
+        assert_eq!(assemble(&octo).unwrap().rom, rom.to_vec());
+    }
+}
+
+#[test]
+fn test_describe() {
+    let rom = assemble(PROGRAM).unwrap().rom;
+    let disassembly = disassemble(&rom, 0x200);
+    let mut memory = vec![0; 0x1000];
+    memory[0x200..0x200 + rom.len()].copy_from_slice(&rom);
+    assert_eq!(disassembly.describe(&memory, 0x202), "LD I, sprite_21E");
+    assert_eq!(disassembly.describe(&memory, 0x212), "sub_212: LD V3, DT");
+
+    // Live memory wins over what was disassembled
+    memory[0x200] = 0x22;
+    memory[0x201] = 0x12;
+    assert_eq!(disassembly.describe(&memory, 0x200), "CALL sub_212");
+    assert_eq!(disassembly.describe(&memory, 0xFFF), "???");
+}
@@ -0,0 +1,98 @@
+extern crate lib;
+use lib::{headless_report, parse_address, run_headless, to_ascii, to_pbm, to_png};
+use lib::{Cpu, CpuError, InputScript, Stop};
+
+fn cpu_with(rom: &[u8]) -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.load_rom_bytes(rom).unwrap();
+    cpu
+}
+
+#[test]
+fn test_input_script() {
+    let script = InputScript::parse("# start\n0 -\n30 5\n\n35 5a  # both\n40 -\n").unwrap();
+    assert_eq!(
+        script.changes,
+        vec![(0, 0), (30, 0x20), (35, 0x420), (40, 0)]
+    );
+    assert_eq!(script.keys_at(29), 0);
+    assert_eq!(script.keys_at(30), 0x20);
+    assert_eq!(script.keys_at(37), 0x420);
+    assert_eq!(script.keys_at(1000), 0);
+
+    assert!(InputScript::parse("x 5").is_err());
+    assert!(InputScript::parse("10 G").is_err());
+    assert!(InputScript::parse("10").is_err());
+    assert!(InputScript::parse("10 5\n5 6").is_err());
+}
+
+#[test]
+fn test_parse_address() {
+    assert_eq!(parse_address("0x2A4"), Ok(0x2A4));
+    assert_eq!(parse_address("200"), Ok(0x200));
+    assert!(parse_address("zz").is_err());
+}
+
+#[test]
+fn test_run_headless_until_pc() {
+    // Counts up in V0 then jumps back
+    let mut cpu = cpu_with(&[0x70, 0x01, 0x12, 0x00]);
+    let (stop, frames) = run_headless(&mut cpu, 10, 100, Some(0x202), &InputScript::default());
+    assert_eq!(stop, Stop::Pc(0x202));
+    assert_eq!(frames, 1);
+    assert_eq!(cpu.v[0], 1);
+
+    // The entry point is reached before anything runs
+    let mut cpu = cpu_with(&[0x70, 0x01, 0x12, 0x00]);
+    let (stop, frames) = run_headless(&mut cpu, 10, 100, Some(0x200), &InputScript::default());
+    assert_eq!((stop, frames), (Stop::Pc(0x200), 0));
+    assert_eq!(cpu.cycles, 0);
+}
+
+#[test]
+fn test_run_headless_frames_and_input() {
+    // V1 = 1 while key 3 is held
+    let mut cpu = cpu_with(&[
+        0x61, 0x00, 0x60, 0x03, 0xE0, 0x9E, 0x12, 0x00, 0x61, 0x01, 0x12, 0x08,
+    ]);
+    let script = InputScript::parse("5 3").unwrap();
+    let (stop, frames) = run_headless(&mut cpu, 10, 4, None, &script);
+    assert_eq!((stop, frames), (Stop::Frames, 4));
+    assert_eq!(cpu.v[1], 0);
+    let (stop, _) = run_headless(&mut cpu, 10, 6, None, &script);
+    assert_eq!(stop, Stop::Frames);
+    assert_eq!(cpu.v[1], 1);
+}
+
+#[test]
+fn test_run_headless_exit_and_fault() {
+    let mut cpu = cpu_with(&[0x00, 0xFD]);
+    let (stop, _) = run_headless(&mut cpu, 10, 10, None, &InputScript::default());
+    assert_eq!(stop, Stop::Exited);
+
+    let mut cpu = cpu_with(&[0x00, 0xEE]);
+    let (stop, _) = run_headless(&mut cpu, 10, 10, None, &InputScript::default());
+    assert_eq!(
+        stop,
+        Stop::Fault(CpuError::StackUnderflow {
+            pc: 0x200,
+            opcode: 0x00EE
+        })
+    );
+    let report = headless_report(&cpu, stop, 1, false);
+    assert!(report.contains("stop: fault: stack underflow"));
+    assert!(report.contains("PC:0x0200"));
+}
+
+#[test]
+fn test_screen_dumps() {
+    let gfx = vec![vec![0, 1, 2], vec![3, 0, 0]];
+    assert_eq!(to_ascii(&gfx), ".#+\n@..\n");
+    assert_eq!(to_pbm(&gfx), b"P1\n3 2\n0 1 1\n1 0 0\n".to_vec());
+
+    let png = to_png(&gfx, 2);
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(png[16..24], [0, 0, 0, 6, 0, 0, 0, 4]); // 6 x 4
+    assert_eq!(&png[png.len() - 12..], b"\0\0\0\0IEND\xAE\x42\x60\x82");
+}
@@ -0,0 +1,70 @@
+extern crate lib;
+use lib::{assemble, call_stack, disassemble, register_at, Cpu, Inspector};
+
+const SOURCE: &str = ": main
+  i := buffer
+  v0 := 200
+  count
+  exit
+: count
+  v1 += 1
+  bump
+  return
+: bump
+  v2 += 1
+  return
+: buffer
+  0x12 0x34
+";
+
+#[test]
+fn test_changes() {
+    let mut cpu = Cpu::new();
+    cpu.load_rom_bytes(&assemble(SOURCE).unwrap().rom).unwrap();
+    let mut inspector = Inspector::new(&cpu);
+
+    cpu.run_frame(2).unwrap();
+    inspector.observe(&cpu);
+    assert!(inspector.i_changed);
+    assert!(inspector.changed[0]);
+    assert_eq!(inspector.changed.iter().filter(|&&c| c).count(), 1);
+    assert_eq!(inspector.value(cpu.v[0]), "C8");
+    assert_eq!(inspector.i_bytes(&cpu), "12 34 00 00 00 00 00 00");
+
+    // Paused, the highlights stay put
+    inspector.observe(&cpu);
+    assert!(inspector.changed[0] && inspector.i_changed);
+
+    // Running on moves them
+    cpu.run_frame(2).unwrap();
+    inspector.observe(&cpu);
+    assert!(!inspector.i_changed);
+    assert!(!inspector.changed[0] && inspector.changed[1]);
+
+    inspector.hex = false;
+    assert_eq!(inspector.value(cpu.v[0]), "200");
+    assert_eq!(inspector.value(cpu.v[1]), "  1");
+    cpu.i = cpu.memory.len() - 2;
+    assert_eq!(inspector.i_bytes(&cpu), "  0   0");
+}
+
+#[test]
+fn test_call_stack() {
+    let rom = assemble(SOURCE).unwrap().rom;
+    let mut cpu = Cpu::new();
+    cpu.load_rom_bytes(&rom).unwrap();
+    let disassembly = disassemble(&rom, 0x200);
+    assert!(call_stack(&cpu, &disassembly).is_empty());
+
+    cpu.run_frame(5).unwrap();
+    assert_eq!(cpu.pc, 0x20E);
+    assert_eq!(
+        call_stack(&cpu, &disassembly),
+        vec!["0x20C sub_208+4".to_string(), "0x206".to_string()]
+    );
+
+    assert_eq!(register_at(3.0, 17.0), Some(0));
+    assert_eq!(register_at(64.0 * 7.0 + 1.0, 40.0), Some(15));
+    assert_eq!(register_at(3.0, 5.0), None);
+    assert_eq!(register_at(64.0 * 8.0, 20.0), None);
+}
@@ -0,0 +1,83 @@
+extern crate lib;
+use lib::Instruction;
+
+#[test]
+fn test_decode_encode_round_trip() {
+    // Every opcode that decodes should encode back to itself
+    for opcode in 0..=0xFFFF_u16 {
+        if let Some(instruction) = Instruction::decode(opcode) {
+            assert_eq!(instruction.encode(), opcode, "{:#06X}", opcode);
+        }
+    }
+}
+
+#[test]
+fn test_decode() {
+    assert_eq!(Instruction::decode(0x00E0), Some(Instruction::Cls));
+    assert_eq!(Instruction::decode(0x00EE), Some(Instruction::Ret));
+    assert_eq!(Instruction::decode(0x0123), Some(Instruction::Sys(0x123)));
+    assert_eq!(Instruction::decode(0x1201), Some(Instruction::Jp(0x201)));
+    assert_eq!(
+        Instruction::decode(0x61F0),
+        Some(Instruction::LdByte(1, 0xF0))
+    );
+    assert_eq!(
+        Instruction::decode(0x8AB6),
+        Some(Instruction::Shr(0xA, 0xB))
+    );
+    assert_eq!(Instruction::decode(0xD125), Some(Instruction::Drw(1, 2, 5)));
+    assert_eq!(Instruction::decode(0xF365), Some(Instruction::LdVxI(3)));
+}
+
+#[test]
+fn test_decode_superchip() {
+    assert_eq!(
+        Instruction::decode(0x00C4),
+        Some(Instruction::ScrollDown(4))
+    );
+    assert_eq!(Instruction::decode(0x00FB), Some(Instruction::ScrollRight));
+    assert_eq!(Instruction::decode(0x00FC), Some(Instruction::ScrollLeft));
+    assert_eq!(Instruction::decode(0x00FD), Some(Instruction::Exit));
+    assert_eq!(Instruction::decode(0x00FE), Some(Instruction::Low));
+    assert_eq!(Instruction::decode(0x00FF), Some(Instruction::High));
+    assert_eq!(Instruction::decode(0xD120), Some(Instruction::Drw(1, 2, 0)));
+    assert_eq!(Instruction::decode(0xF530), Some(Instruction::LdHfVx(5)));
+    assert_eq!(Instruction::decode(0xF775), Some(Instruction::LdRVx(7)));
+    assert_eq!(Instruction::decode(0xF785), Some(Instruction::LdVxR(7)));
+}
+
+#[test]
+fn test_decode_xochip() {
+    assert_eq!(Instruction::decode(0x00D3), Some(Instruction::ScrollUp(3)));
+    assert_eq!(
+        Instruction::decode(0x5122),
+        Some(Instruction::SaveRange(1, 2))
+    );
+    assert_eq!(
+        Instruction::decode(0x5213),
+        Some(Instruction::LoadRange(2, 1))
+    );
+    assert_eq!(Instruction::decode(0xF000), Some(Instruction::LdILong));
+    assert_eq!(Instruction::decode(0xF201), Some(Instruction::Plane(2)));
+    assert_eq!(Instruction::decode(0xF002), Some(Instruction::Audio));
+    assert_eq!(Instruction::decode(0xF43A), Some(Instruction::Pitch(4)));
+    assert_eq!(Instruction::LdILong.size(), 4);
+    assert_eq!(Instruction::Cls.size(), 2);
+}
+
+#[test]
+fn test_decode_unknown() {
+    assert_eq!(Instruction::decode(0x5001), None);
+    assert_eq!(Instruction::decode(0x8008), None);
+    assert_eq!(Instruction::decode(0x9001), None);
+    assert_eq!(Instruction::decode(0xE000), None);
+    assert_eq!(Instruction::decode(0xF0FF), None);
+}
+
+#[test]
+fn test_display() {
+    assert_eq!(Instruction::LdByte(1, 0x0F).to_string(), "LD V1, 0x0F");
+    assert_eq!(Instruction::Call(0x2A4).to_string(), "CALL 0x2A4");
+    assert_eq!(Instruction::Drw(0, 0xA, 5).to_string(), "DRW V0, VA, 5");
+    assert_eq!(Instruction::LdIVx(0xF).to_string(), "LD [I], VF");
+}
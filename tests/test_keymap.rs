@@ -0,0 +1,105 @@
+extern crate lib;
+use ggez::event::KeyCode;
+use lib::{Keymap, KeymapConfig, Layout, PhysicalKey, Rebinder};
+
+#[test]
+fn test_layouts() {
+    let qwerty = Keymap::new(Layout::Qwerty);
+    assert_eq!(qwerty.key(Some(KeyCode::X), 45), Some(0x0));
+    assert_eq!(qwerty.key(Some(KeyCode::Key4), 5), Some(0xC));
+    assert_eq!(qwerty.key(Some(KeyCode::V), 47), Some(0xF));
+    assert_eq!(qwerty.key(Some(KeyCode::Key5), 6), None);
+    assert_eq!(qwerty.bindings.len(), 16);
+
+    // By position, so the AZERTY A (where Q is on QWERTY) is 4
+    let scancode = Keymap::new(Layout::Scancode);
+    assert_eq!(scancode.key(Some(KeyCode::A), 16), Some(0x4));
+    assert_eq!(scancode.key(None, 16), Some(0x4));
+    assert_eq!(
+        scancode.physical(Some(KeyCode::A), 16),
+        PhysicalKey::Scancode(16)
+    );
+    assert_eq!(
+        qwerty.physical(Some(KeyCode::A), 16),
+        PhysicalKey::Code(KeyCode::A)
+    );
+    assert_eq!(
+        qwerty.physical(Some(KeyCode::F5), 63),
+        PhysicalKey::Scancode(63)
+    );
+}
+
+const CONFIG: &str = "
+# mine
+layout = qwerty
+5 = W, Up   # two keys
+0 = sc:57
+
+[pong.ch8]
+layout = azerty
+1 = Key1
+";
+
+#[test]
+fn test_config() {
+    let config = KeymapConfig::parse(CONFIG).unwrap();
+    let keymap = config.keymap("other.ch8");
+    assert_eq!(keymap.layout, Layout::Qwerty);
+    assert_eq!(keymap.key(Some(KeyCode::Up), 103), Some(0x5));
+    assert_eq!(keymap.key(Some(KeyCode::W), 17), Some(0x5));
+    assert_eq!(keymap.key(None, 57), Some(0x0));
+    assert_eq!(keymap.key(Some(KeyCode::X), 45), None);
+
+    // The rom's section goes over the top, on its own layout
+    let keymap = config.keymap("pong.ch8");
+    assert_eq!(keymap.layout, Layout::Scancode);
+    assert_eq!(keymap.keys_for(0x1), vec![PhysicalKey::Code(KeyCode::Key1)]);
+    assert_eq!(keymap.key(Some(KeyCode::Up), 103), Some(0x5));
+    assert_eq!(keymap.key(None, 30), Some(0x7));
+
+    assert_eq!(KeymapConfig::parse(&config.to_string()), Ok(config));
+
+    for (bad, error) in [
+        ("1 = Key1\nG = A", "line 2: not a CHIP-8 key (0 - F): G"),
+        ("layout = colemak", "line 1: unknown layout: colemak"),
+        ("5 = Up, Sideways", "line 1: unknown key: Sideways"),
+        ("5 = sc:x", "line 1: bad scancode: sc:x"),
+        ("5", "line 1: expected key = value: 5"),
+    ] {
+        let err = KeymapConfig::parse(bad).unwrap_err();
+        assert!(err.starts_with(error), "{}", err);
+    }
+}
+
+#[test]
+fn test_rebinding() {
+    let mut rebinder = Rebinder::new(Keymap::new(Layout::Qwerty));
+    assert_eq!(rebinder.current(), 0x1);
+
+    // The first key replaces, the next adds, and a key moves from wherever
+    // it was bound before
+    rebinder.press(PhysicalKey::Code(KeyCode::Numpad7));
+    rebinder.press(PhysicalKey::Code(KeyCode::Key2));
+    assert_eq!(
+        rebinder.keymap.keys_for(0x1),
+        vec![
+            PhysicalKey::Code(KeyCode::Key2),
+            PhysicalKey::Code(KeyCode::Numpad7)
+        ]
+    );
+    assert!(rebinder.keymap.keys_for(0x2).is_empty());
+
+    rebinder.advance(-1);
+    assert_eq!(rebinder.current(), 0xF);
+    rebinder.clear();
+    assert!(rebinder.keymap.keys_for(0xF).is_empty());
+    rebinder.advance(2);
+    assert_eq!(rebinder.current(), 0x2);
+    rebinder.press(PhysicalKey::Scancode(3));
+    assert_eq!(rebinder.keymap.key(None, 3), Some(0x2));
+
+    let section = rebinder.keymap.section();
+    assert_eq!(section.layout, Some(Layout::Qwerty));
+    assert_eq!(section.bindings.len(), 16);
+    assert_eq!(section.bindings[&0xF], vec![]);
+}
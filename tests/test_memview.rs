@@ -0,0 +1,88 @@
+extern crate lib;
+use lib::{format_row, Cpu, MemoryView, Region};
+
+#[test]
+fn test_regions() {
+    let mut cpu = Cpu::new();
+    cpu.load_rom_bytes(&[0x22, 0x04, 0x00, 0xFD, 0x00, 0xEE])
+        .unwrap();
+    cpu.run_frame(1).unwrap();
+    cpu.i = 0x300;
+    let view = MemoryView::new(0x200..0x206, 10);
+
+    assert_eq!(view.region(&cpu, 0x000), Region::Font);
+    assert_eq!(view.region(&cpu, 0x0EF), Region::Font);
+    assert_eq!(view.region(&cpu, 0x0F0), Region::Free);
+    assert_eq!(view.region(&cpu, 0x200), Region::Rom);
+    assert_eq!(view.region(&cpu, 0x202), Region::Stack);
+    assert_eq!(view.region(&cpu, 0x203), Region::Stack);
+    assert_eq!(view.region(&cpu, 0x205), Region::Rom);
+    assert_eq!(view.region(&cpu, 0x206), Region::Free);
+    assert_eq!(view.region(&cpu, 0x300), Region::I);
+}
+
+#[test]
+fn test_writes_and_edits() {
+    let mut memory = vec![0u8; 0x400];
+    let mut view = MemoryView::new(0x200..0x210, 4);
+    view.observe(&memory, 0);
+    assert_eq!(view.age(0x300), None);
+
+    // Writes light up, and only age while the cpu runs
+    memory[0x300] = 7;
+    view.observe(&memory, 10);
+    assert_eq!(view.age(0x300), Some(0));
+    view.observe(&memory, 10);
+    assert_eq!(view.age(0x300), Some(0));
+    view.observe(&memory, 11);
+    assert_eq!(view.age(0x300), Some(1));
+    for cycles in 12..100 {
+        view.observe(&memory, cycles);
+    }
+    assert_eq!(view.age(0x300), None);
+
+    // Two digits make a byte and move along
+    view.select(Some(0x3FE));
+    assert_eq!(view.type_digit(&mut memory, 0xA), None);
+    assert_eq!(view.nibble, Some(0xA));
+    assert_eq!(view.type_digit(&mut memory, 0x5), Some(0x3FE));
+    assert_eq!(memory[0x3FE], 0xA5);
+    assert_eq!(view.age(0x3FE), Some(0));
+    assert_eq!(view.selected, Some(0x3FF));
+    assert_eq!(view.top, 0x3C0);
+    view.select(None);
+    assert_eq!(view.type_digit(&mut memory, 1), None);
+}
+
+#[test]
+fn test_layout() {
+    let mut view = MemoryView::new(0x200..0x210, 4);
+    assert_eq!(view.top, 0x200);
+    view.scroll(-1, 0x1000);
+    assert_eq!(view.top, 0x1F0);
+    view.scroll(1000, 0x1000);
+    assert_eq!(view.top, 0xFC0);
+    view.show(0x123, 0x1000);
+    assert_eq!(view.top, 0x120);
+    view.show(0x175, 0x1000);
+    assert_eq!(view.top, 0x140);
+
+    // Row 2, the 4th byte, in the hex and the ASCII columns
+    assert_eq!(
+        view.address_at(44.0 + 3.0 * 20.0 + 5.0, 32.0, 0x1000),
+        Some(0x163)
+    );
+    assert_eq!(
+        view.address_at(374.0 + 3.0 * 8.0 + 1.0, 32.0, 0x1000),
+        Some(0x163)
+    );
+    assert_eq!(view.address_at(10.0, 32.0, 0x1000), None);
+    assert_eq!(view.address_at(50.0, 4.0 * 15.0, 0x1000), None);
+
+    let mut memory = vec![0u8; 0x20];
+    memory[0x10..0x13].copy_from_slice(b"Hi\n");
+    assert_eq!(
+        format_row(&memory, 0x10),
+        "0010: 48 69 0A 00 00 00 00 00 00 00 00 00 00 00 00 00 |Hi..............|"
+    );
+}
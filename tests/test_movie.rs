@@ -0,0 +1,96 @@
+extern crate lib;
+use lib::{Cpu, Movie, MovieError};
+
+// Draws a "0" somewhere random each time key 5 is seen held
+const ROM: [u8; 16] = [
+    0x65, 0x05, // LD V5, 5
+    0xE5, 0xA1, // SKNP V5
+    0x12, 0x08, // JP 0x208
+    0x12, 0x02, // JP 0x202
+    0xC0, 0x3F, // RND V0, 0x3F
+    0xC1, 0x1F, // RND V1, 0x1F
+    0xD0, 0x15, // DRW V0, V1, 5
+    0x12, 0x02, // JP 0x202
+];
+
+fn cpu() -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.load_rom_bytes(&ROM).unwrap();
+    cpu.seed_rng(7);
+    cpu
+}
+
+fn record() -> Movie {
+    let mut cpu = cpu();
+    let mut movie = Movie::new(&cpu, 7, 10);
+    for frame in 0..30 {
+        cpu.input.keys[5] = frame % 4 == 0;
+        movie.record_frame(&cpu);
+        cpu.run_frame(10).unwrap();
+    }
+    movie.finish(&cpu);
+    movie
+}
+
+#[test]
+fn test_key_mask() {
+    let mut cpu = Cpu::new();
+    cpu.input.keys[0] = true;
+    cpu.input.keys[0xF] = true;
+    assert_eq!(cpu.input.key_mask(), 0x8001);
+    cpu.input.set_key_mask(0x0020);
+    assert_eq!(cpu.input.key_mask(), 0x0020);
+    assert!(cpu.input.keys[5]);
+}
+
+#[test]
+fn test_replay() {
+    let movie = record();
+    assert_eq!(movie.frames.len(), 30);
+    assert_eq!(movie.frames[0], 0x0020);
+    assert_eq!(movie.frames[1], 0);
+
+    // A different seed here makes no difference, the movie has its own
+    let mut replay = cpu();
+    replay.seed_rng(99);
+    movie.play(&mut replay).unwrap();
+}
+
+#[test]
+fn test_replay_desync() {
+    let mut movie = record();
+    movie.frames[4] = 0;
+    match movie.play(&mut cpu()) {
+        Err(MovieError::Desync { .. }) => (),
+        result => panic!("expected a desync, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_replay_wrong_rom() {
+    let movie = record();
+    let mut other = Cpu::new();
+    other.load_rom_bytes(&[0x12, 0x00]).unwrap();
+    match movie.play(&mut other) {
+        Err(MovieError::State(_)) => (),
+        result => panic!("expected a bad state, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_movie_file_round_trip() {
+    let movie = record();
+    let bytes = movie.to_bytes();
+    let loaded = Movie::from_bytes(&bytes).unwrap();
+    assert_eq!(loaded.seed, 7);
+    assert_eq!(loaded.ipf, 10);
+    assert_eq!(loaded.frames, movie.frames);
+    assert_eq!(loaded.gfx_hash, movie.gfx_hash);
+    loaded.play(&mut cpu()).unwrap();
+
+    assert_eq!(
+        Movie::from_bytes(&bytes[..bytes.len() - 3]).err(),
+        Some(MovieError::Truncated)
+    );
+    assert_eq!(Movie::from_bytes(b"R8SS").err(), Some(MovieError::BadMagic));
+}
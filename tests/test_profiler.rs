@@ -0,0 +1,117 @@
+extern crate lib;
+use lib::{assemble, disassemble, Cpu, Profile, Rewind, Subroutine};
+
+const PROGRAM: &str = "
+: main
+  v0 := 0
+  loop
+    count
+    v0 += 1
+    if v0 != 3 then
+  again
+  exit
+: count
+  bump
+  return
+: bump
+  v1 += 1
+  return
+";
+
+fn profiled(cycles: usize) -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.load_rom_bytes(&assemble(PROGRAM).unwrap().rom).unwrap();
+    cpu.profile = Some(Profile::new());
+    cpu.run_frame(cycles).unwrap();
+    cpu
+}
+
+#[test]
+fn test_counts() {
+    let cpu = profiled(100);
+    let profile = cpu.profile.unwrap();
+    assert_eq!(profile.instructions, 25);
+    assert_eq!(profile.hits[&0x202], 3);
+    assert_eq!(profile.hits[&0x208], 2);
+    assert_eq!(profile.hits.get(&0x20A), Some(&1));
+    assert_eq!(profile.classes["Call"], 6);
+    assert_eq!(profile.classes["Jp"], 2);
+    assert_eq!(profile.classes["SeByte"], 3);
+    assert_eq!(profile.hot_loops(), vec![(0x202, 0x208, 2, 11)]);
+
+    let subroutines = profile.subroutines();
+    assert_eq!(
+        subroutines[&0x20C],
+        Subroutine {
+            calls: 3,
+            inclusive: 12,
+            exclusive: 6
+        }
+    );
+    assert_eq!(
+        subroutines[&0x210],
+        Subroutine {
+            calls: 3,
+            inclusive: 6,
+            exclusive: 6
+        }
+    );
+}
+
+#[test]
+fn test_unfinished_calls() {
+    // Stopped inside bump, inside count
+    let cpu = profiled(4);
+    let subroutines = cpu.profile.unwrap().subroutines();
+    assert_eq!(
+        subroutines[&0x20C],
+        Subroutine {
+            calls: 1,
+            inclusive: 2,
+            exclusive: 1
+        }
+    );
+    assert_eq!(
+        subroutines[&0x210],
+        Subroutine {
+            calls: 1,
+            inclusive: 1,
+            exclusive: 1
+        }
+    );
+}
+
+#[test]
+fn test_report() {
+    let cpu = profiled(100);
+    let rom = assemble(PROGRAM).unwrap().rom;
+    let profile = cpu.profile.as_ref().unwrap();
+    let report = profile.report(2, &disassemble(&rom, 0x200), &cpu.memory, 3);
+    assert!(report.starts_with("25 instructions in 2 frames, 12.5 a frame\n"));
+    assert!(report.contains("  0x202           3  12.0%  label_202: CALL sub_20C\n"));
+    assert!(report.contains("  0x202-0x208          11  44.0%  2 times round, at label_202\n"));
+    assert!(
+        report.contains("  sub_20C                      12  48.0%           6  24.0%        3\n")
+    );
+    assert_eq!(report.matches("  Call ").count(), 1);
+}
+
+#[test]
+fn test_step_back_unprofiled() {
+    let mut cpu = Cpu::new();
+    cpu.load_rom_bytes(&assemble(PROGRAM).unwrap().rom).unwrap();
+    cpu.profile = Some(Profile::new());
+    let mut rewind = Rewind::new(10, 1);
+    rewind.run_frame(&mut cpu, 6).unwrap();
+    rewind.tick(&mut cpu).unwrap();
+    let before = cpu.profile.clone().unwrap();
+    assert_eq!(before.instructions, 7);
+
+    // The replay up to the step before isn't counted again
+    assert!(rewind.step_back(&mut cpu));
+    assert_eq!(cpu.cycles, 6);
+    let after = cpu.profile.as_ref().unwrap();
+    assert_eq!(after.instructions, 7);
+    assert_eq!(after.hits, before.hits);
+    assert_eq!(after.subroutines(), before.subroutines());
+}
@@ -0,0 +1,148 @@
+extern crate lib;
+use lib::{assemble, Quirks, Repl};
+use std::io::Cursor;
+
+const PROGRAM: &str = "
+: main
+  v0 := 5
+  i := buffer
+  save v0
+  count
+  sprite v1 v2 1
+  exit
+: count
+  v1 += 1
+  bump
+  return
+: bump
+  v2 += 1
+  return
+: buffer
+  0x80
+";
+
+fn repl() -> Repl {
+    let rom = assemble(PROGRAM).unwrap().rom;
+    Repl::new(&rom, Quirks::default(), 4096, 0, 11).unwrap()
+}
+
+#[test]
+fn test_stepping() {
+    let mut repl = repl();
+    assert_eq!(
+        repl.execute("step").unwrap(),
+        "=> 0x202  LD I, sprite_216\n"
+    );
+    assert_eq!(repl.execute("s 2").unwrap(), "=> 0x206  CALL sub_20C\n");
+    assert_eq!(
+        repl.execute("next").unwrap(),
+        "stepped over to 0x208\n=> 0x208  DRW V1, V2, 1\n"
+    );
+    assert_eq!((repl.cpu.v[1], repl.cpu.v[2]), (1, 1));
+
+    // An empty line does it again
+    assert_eq!(repl.execute("").unwrap(), "=> 0x20A  EXIT\n");
+    assert_eq!(
+        repl.execute("continue").unwrap(),
+        "the rom exited\n=> 0x20A  EXIT\n"
+    );
+    assert!(repl.execute("step").is_err());
+    assert_eq!(repl.execute("reset").unwrap(), "=> 0x200  LD V0, 0x05\n");
+}
+
+#[test]
+fn test_breakpoints() {
+    let mut repl = repl();
+    assert_eq!(
+        repl.execute("break 212").unwrap(),
+        "breakpoint at 0x212 (sub_212)\n"
+    );
+    assert_eq!(repl.execute("watch w:216").unwrap(), "watching w:216\n");
+    assert_eq!(
+        repl.execute("b").unwrap(),
+        "break 0x212 (sub_212)\nwatch w:216\n"
+    );
+    assert_eq!(
+        repl.execute("c").unwrap(),
+        "write of 0x216 by 0x204: 0x80 -> 0x05\n=> 0x206  CALL sub_20C\n"
+    );
+    assert_eq!(
+        repl.execute("c").unwrap(),
+        "breakpoint at 0x212\n=> 0x212  sub_212: ADD V2, 0x01\n"
+    );
+    assert_eq!(
+        repl.execute("finish").unwrap(),
+        "stepped out to 0x210\n=> 0x210  RET\n"
+    );
+    assert_eq!(
+        repl.execute("until 20a").unwrap(),
+        "ran to 0x20A\n=> 0x20A  EXIT\n"
+    );
+
+    assert!(repl.execute("delete 300").is_err());
+    assert!(repl.execute("delete w:216").is_ok());
+    assert!(repl.execute("delete 0x212").is_ok());
+    assert_eq!(
+        repl.execute("break").unwrap(),
+        "no breakpoints or watchpoints\n"
+    );
+}
+
+#[test]
+fn test_inspecting() {
+    let mut repl = repl();
+    repl.execute("s 3").unwrap();
+    assert_eq!(repl.execute("x/4 I").unwrap(), "0x216: 05 00 00 00\n");
+    assert_eq!(
+        repl.execute("x/10 200").unwrap(),
+        "0x200: 60 05 A2 16 F0 55 22 0C\n0x208: D1 21\n"
+    );
+    assert_eq!(repl.execute("set v3=0x10").unwrap(), "v3 = 0x10\n");
+    assert_eq!(repl.execute("set i = 300").unwrap(), "i = 0x12C\n");
+    assert_eq!(repl.cpu.v[3], 0x10);
+    assert_eq!(repl.cpu.i, 300);
+    assert!(repl.execute("set v3=256").is_err());
+    assert!(repl.execute("set vg=1").is_err());
+
+    let regs = repl.execute("regs").unwrap();
+    assert!(regs.starts_with("PC:0x0206 I:0x012C SP:0 DT:0 ST:0\nV0:05 V1:00 V2:00 V3:10 "));
+    assert_eq!(
+        repl.execute("disasm 20c 3").unwrap(),
+        "   0x20C  sub_20C: ADD V1, 0x01\n   0x20E  CALL sub_212\n   0x210  RET\n"
+    );
+    assert_eq!(
+        repl.execute("disasm pc 1").unwrap(),
+        "=> 0x206  CALL sub_20C\n"
+    );
+
+    repl.execute("set i=0x216").unwrap();
+    repl.execute("next").unwrap();
+    repl.execute("step").unwrap();
+    let display = repl.execute("display").unwrap();
+    assert_eq!(display.lines().count(), 32);
+    // The 0x05 saved over the sprite, drawn at (1, 1)
+    assert_eq!(&display.lines().nth(1).unwrap()[..10], "......#.#.");
+
+    assert!(repl.execute("frobnicate").is_err());
+    assert!(repl.execute("keys 5G").is_err());
+    assert_eq!(repl.execute("keys 5a").unwrap(), "holding 0x0420\n");
+    assert_eq!(repl.cpu.input.key_mask(), 0x420);
+}
+
+#[test]
+fn test_session() {
+    let mut repl = repl();
+    let input = Cursor::new("break 20c\ncontinue\nbogus\nquit\nstep\n");
+    let mut output = Vec::new();
+    repl.run(input, &mut output, false).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "=> 0x200  LD V0, 0x05\n\
+         breakpoint at 0x20C (sub_20C)\n\
+         breakpoint at 0x20C\n\
+         => 0x20C  sub_20C: ADD V1, 0x01\n\
+         error: don't know 'bogus', try help\n"
+    );
+    assert!(repl.quit);
+    assert_eq!(repl.cpu.pc, 0x20C);
+}
@@ -0,0 +1,171 @@
+extern crate lib;
+use lib::{Cpu, RandomSource, Rewind};
+
+// Counts, reads the delay timer and rolls random numbers in a loop
+fn busy_cpu() -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.load_rom_bytes(&[
+        0x6A, 0x3C, // LD VA, 60
+        0xFA, 0x15, // LD DT, VA
+        0xC1, 0xFF, // RND V1, 0xFF
+        0xF2, 0x07, // LD V2, DT
+        0x74, 0x01, // ADD V4, 1
+        0x12, 0x04, // JP 0x204
+    ])
+    .unwrap();
+    cpu.seed_rng(42);
+    cpu
+}
+
+// V registers after running the first frame plus a few single ticks
+fn busy_cpu_after(cycles: usize) -> [u8; 16] {
+    let mut cpu = busy_cpu();
+    cpu.run_frame(5).unwrap();
+    cpu.signal_vblank();
+    for _ in 5..cycles {
+        cpu.tick(false).unwrap();
+    }
+    cpu.v
+}
+
+#[test]
+fn test_step_back() {
+    let mut cpu = busy_cpu();
+    let mut rewind = Rewind::new(10, 2);
+    for _ in 0..3 {
+        rewind.run_frame(&mut cpu, 5).unwrap();
+    }
+    assert_eq!(cpu.cycles, 15);
+
+    // The same run, stopped one instruction short
+    let mut reference = busy_cpu();
+    reference.run_frame(5).unwrap();
+    reference.run_frame(5).unwrap();
+    reference.signal_vblank();
+    for _ in 0..4 {
+        reference.tick(false).unwrap();
+    }
+
+    assert!(rewind.step_back(&mut cpu));
+    assert_eq!(cpu.cycles, 14);
+    assert_eq!(cpu.save_state(), reference.save_state());
+
+    // And back over a frame boundary, past a snapshot
+    for _ in 0..5 {
+        assert!(rewind.step_back(&mut cpu));
+    }
+    assert_eq!(cpu.cycles, 9);
+    assert_eq!(cpu.delay_timer, 59);
+
+    // Stepping forward again follows the same path
+    rewind.tick(&mut cpu).unwrap();
+    assert_eq!(cpu.v, busy_cpu_after(10));
+}
+
+#[test]
+fn test_step_back_at_start() {
+    let mut cpu = busy_cpu();
+    let mut rewind = Rewind::new(10, 2);
+    assert!(!rewind.step_back(&mut cpu));
+    rewind.tick(&mut cpu).unwrap();
+    assert!(rewind.step_back(&mut cpu));
+    assert_eq!(cpu.pc, 0x200);
+    assert!(!rewind.step_back(&mut cpu));
+}
+
+#[test]
+fn test_step_back_keeps_edits() {
+    let mut cpu = busy_cpu();
+    let mut rewind = Rewind::new(10, 2);
+    rewind.run_frame(&mut cpu, 5).unwrap();
+
+    // Typed over between frames, then run on past it
+    cpu.memory[0x300] = 0xAB;
+    rewind.edited(&cpu);
+    rewind.run_frame(&mut cpu, 5).unwrap();
+    assert!(rewind.step_back(&mut cpu));
+    assert_eq!(cpu.cycles, 9);
+    assert_eq!(cpu.memory[0x300], 0xAB);
+
+    // Going back past the edit undoes it
+    for _ in 0..5 {
+        assert!(rewind.step_back(&mut cpu));
+    }
+    assert_eq!(cpu.memory[0x300], 0);
+}
+
+#[test]
+fn test_rewind() {
+    let mut cpu = busy_cpu();
+    let mut rewind = Rewind::new(10, 2);
+    let mut states = Vec::new();
+    for _ in 0..6 {
+        states.push(cpu.save_state());
+        rewind.run_frame(&mut cpu, 5).unwrap();
+    }
+    assert_eq!(rewind.len(), 3);
+
+    // Snapshots were taken before frames 5, 3 and 1
+    assert!(rewind.rewind(&mut cpu));
+    assert_eq!(cpu.save_state(), states[4]);
+    assert!(rewind.rewind(&mut cpu));
+    assert_eq!(cpu.save_state(), states[2]);
+    assert!(rewind.rewind(&mut cpu));
+    assert_eq!(cpu.save_state(), states[0]);
+
+    // The oldest is kept, and there's no going further back
+    assert!(!rewind.rewind(&mut cpu));
+    assert_eq!(cpu.save_state(), states[0]);
+    assert_eq!(rewind.len(), 1);
+
+    // Until it has run on again
+    rewind.run_frame(&mut cpu, 5).unwrap();
+    assert!(rewind.rewind(&mut cpu));
+    assert_eq!(cpu.save_state(), states[0]);
+}
+
+// Gives 0, 1, 2... with no state to save, so replays see different numbers
+struct Counter(u8);
+
+impl RandomSource for Counter {
+    fn next_byte(&mut self) -> u8 {
+        self.0 = self.0.wrapping_add(1);
+        self.0 - 1
+    }
+}
+
+#[test]
+fn test_step_back_diverging_replay() {
+    let mut cpu = Cpu::new();
+    cpu.load_rom_bytes(&[
+        0xC0, 0x01, // RND V0, 1
+        0x30, 0x00, // SE V0, 0
+        0x00, 0xEE, // RET, which underflows
+        0x12, 0x06, // JP 0x206
+    ])
+    .unwrap();
+    cpu.set_rng(Box::new(Counter(0)));
+    let mut rewind = Rewind::new(10, 2);
+    for _ in 0..4 {
+        rewind.tick(&mut cpu).unwrap();
+    }
+    assert_eq!((cpu.pc, cpu.cycles), (0x206, 4));
+
+    // The replay rolls a 1 this time and faults, so nothing changes
+    let state = cpu.save_state();
+    assert!(!rewind.step_back(&mut cpu));
+    assert_eq!(cpu.save_state(), state);
+}
+
+#[test]
+fn test_rewind_capacity() {
+    let mut cpu = busy_cpu();
+    let mut rewind = Rewind::new(2, 1);
+    for _ in 0..5 {
+        rewind.run_frame(&mut cpu, 5).unwrap();
+    }
+    assert_eq!(rewind.len(), 2);
+    rewind.clear();
+    assert!(rewind.is_empty());
+    assert!(!rewind.rewind(&mut cpu));
+}
@@ -0,0 +1,67 @@
+extern crate lib;
+use lib::{Beeper, Cpu};
+
+#[test]
+fn test_square_wave() {
+    // 4 samples per period: two high, two low
+    let mut beeper = Beeper::new(1000.0, 0.5, 4000);
+    let mut buffer = [0.0; 8];
+    beeper.fill(&mut buffer);
+    assert_eq!(buffer, [0.5, 0.5, -0.5, -0.5, 0.5, 0.5, -0.5, -0.5]);
+}
+
+#[test]
+fn test_volume_clamped() {
+    let mut beeper = Beeper::new(1000.0, 3.0, 4000);
+    assert_eq!(beeper.next_sample(), 1.0);
+}
+
+#[test]
+fn test_period_samples() {
+    let beeper = Beeper::new(441.0, 0.5, 44_100);
+    assert_eq!(beeper.period_samples(1), 100);
+    assert_eq!(beeper.period_samples(3), 300);
+}
+
+#[test]
+fn test_to_wav() {
+    let mut beeper = Beeper::new(1000.0, 1.0, 4000);
+    let wav = beeper.to_wav(4);
+    assert_eq!(&wav[0..4], b"RIFF");
+    assert_eq!(&wav[8..12], b"WAVE");
+    assert_eq!(wav.len(), 44 + 4 * 2);
+    assert_eq!(i16::from_le_bytes([wav[44], wav[45]]), i16::MAX);
+    assert_eq!(i16::from_le_bytes([wav[48], wav[49]]), -i16::MAX);
+}
+
+#[test]
+fn test_sound_timer_drives_buzzer() {
+    let mut cpu = Cpu::new();
+    cpu.memory[0x200] = 0x12; // Loop forever at 0x200
+    cpu.memory[0x201] = 0x00;
+    assert!(!cpu.sound_active());
+
+    cpu.v[0] = 2;
+    cpu.run_opcode(0xF018, Some(false)).unwrap(); // St = V0
+    cpu.pc = 0x200;
+    assert!(cpu.sound_active());
+
+    cpu.run_frame(10).unwrap();
+    assert!(cpu.sound_active());
+    cpu.run_frame(10).unwrap();
+    assert!(!cpu.sound_active());
+}
+
+#[test]
+fn test_pattern() {
+    let mut beeper = Beeper::new(440.0, 0.5, 4000);
+    let mut pattern = [0; 16];
+    pattern[0] = 0b1010_0000;
+    beeper.set_pattern(pattern, 64);
+    // 4000 bits a second at pitch 64, so one bit per sample
+    assert_eq!(beeper.frequency, 4000.0 / 128.0);
+    let mut buffer = [0.0; 4];
+    beeper.fill(&mut buffer);
+    assert_eq!(buffer, [0.5, -0.5, 0.5, -0.5]);
+    assert_eq!(Beeper::playback_rate(112), 8000.0);
+}
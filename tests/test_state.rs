@@ -0,0 +1,98 @@
+extern crate lib;
+use lib::{Cpu, Quirks, StateError, STATE_VERSION};
+
+fn running_cpu() -> Cpu {
+    let mut cpu = Cpu::with_quirks(Quirks::cosmac_vip());
+    cpu.load_rom_bytes(&[0x60, 0x05, 0xC1, 0xFF, 0x22, 0x00])
+        .unwrap();
+    cpu.seed_rng(1234);
+    cpu.run_frame(3).unwrap();
+    cpu.gfx[4][7] = 1;
+    cpu.delay_timer = 30;
+    cpu.input.keys[0xA] = true;
+    cpu.rpl[2] = 9;
+    cpu
+}
+
+#[test]
+fn test_save_load_round_trip() {
+    let mut cpu = running_cpu();
+    let state = cpu.save_state();
+
+    let mut restored = Cpu::new();
+    restored
+        .load_rom_bytes(&[0x60, 0x05, 0xC1, 0xFF, 0x22, 0x00])
+        .unwrap();
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.save_state(), state);
+    assert_eq!(restored.quirks, Quirks::cosmac_vip());
+    assert_eq!(restored.pc, cpu.pc);
+    assert_eq!(restored.v, cpu.v);
+    assert_eq!(restored.sp, 1);
+    assert_eq!(restored.gfx[4][7], 1);
+    assert!(restored.input.keys[0xA]);
+
+    // Both carry on identically, random numbers included
+    cpu.pc = 0x202;
+    restored.pc = 0x202;
+    cpu.tick(false).unwrap();
+    restored.tick(false).unwrap();
+    assert_eq!(restored.v[1], cpu.v[1]);
+}
+
+#[test]
+fn test_load_store_quirk_round_trip() {
+    for quirks in &[Quirks::chip48(), Quirks::superchip(), Quirks::xochip()] {
+        let state = Cpu::with_quirks(*quirks).save_state();
+        let mut restored = Cpu::new();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.quirks, *quirks);
+    }
+}
+
+#[test]
+fn test_load_state_bad_magic() {
+    let mut cpu = running_cpu();
+    assert_eq!(cpu.load_state(b"NOPE"), Err(StateError::BadMagic));
+    assert_eq!(cpu.load_state(&[]), Err(StateError::BadMagic));
+}
+
+#[test]
+fn test_load_state_version() {
+    let mut cpu = running_cpu();
+    let mut state = cpu.save_state();
+    state[4..6].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+    assert_eq!(
+        cpu.load_state(&state),
+        Err(StateError::UnsupportedVersion {
+            version: STATE_VERSION + 1
+        })
+    );
+}
+
+#[test]
+fn test_load_state_rom_mismatch() {
+    let state = running_cpu().save_state();
+    let mut other = Cpu::new();
+    other.load_rom_bytes(&[0x12, 0x00]).unwrap();
+    match other.load_state(&state) {
+        Err(StateError::RomMismatch { .. }) => (),
+        result => panic!("expected a ROM mismatch, got {:?}", result),
+    }
+}
+
+#[test]
+fn test_load_state_truncated() {
+    let mut cpu = running_cpu();
+    let state = cpu.save_state();
+    let pc = cpu.pc;
+    cpu.pc = 0x300;
+    assert_eq!(
+        cpu.load_state(&state[..state.len() - 1]),
+        Err(StateError::Truncated)
+    );
+    // Nothing was touched
+    assert_eq!(cpu.pc, 0x300);
+    cpu.load_state(&state).unwrap();
+    assert_eq!(cpu.pc, pc);
+}
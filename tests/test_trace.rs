@@ -0,0 +1,120 @@
+extern crate lib;
+use lib::{assemble, diff_traces, trace_fields, Cpu, Rewind, TRACE_HEADER};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// A writer the test can still read once the cpu has it
+#[derive(Clone, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn trace(source: &str, cycles: usize) -> String {
+    let mut cpu = Cpu::new();
+    cpu.load_rom_bytes(&assemble(source).unwrap().rom).unwrap();
+    let out = Shared::default();
+    cpu.trace = Some(Box::new(out.clone()));
+    cpu.run_frame(cycles).unwrap();
+    let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+    format!("{}{}", TRACE_HEADER, text)
+}
+
+const PROGRAM: &str = "
+  v0 := 0xFF
+  v1 := 2
+  v0 += v1
+  i := 0x300
+  delay := v1
+";
+
+#[test]
+fn test_trace_lines() {
+    let trace = trace(PROGRAM, 5);
+    let lines: Vec<&str> = trace.lines().collect();
+    assert_eq!(lines.len(), 6);
+    assert_eq!(
+        lines[3],
+        "cycle=2 pc=0204 op=8014 \
+         v=FF020000000000000000000000000000 i=0000 sp=0 dt=00 st=00 \
+         v'=01020000000000000000000000000001 i'=0000 sp'=0 dt'=00 st'=00 ; ADD V0, V1"
+    );
+    assert!(lines[5].starts_with("cycle=4 pc=0208 op=F115 "));
+    assert!(lines[5].contains(" dt=00 st=00 v'="));
+    assert!(lines[5].ends_with(" dt'=02 st'=00 ; LD DT, V1"));
+
+    let fields = trace_fields(lines[4]).unwrap();
+    assert_eq!(fields["i'"], "0300");
+    assert_eq!(fields["op"], "A300");
+    assert_eq!(trace_fields(lines[0]), None);
+    assert_eq!(trace_fields("  "), None);
+}
+
+#[test]
+fn test_diff() {
+    let ours = trace(PROGRAM, 5);
+    assert_eq!(diff_traces(&ours, &ours, &[]), Ok(5));
+
+    // A reference that only has some of the fields, written its own way,
+    // and stops early
+    let reference = "\
+        # another emulator\n\
+        pc=0x200 v'=ff000000000000000000000000000000\n\
+        pc=0x202 v'=ff020000000000000000000000000000 ; v1 := 2\n\
+        pc=0x204 v'=01020000000000000000000000000001\n";
+    assert_eq!(diff_traces(&ours, reference, &[]), Ok(3));
+
+    // One that doesn't set the carry
+    let reference = reference.replace("0000000001\n", "0000000000\n");
+    let divergence = diff_traces(&ours, &reference, &[]).unwrap_err();
+    assert_eq!(divergence.index, 2);
+    assert_eq!(
+        divergence.fields,
+        vec![("VF after".to_string(), "01".to_string(), "00".to_string())]
+    );
+    assert!(divergence.previous.unwrap().starts_with("cycle=1 pc=0202"));
+    assert!(divergence.reference.starts_with("pc=0x204"));
+    assert!(divergence.ours.starts_with("cycle=2 pc=0204"));
+    assert_eq!(diff_traces(&ours, &reference, &["v".to_string()]), Ok(3));
+
+    // Timers tick at different times in different emulators
+    let reference = ours.replace("dt'=02", "dt'=01");
+    let divergence = diff_traces(&ours, &reference, &[]).unwrap_err();
+    assert_eq!(divergence.index, 4);
+    assert_eq!(
+        divergence.fields,
+        vec![("dt'".to_string(), "02".to_string(), "01".to_string())]
+    );
+    assert_eq!(diff_traces(&ours, &reference, &["dt".to_string()]), Ok(5));
+}
+
+#[test]
+fn test_step_back_untraced() {
+    let mut cpu = Cpu::new();
+    cpu.load_rom_bytes(&assemble(PROGRAM).unwrap().rom).unwrap();
+    let out = Shared::default();
+    cpu.trace = Some(Box::new(out.clone()));
+    let mut rewind = Rewind::new(10, 1);
+    rewind.run_frame(&mut cpu, 3).unwrap();
+    rewind.tick(&mut cpu).unwrap();
+    let traced = out.0.borrow().clone();
+    assert_eq!(
+        String::from_utf8(traced.clone()).unwrap().lines().count(),
+        4
+    );
+
+    // Replaying up to the step before doesn't write it all out again
+    assert!(rewind.step_back(&mut cpu));
+    assert_eq!(cpu.cycles, 3);
+    assert_eq!(*out.0.borrow(), traced);
+    assert!(cpu.trace.is_some());
+}